@@ -4,7 +4,8 @@ pub mod model;
 use crate::{DEBUG, Launch, CONFIG_PATH};
 use crate::cli::CLI;
 use crate::config::Config;
-use crate::app::model::{library::Flag, player::Mode};
+use crate::app::model::{Listing, library::Flag, player::Mode};
+use crate::app::model::library::reader::MetadataReader;
 use crate::utils::{panic_hook, setup_logger};
 use crate::app::canvas::View;
 use crate::error::{Result, anyhow};
@@ -144,6 +145,11 @@ impl Launch for App {
             .execute(LeaveAlternateScreen)?
             .show_cursor()?;
 
+        // Saved last, so that a failure doesn't leave the terminal in raw mode.
+        if let Err(e) = self.model.checkpoint() {
+            if DEBUG.load(Relaxed) { trace!("Unable to save the session: {}", e); }
+        }
+
         Ok(())
     }
 
@@ -151,6 +157,13 @@ impl Launch for App {
 
 impl App {
 
+    /// Let crates embedding `ultra` read the metadata of more formats, or
+    /// read the built-in ones differently. Call it before `bootstrap`.
+    #[inline]
+    pub fn register_reader(&mut self, reader: impl MetadataReader + 'static) {
+        self.model.library.register(reader);
+    }
+
     #[inline]
     fn draw(&mut self, f: &mut Frame<CrosstermBackend<Stdout>>) {
         self.canvas.draw(f, &mut self.model);
@@ -159,6 +172,9 @@ impl App {
     #[inline]
     fn handle_event(&mut self, event_rx: Rc<Receiver<Event>>) -> Result<()> {
         self.sync_boundary();
+        self.model.tick()?;
+        self.model.library.poll_analysis()?;
+        self.model.autosave();
         if let Ok(event) = event_rx.try_recv() {
             // if DEBUG.load(Relaxed) { trace!("RECEIVE EVENT: {:#?}", event) }
             match event {
@@ -166,7 +182,7 @@ impl App {
                     // TODO
                 }
                 Event::LibraryChanged => {
-                    self.model.library.commit(false)?;
+                    self.model.library.commit()?;
                     self.model.sync_headers()?;
                 }
                 Event::UserInput(term_event) => {
@@ -188,13 +204,15 @@ impl App {
             self.terminated = true;
         }
         match event.code {
+            KeyCode::Left if event.modifiers == KeyModifiers::SHIFT => self.on_shift_left()?,
+            KeyCode::Right if event.modifiers == KeyModifiers::SHIFT => self.on_shift_right()?,
             KeyCode::Char(c) => self.on_char(c)?,
             KeyCode::Esc => self.on_esc(),
             KeyCode::Backspace => self.on_backspace(),
             KeyCode::Up => self.on_up(),
             KeyCode::Down => self.on_down(),
-            KeyCode::Left => self.on_left(),
-            KeyCode::Right => self.on_right(),
+            KeyCode::Left => self.on_left()?,
+            KeyCode::Right => self.on_right()?,
             KeyCode::Enter => self.on_enter()?,
             KeyCode::Tab => self.on_tab(),
             KeyCode::F(n) => self.on_f(n),
@@ -209,8 +227,10 @@ impl App {
             MouseEvent::ScrollDown(_, _, _) => self.on_scroll_down(),
             MouseEvent::ScrollUp(_, _, _) => self.on_scroll_up(),
             MouseEvent::Down(button, x, y, _) => {
-                if matches!(button, MouseButton::Left) {
-                    self.on_click(x, y);
+                match button {
+                    MouseButton::Left => self.on_click(x, y)?,
+                    MouseButton::Right => self.on_right_click(x, y)?,
+                    _ => {}
                 }
             }
             _ => {}
@@ -231,6 +251,33 @@ impl App {
                     't' => self.model.flag(Flag::Title),
                     'a' => self.model.flag(Flag::Artist),
                     'd' => self.model.flag(Flag::Duration),
+                    's' => self.model.toggle_listing(),
+                     _  => {}
+                }
+            }
+            1 => {
+                if c == 'p' {
+                    self.model.player.equalizer.next_preset();
+                }
+            }
+            2 => {
+                match c {
+                    ']' => self.model.player.faster(),
+                    '[' => self.model.player.slower(),
+                    '=' => self.model.player.reset_speed(),
+                    'p' => self.model.player.toggle_speed_mode()?,
+                    't' => self.model.player.cycle_timer(),
+                    'r' => self.model.player.restart()?,
+                    'o' => self.model.player.cycle_device()?,
+                    'm' => self.model.player.toggle_mute(),
+                    'M' => self.model.player.toggle_mono(),
+                    ',' => self.model.player.balance_left(),
+                    '.' => self.model.player.balance_right(),
+                    'b' => self.model.player.center_balance(),
+                    'l' => {
+                        let pos = self.model.player.position();
+                        self.model.player.mark(pos)?;
+                    }
                      _  => {}
                 }
             }
@@ -252,6 +299,7 @@ impl App {
         match self.model.focus {
             0 => self.model.unselect_board(),
             1 => self.model.unselect_spectrum(),
+            2 => self.model.player.set_timer(None),
             3 => self.model.close_search(),
             _ => {}
         }
@@ -275,7 +323,7 @@ impl App {
     fn on_up(&mut self) {
         match self.model.focus {
             0 | 3 => self.model.select_previous_song(),
-            1 => {}
+            1 => self.model.adjust_band(1.0),
             2 => self.model.player.increase_volume(),
             _ => {}
         }
@@ -285,7 +333,7 @@ impl App {
     fn on_down(&mut self) {
         match self.model.focus {
             0 | 3 => self.model.select_next_song(),
-            1 => {}
+            1 => self.model.adjust_band(-1.0),
             2 => self.model.player.decrease_volume(),
 
             _ => {}
@@ -293,27 +341,51 @@ impl App {
     }
 
     #[inline]
-    fn on_left(&mut self) {
+    fn on_left(&mut self) -> Result<()> {
+        match self.model.focus {
+            1 => self.model.select_previous_band(),
+            2 => self.model.player.previous()?,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn on_right(&mut self) -> Result<()> {
+        match self.model.focus {
+            1 => self.model.select_next_band(),
+            2 => self.model.player.next()?,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn on_shift_left(&mut self) -> Result<()> {
         match self.model.focus {
-            // 2 => self.model.
+            2 => self.model.player.rewind()?,
             _ => {}
         }
+        Ok(())
     }
 
     #[inline]
-    fn on_right(&mut self) {
+    fn on_shift_right(&mut self) -> Result<()> {
         match self.model.focus {
-            // 2 => self.model.player.play(&self.model.next_song().unwrap()),
+            2 => self.model.player.forward()?,
             _ => {}
         }
+        Ok(())
     }
 
     #[inline]
     fn on_enter(&mut self) -> Result<()> {
         match self.model.focus {
             0 | 3 => {
-                if let Some(target) = self.model.offset {
-                    self.model.player.handle(&self.model.library.record.cache[target])?;
+                match (self.model.offset, self.model.listing) {
+                    (Some(target), Listing::Songs) => self.model.play(target)?,
+                    (Some(target), Listing::Stations) => self.model.tune(target)?,
+                    (None, _) => {}
                 }
             }
             1 => {}
@@ -335,9 +407,10 @@ impl App {
     #[inline]
     fn on_f(&mut self, n: u8) {
         match n {
-            1 => self.model.player.mode = Mode::Sequential,
-            2 => self.model.player.mode = Mode::Random,
-            3 => self.model.player.mode = Mode::SingleCycle,
+            1 => self.model.player.set_mode(Mode::RepeatAll),
+            2 => self.model.player.set_mode(Mode::Random),
+            3 => self.model.player.set_mode(Mode::SingleCycle),
+            4 => self.model.player.set_mode(Mode::RepeatOff),
             _ => {}
         }
     }
@@ -359,7 +432,7 @@ impl App {
     }
 
     #[inline]
-    fn on_click(&mut self, x: u16, y: u16) {
+    fn on_click(&mut self, x: u16, y: u16) -> Result<()> {
         if self.model.focus != 3 {
             click!(x, y, self.canvas.board, self.model);
             click!(x, y, self.canvas.spectrum, self.model);
@@ -371,6 +444,35 @@ impl App {
                     self.model.select_board(y as usize - 2);
                 }
             }
+            if self.model.focus == 2 {
+                if let Some(pos) = self.gauge_position(x, y) {
+                    self.model.player.seek(pos)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Right clicks on the gauge set the points of the A-B loop.
+    #[inline]
+    fn on_right_click(&mut self, x: u16, y: u16) -> Result<()> {
+        if self.model.focus == 2 {
+            if let Some(pos) = self.gauge_position(x, y) {
+                self.model.player.mark(pos)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Position in the current song under the cell at `x`, `y` of the gauge.
+    #[inline]
+    fn gauge_position(&self, x: u16, y: u16) -> Option<Duration> {
+        let gauge = self.canvas.timeline.gauge;
+        if y == gauge.y && x >= gauge.left() && x < gauge.right() {
+            let ratio = (x - gauge.left()) as f64 / gauge.width as f64;
+            self.model.player.duration().map(|d| d.mul_f64(ratio))
+        } else {
+            None
         }
     }
 
@@ -378,7 +480,7 @@ impl App {
     fn sync_boundary(&mut self) {
         if self.model.focus == 0 {
             let song_height = self.canvas.board.area.height as usize - 2;
-            let song_nums = self.model.library.record.cache.len();
+            let song_nums = self.model.rows();
 
             if let Some(offset) = self.model.offset {
                 if offset <= song_height && self.model.topline == 0 {
@@ -3,6 +3,7 @@ use unicode_width::UnicodeWidthStr;
 use tui::layout::{Layout, Constraint, Direction};
 use tui::widgets::{Paragraph, Table, Cell, Row};
 use tui::style::Modifier;
+use crate::app::model::Listing;
 
 #[derive(Debug)]
 pub struct Search {
@@ -66,17 +67,63 @@ impl View for Board {
         let unselected_style = Style::default().fg(colorscheme["board_unselected"]);
         let header_style = Style::default().fg(colorscheme["board_header"]).add_modifier(Modifier::BOLD | Modifier::UNDERLINED);
 
-        let rows = model.songs.iter().map(|item| {
-            let cells = item.iter().map(|c| Cell::from(c.clone()));
-            Row::new(cells).height(1)
+        let bookmarks = &model.player.bookmarks;
+        let songs = model.songs.iter().zip(model.listed.iter()).map(|(item, song)| {
+            let mut item = item.clone();
+            // Show how far into the song its bookmark is.
+            if let (Some(progress), Some(duration)) = (bookmarks.progress(song), item.last_mut()) {
+                *duration = format!("{} {:.0}%", duration, progress * 100.0);
+            }
+            let cells = item.into_iter().map(Cell::from);
+            let row = Row::new(cells).height(1);
+            if song.broken {
+                row.style(Style::default().add_modifier(Modifier::CROSSED_OUT | Modifier::DIM))
+            } else {
+                row
+            }
         });
 
+        // The station playing is shown in bold.
+        let playing = model.player.station();
+        let stations = model.stations.iter().enumerate().map(|(i, station)| {
+            let cells = vec![(i + 1).to_string(), station.name.clone(), station.url.clone()];
+            let row = Row::new(cells.into_iter().map(Cell::from)).height(1);
+            if playing == Some(station) {
+                row.style(Style::default().add_modifier(Modifier::BOLD))
+            } else {
+                row
+            }
+        });
+
+        let (rows, headers, widths): (Vec<Row>, &[&str], &[Constraint]) = match model.listing {
+            Listing::Songs => (
+                songs.collect(),
+                &["No.", "Title", "Artist", "Album", "Duration"],
+                &[
+                    Constraint::Percentage(5),
+                    Constraint::Percentage(30),
+                    Constraint::Percentage(30),
+                    Constraint::Percentage(25),
+                    Constraint::Percentage(10),
+                ],
+            ),
+            Listing::Stations => (
+                stations.collect(),
+                &["No.", "Station", "URL"],
+                &[
+                    Constraint::Percentage(5),
+                    Constraint::Percentage(35),
+                    Constraint::Percentage(60),
+                ],
+            ),
+        };
+
         let lib = Block::default()
             .border_style(border_style)
             .border_type(BorderType::Thick)
             .borders(Borders::LEFT | Borders::RIGHT);
 
-        let header_cells = ["No.", "Title", "Artist", "Album", "Duration"]
+        let header_cells = headers
             .iter()
             .map(|n| Cell::from(*n));
 
@@ -91,13 +138,7 @@ impl View for Board {
             .style(unselected_style)
             .highlight_style(selected_style)
             .highlight_symbol("> ")
-            .widths(&[
-                Constraint::Percentage(5),
-                Constraint::Percentage(30),
-                Constraint::Percentage(30),
-                Constraint::Percentage(25),
-                Constraint::Percentage(10),
-            ]);
+            .widths(widths);
 
         if model.focus == 3 {
             let chunks = Layout::default()
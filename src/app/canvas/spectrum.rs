@@ -1,4 +1,6 @@
 use super::prelude::*;
+use tui::widgets::Paragraph;
+use crate::app::model::player::equalizer::{FREQUENCIES, MAX_GAIN};
 
 #[derive(Debug)]
 pub struct Spectrum {
@@ -31,8 +33,46 @@ impl View for Spectrum {
             .style(border_style)
             .border_type(BorderType::Thick)
             .borders(Borders::LEFT | Borders::RIGHT);
-
+        let inner = spectrum.inner(area);
         f.render_widget(spectrum, area);
+
+        let equalizer = &model.player.equalizer;
+        let mut lines = vec![
+            Spans::from(Span::styled(
+                format!(" EQ: {}", equalizer.name()),
+                Style::default().fg(colorscheme["board_header"]),
+            )),
+            Spans::default(),
+        ];
+        lines.extend(FREQUENCIES.iter().zip(equalizer.gains().iter()).enumerate().map(|(i, (&freq, &gain))| {
+            let style = if model.band == Some(i) {
+                Style::default().fg(colorscheme["board_selected"])
+            } else {
+                Style::default().fg(colorscheme["board_unselected"])
+            };
+            Spans::from(Span::styled(band(freq, gain, inner.width as usize), style))
+        }));
+
+        f.render_widget(Paragraph::new(lines), inner);
     }
 }
 
+/// One row of the equalizer: the center frequency, a bar growing from the
+/// middle to the left or the right, and the gain.
+#[inline]
+fn band(freq: f32, gain: f32, width: usize) -> String {
+    let label = if freq >= 1000.0 {
+        format!("{}k", freq / 1000.0)
+    } else {
+        format!("{}", freq)
+    };
+    // Room left for the bar once the label and the gain are printed.
+    let half = width.saturating_sub(4 + 1 + 1 + 4) / 2;
+    let len = ((gain.abs() / MAX_GAIN) * half as f32).round() as usize;
+    let (left, right) = if gain < 0.0 {
+        (format!("{:>w$}", "█".repeat(len), w = half), String::new())
+    } else {
+        (" ".repeat(half), "█".repeat(len))
+    };
+    format!("{:>4} {}│{:<w$} {:+3.0}", label, left, right, gain, w = half)
+}
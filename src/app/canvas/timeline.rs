@@ -1,14 +1,16 @@
 use super::prelude::*;
 use tui::layout::{Layout, Constraint};
-use tui::widgets::Gauge;
+use tui::widgets::{Gauge, Paragraph};
 use tui::style::Modifier;
 use crate::utils::display_duration;
-use std::sync::atomic::Ordering;
+use crate::app::model::player::volume;
+use std::time::Duration;
 
 #[derive(Debug)]
 pub struct Timeline {
     pub win_id: u64,
     pub area: Rect,
+    pub gauge: Rect,
 }
 
 impl Default for Timeline {
@@ -17,6 +19,7 @@ impl Default for Timeline {
         Timeline {
             win_id: 2,
             area: Default::default(),
+            gauge: Default::default(),
         }
     }
 }
@@ -40,21 +43,63 @@ impl View for Timeline {
                 Constraint::Length(2),].as_ref()
             ).split(area);
 
+        let player = &model.player;
+        let mut status = vec![player.mode.to_string()];
+        status.push(if player.muted() {
+            "muted".to_owned()
+        } else {
+            match volume::decibels(player.volume()) {
+                Some(db) => format!("vol {}% ({:.0} dB)", player.volume(), db),
+                None => format!("vol {}%", player.volume()),
+            }
+        });
+        let balance = player.balance();
+        if balance != 0.0 {
+            let side = if balance < 0.0 { "L" } else { "R" };
+            status.push(format!("bal {}{:.0}", side, balance.abs() * 100.0));
+        }
+        if player.mono() {
+            status.push("mono".to_owned());
+        }
+
         let timeline = Block::default()
+            .title(format!(" {} ", status.join(" · ")))
             .style(border_style)
             .border_type(BorderType::Thick)
             .borders(Borders::TOP | Borders::BOTTOM);
 
         f.render_widget(timeline, area);
 
-        let current = display_duration(Some(model.player.elapsed.load(Ordering::Relaxed)));
-        let total = if let Some(s) = model.player.current.as_ref() {
-            display_duration(s.metadata.duration)
+        if let Some(notice) = model.notice() {
+            let line = Rect::new(area.x, chunks[0].y + 1, area.width, 1);
+            let notice = Paragraph::new(notice.to_owned())
+                .style(Style::default().fg(colorscheme["timeline_border"]));
+            f.render_widget(notice, line);
+        }
+
+        let current = display_duration(Some(model.player.position().as_secs()));
+        let total = if model.player.current.is_some() {
+            display_duration(model.player.duration().map(|d| d.as_secs()))
         } else {
             "00:00".to_string()
         };
 
-        let label = format!("{}/{}", current, total);
+        let speed = model.player.speed();
+        let mut label = if speed == 1.0 {
+            format!("{}/{}", current, total)
+        } else {
+            let remaining = display_duration(model.player.remaining().map(|r| r.as_secs()));
+            format!("{}/{} {}x -{}", current, total, speed, remaining)
+        };
+        if let Some(station) = model.player.station() {
+            label = match model.player.stream_title() {
+                Some(title) => format!("{}: {} {}", station.name, title, current),
+                None => format!("{} {}", station.name, current),
+            };
+        }
+        if let Some(timer) = model.player.timer() {
+            label = format!("{} ({})", label, timer);
+        }
         let gauge = Gauge::default()
             .block(Block::default())
             .label(label)
@@ -66,8 +111,24 @@ impl View for Timeline {
             )
             .ratio(model.player.ratio().unwrap_or(0.0));
 
+        self.gauge = chunks[1];
         f.render_widget(gauge, chunks[1]);
 
+        // Highlight the range of the A-B loop, or point A alone.
+        if let (Some((a, b)), Some(duration)) = (model.player.marks(), model.player.duration()) {
+            let gauge = chunks[1];
+            let column = |pos: Duration| {
+                let ratio = (pos.as_secs_f64() / duration.as_secs_f64()).min(1.0);
+                gauge.left() + ((gauge.width as f64 * ratio) as u16).min(gauge.width.saturating_sub(1))
+            };
+            let left = column(a);
+            let right = b.map(column).unwrap_or(left);
+            let range = Rect::new(left, gauge.y, right - left + 1, 1);
+            let highlight = Block::default()
+                .style(Style::default().add_modifier(Modifier::REVERSED));
+            f.render_widget(highlight, range);
+        }
+
 
     }
 }
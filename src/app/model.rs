@@ -1,17 +1,46 @@
 pub mod player;
 pub mod library;
+pub mod radio;
+pub mod session;
 
 use player::Player;
 use library::{Library, Flag};
-use crate::Launch;
+use radio::Station;
+use session::Session;
+use crate::{DEBUG, Launch};
 use crate::error::Result;
 use crate::config::Config;
 use crate::app::model::library::song::Song;
 use rand::prelude::*;
 use tui::widgets::{TableState, ListState};
 use crate::app::model::player::Mode;
+use crate::app::model::player::equalizer::FREQUENCIES;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::Ordering::Relaxed;
+use std::time::{Duration, Instant};
 use log::{info, trace};
 
+/// How often the session is saved while running.
+const CHECKPOINT_PERIOD: Duration = Duration::from_secs(30);
+
+/// How long a notice stays on screen.
+const NOTICE_PERIOD: Duration = Duration::from_secs(5);
+
+/// What the board lists.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Listing {
+    Songs,
+    Stations,
+}
+
+impl Default for Listing {
+    #[inline]
+    fn default() -> Self {
+        Listing::Songs
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct Model {
     pub focus: u64,
@@ -23,26 +52,52 @@ pub struct Model {
     pub library: Library,
     pub offset: Option<usize>,
     pub board_state: TableState,
+    /// Equalizer band selected in the spectrum.
+    pub band: Option<usize>,
     pub songs: Vec<Vec<String>>,
-    pub current_play_idx: Option<usize>,
+    /// Songs behind the rows of `songs`.
+    pub listed: Vec<Song>,
+    pub listing: Listing,
+    pub stations: Vec<Station>,
+    /// Message for the user and when it was given.
+    notice: Option<(String, Instant)>,
+    last_checkpoint: Option<Instant>,
 }
 
 impl Launch for Model {
     fn bootstrap(&mut self, config: &Config) -> Result<()> {
         self.player.bootstrap(config)?;
         self.library.bootstrap(config)?;
+        self.player.bookmarks.load(self.library.bookmarks()?);
+        self.stations = radio::load(config.stations.as_ref().unwrap(), config.station_files.as_ref().unwrap())?;
         self.sync_headers()?;
+        if config.restore_session.unwrap() {
+            self.restore(config.resume.unwrap(), config.volume.is_none())?;
+        }
+        self.last_checkpoint = Some(Instant::now());
         Ok(())
     }
 }
 
 impl Model {
 
+    /// Number of rows on the board.
+    #[inline]
+    pub fn rows(&self) -> usize {
+        match self.listing {
+            Listing::Songs => self.songs.len(),
+            Listing::Stations => self.stations.len(),
+        }
+    }
+
     #[inline]
     pub fn select_next_song(&mut self) {
+        if self.rows() == 0 {
+            return;
+        }
         self.offset = match self.board_state.selected() {
             Some(i) => {
-                if i >= self.songs.len() - 1 {
+                if i >= self.rows() - 1 {
                     Some(0)
                 } else {
                     Some(i + 1)
@@ -55,10 +110,13 @@ impl Model {
 
     #[inline]
     pub fn select_previous_song(&mut self) {
+        if self.rows() == 0 {
+            return;
+        }
         self.offset = match self.board_state.selected() {
             Some(i) => {
                 if i == 0 {
-                    Some(self.songs.len() - 1)
+                    Some(self.rows() - 1)
                 } else {
                     Some(i - 1)
                 }
@@ -70,9 +128,21 @@ impl Model {
 
     #[inline]
     pub fn open_search(&mut self) {
+        self.listing = Listing::Songs;
         self.focus = 3;
     }
 
+    /// List the radio stations instead of the songs, or the other way around.
+    #[inline]
+    pub fn toggle_listing(&mut self) {
+        self.listing = match self.listing {
+            Listing::Songs => Listing::Stations,
+            Listing::Stations => Listing::Songs,
+        };
+        self.unselect_board();
+        self.topline = 0;
+    }
+
     #[inline]
     pub fn close_search(&mut self) {
         self.focus = 0;
@@ -92,8 +162,33 @@ impl Model {
         self.offset = None;
     }
 
+    #[inline]
+    pub fn select_next_band(&mut self) {
+        self.band = match self.band {
+            Some(i) => Some((i + 1) % FREQUENCIES.len()),
+            None => Some(0),
+        };
+    }
+
+    #[inline]
+    pub fn select_previous_band(&mut self) {
+        self.band = match self.band {
+            Some(0) | None => Some(FREQUENCIES.len() - 1),
+            Some(i) => Some(i - 1),
+        };
+    }
+
+    /// Raise or lower the gain of the selected band by `delta` dB.
+    #[inline]
+    pub fn adjust_band(&mut self, delta: f32) {
+        if let Some(band) = self.band {
+            self.player.equalizer.adjust(band, delta);
+        }
+    }
+
+    #[inline]
     pub fn unselect_spectrum(&mut self) {
-        // TODO
+        self.band = None;
     }
 
     #[inline]
@@ -105,8 +200,8 @@ impl Model {
     pub fn sync_headers(&mut self) -> Result<()> {
         let mut idx = 1;
         let query = self.query().clone();
-        self.songs = self.library
-            .songs(self.flag, query)?
+        self.listed = self.library.songs(self.flag, query)?;
+        self.songs = self.listed
             .iter()
             .map(|s| {
                 let mut raw_row = s.row();
@@ -115,10 +210,131 @@ impl Model {
                 raw_row
             })
             .collect();
+        if !self.player.queue.is_empty() {
+            self.player.update_queue(self.listed.clone());
+        }
+
+        Ok(())
+    }
+
+    /// Queue up the songs on the board and play the one at `offset`.
+    #[inline]
+    pub fn play(&mut self, offset: usize) -> Result<()> {
+        self.player.queue.load(self.listed.clone(), offset);
+        if let Some(song) = self.player.queue.current().cloned() {
+            self.player.handle(&song)?;
+        }
+        Ok(())
+    }
+
+    /// Tune in to the station at `offset`.
+    #[inline]
+    pub fn tune(&mut self, offset: usize) -> Result<()> {
+        if let Some(station) = self.stations.get(offset).cloned() {
+            self.player.tune(&station)?;
+        }
+        Ok(())
+    }
+
+    /// Bring back the session saved by the last run, `resume` cues its song
+    /// paused at the position it was left at. Its volume is only brought back
+    /// with `volume`, a volume given in the config or on the command line wins.
+    #[inline]
+    fn restore(&mut self, resume: bool, volume: bool) -> Result<()> {
+        let session = match self.library.load_session()? {
+            Some(session) => session,
+            None => return Ok(()),
+        };
+        if DEBUG.load(Relaxed) { info!("Restore the last session."); }
+        let songs: HashMap<PathBuf, Song> = self.library
+            .record
+            .cache
+            .iter()
+            .map(|s| (s.id(), s.clone()))
+            .collect();
+
+        self.flag = session.flag;
+        self.player.mode = session.mode;
+        if volume {
+            self.player.set_volume(session.volume);
+        }
+        self.player.history = Session::find(&session.history, &songs);
+
+        let queue = Session::find(&session.queue, &songs);
+        let current = session.song.as_ref().and_then(|p| songs.get(p)).cloned();
+        let cursor = current
+            .as_ref()
+            .and_then(|c| queue.iter().position(|s| s.id() == c.id()))
+            .unwrap_or(queue.len());
+        self.player.queue.load(queue, cursor);
+        if let (true, Some(song)) = (resume, current) {
+            if let Err(e) = self.player.cue(&song, session.position) {
+                if DEBUG.load(Relaxed) { trace!("Unable to resume {:?}: {}", song.path(), e); }
+            }
+        }
 
+        self.sync_headers()?;
+        if let Some(offset) = session.selected.filter(|&i| i < self.songs.len()) {
+            self.select_board(offset);
+        }
         Ok(())
     }
 
+    /// Let the player move on and pick up what it has to say.
+    #[inline]
+    pub fn tick(&mut self) -> Result<()> {
+        self.player.tick()?;
+        if let Some(notice) = self.player.take_notice() {
+            self.notify(notice);
+        }
+        for path in self.player.take_broken() {
+            if let Err(e) = self.library.mark_broken(&path) {
+                self.notify(format!("Unable to remember {} is broken: {}", path.display(), e));
+            }
+            self.listed
+                .iter_mut()
+                .filter(|s| s.id() == path)
+                .for_each(|s| s.broken = true);
+        }
+        Ok(())
+    }
+
+    #[inline]
+    pub fn notify(&mut self, notice: String) {
+        self.notice = Some((notice, Instant::now()));
+    }
+
+    /// The notice to show, until it's been on screen for `NOTICE_PERIOD`.
+    #[inline]
+    pub fn notice(&self) -> Option<&str> {
+        match self.notice.as_ref() {
+            Some((notice, since)) if since.elapsed() < NOTICE_PERIOD => Some(notice),
+            _ => None,
+        }
+    }
+
+    /// Save the session so that the next run can pick up from here.
+    #[inline]
+    pub fn checkpoint(&mut self) -> Result<()> {
+        self.library.save_session(&Session::capture(self))?;
+        self.library.save_bookmarks(self.player.bookmarks.drain())?;
+        self.last_checkpoint = Some(Instant::now());
+        Ok(())
+    }
+
+    /// Save the session every `CHECKPOINT_PERIOD`, in case `uta` doesn't exit
+    /// cleanly. A failure is reported and tried again next period.
+    #[inline]
+    pub fn autosave(&mut self) {
+        if matches!(self.last_checkpoint, Some(last) if last.elapsed() < CHECKPOINT_PERIOD) {
+            return;
+        }
+        if let Err(e) = self.checkpoint() {
+            self.last_checkpoint = Some(Instant::now());
+            self.notify(format!("Unable to save the session: {}", e));
+        }
+    }
+
     #[inline]
     pub fn query(&self) -> Option<String> {
         if !self.query.is_empty() && self.focus == 3 {
@@ -128,19 +344,4 @@ impl Model {
         }
     }
 
-
-
-    // #[inline]
-    // pub fn next_song(&self) -> usize {
-    //     match self.player.mode {
-    //         Mode::Sequential => {
-    //             if let Some(i) = self.current_play_idx {
-    //                 if i >= self.songs
-    //             }
-    //         }
-    //         Mode::SingleCycle => {}
-    //         Mode::Random => {}
-    //     }
-    // }
-
 }
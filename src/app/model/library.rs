@@ -1,23 +1,54 @@
+pub mod ape;
+pub mod cue;
+pub mod decode;
 pub mod format;
+pub mod loudness;
+pub mod mp4;
+pub mod ogg;
+pub mod reader;
+pub mod replaygain;
+pub mod schema;
 pub mod song;
 pub mod sql;
+pub mod wav;
 
-use crate::{DEBUG, Launch};
-use crate::error::{Result, anyhow, BrokenConnection};
+use crate::{DEBUG, Launch, CUE_SHEET};
+use crate::error::{Result, anyhow, Unknown, BrokenConnection};
 use crate::config::Config;
 use crate::utils::{setup_logger, get_snapshot, get_last_modified_time,};
-use std::sync::atomic::Ordering::Relaxed;
-use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering::Relaxed};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 use std::fs;
-use std::time::SystemTime;
+use std::thread;
+use std::time::{Duration, SystemTime};
 use sql::*;
 use rayon::prelude::*;
-use rusqlite::{params, Connection, NO_PARAMS};
+use rusqlite::{params, Connection, Row, NO_PARAMS};
+use rusqlite::types::Type;
 use serde::{Deserialize, Serialize};
+use serde::de::DeserializeOwned;
 use song::Song;
+use super::session::Session;
+use loudness::Loudness;
+use reader::{MetadataReader, Readers};
 use log::{info, trace};
 
-#[derive(Debug, Copy, Clone, PartialEq)]
+/// How many analysis results are kept in memory before they are written to the database.
+const ANALYSIS_BATCH: usize = 32;
+
+/// `incremental` only reads the files that changed since the last scan, `full` reads them all.
+pub const RESCAN_MODES: [&str; 2] = ["incremental", "full"];
+
+/// Path of the test file `name`.
+#[cfg(test)]
+pub(crate) fn fixture(name: &str) -> PathBuf {
+    Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures")).join(name)
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Flag {
     Title,
     Artist,
@@ -36,6 +67,18 @@ pub struct Library {
     flag: Flag,
     pub record: Record,
     database: Option<Connection>,
+    loudness_scan: bool,
+    full_rescan: bool,
+    analysis: Option<Analysis>,
+    readers: Readers,
+}
+
+/// Loudness analysis running in the background.
+#[derive(Debug)]
+struct Analysis {
+    cancel: Arc<AtomicBool>,
+    results: Receiver<(PathBuf, Loudness)>,
+    unsaved: Vec<(PathBuf, Loudness)>,
 }
 
 #[derive(Debug, Default)]
@@ -53,7 +96,8 @@ impl Launch for Library {
             info!("Start to bootstrap library.");
         }
         self.record.pos = PathBuf::from(config.lib_pos.as_ref().unwrap());
-        self.record.modified = Some(get_last_modified_time(&self.record.pos));
+        self.loudness_scan = config.loudness_scan.unwrap();
+        self.full_rescan = config.rescan.as_ref().unwrap() == "full";
 
         let db_pos = PathBuf::from(config.db_pos.as_ref().unwrap());
         if !db_pos.exists() {
@@ -69,38 +113,37 @@ impl Launch for Library {
             }
             fs::File::create(&db)?;
         }
-        self.database = Some(Connection::open(&db)?);
+        let mut conn = Connection::open(&db)?;
+        conn.execute_batch(ENABLE_FOREIGN_KEYS)?;
+        schema::migrate(&mut conn)?;
+        self.database = Some(conn);
 
-        self.connection()?.execute(BUILD_RECORD, NO_PARAMS)?;
         self.clean_up()?;
 
+        // The modification time of the root doesn't tell whether anything
+        // deeper down changed, the cache is brought up to date every time and
+        // only what changed is read again.
         if let Ok(record) = self.fetch() {
             if DEBUG.load(Relaxed) {
                 trace!("GOT RECORD.");
             }
-            if self.record.modified == record.modified {
-                if DEBUG.load(Relaxed) {
-                    trace!("FRESH CACHE.");
-                }
-                self.record.cache = record.cache;
-            } else {
-                if DEBUG.load(Relaxed) {
-                    trace!("EXPIRED CACHE.");
-                }
-                self.commit(false)?;
-            }
-        } else {
-            if DEBUG.load(Relaxed) {
-                trace!("NO RECORD.");
-            }
-            self.commit(true)?;
+            self.record.cache = record.cache;
+        } else if DEBUG.load(Relaxed) {
+            trace!("NO RECORD OR UNREADABLE RECORD.");
         }
+        self.commit()?;
         Ok(())
     }
 }
 
 impl Library {
 
+    /// Have `reader` read the files it probes, ahead of the built-in readers.
+    #[inline]
+    pub fn register(&mut self, reader: impl MetadataReader + 'static) {
+        self.readers.register(reader);
+    }
+
     #[inline]
     pub fn songs(&mut self, flag: Flag, query: Option<String>) -> Result<Vec<Song>> {
         if self.flag != flag {
@@ -141,26 +184,33 @@ impl Library {
     }
 
     #[inline]
-    pub fn commit(&mut self, append: bool) -> Result<()> {
+    pub fn commit(&mut self) -> Result<()> {
         if DEBUG.load(Relaxed) {
-            trace!("Commit to database. Append: {}.", append);
+            trace!("Commit to database. Full rescan: {}.", self.full_rescan);
+        }
+        let changed = self.sync();
+        self.save(&changed)?;
+        if self.loudness_scan {
+            self.analyze();
         }
-        self.sync();
-        let conn = self.connection()?;
-        if !append {
-            conn.execute(
-                DELETE_RECORD,
-                params![bincode::serialize(&self.record.pos)?],
-            )?;
-        }
-        conn.execute(
-            INSERT_RECORD,
-            params![
-                bincode::serialize(&self.record.pos)?,
-                bincode::serialize(&self.record.cache)?,
-                bincode::serialize(&self.record.modified)?
-            ],
-        )?;
+
+        Ok(())
+    }
+
+    /// Write the songs of the `changed` files to the database, in place of
+    /// what was there.
+    #[inline]
+    fn save(&self, changed: &HashSet<PathBuf>) -> Result<()> {
+        let tx = self.connection()?.unchecked_transaction()?;
+        let library = schema::save_library(&tx, &self.record.pos, self.record.modified)?;
+        for path in changed {
+            tx.prepare_cached(DELETE_FILE)?.execute(params![library, schema::utf8(path)?])?;
+        }
+        for song in self.record.cache.iter().filter(|s| changed.contains(&s.path())) {
+            schema::insert_song(&tx, library, song)?;
+        }
+        tx.execute_batch(DELETE_ORPHANS)?;
+        tx.commit()?;
 
         Ok(())
     }
@@ -174,16 +224,228 @@ impl Library {
         }
     }
 
+    /// Bring the cache up to date with the files, returns the paths whose
+    /// songs were read again or are gone. A file is only read again if it is
+    /// new, if its modification time, size or inode changed, or on a full
+    /// rescan, and so is a CUE sheet along with the files it splits.
     #[inline]
-    fn sync(&mut self) {
+    fn sync(&mut self) -> HashSet<PathBuf> {
         if DEBUG.load(Relaxed) {
             trace!("Get the latest snapshot.");
         }
         self.record.modified = Some(get_last_modified_time(&self.record.pos));
-        self.record.cache = get_snapshot(&self.record.pos)
+        let mut cached = HashMap::<_, Vec<_>>::new();
+        for song in std::mem::take(&mut self.record.cache) {
+            cached.entry(song.path()).or_default().push(song);
+        }
+        let analyzed = cached
+            .values()
+            .flatten()
+            .filter_map(|s| s.metadata.loudness.map(|l| (s.path(), l)))
+            .collect::<HashMap<_, _>>();
+        let mut sheeted = HashMap::<_, Vec<_>>::new();
+        for song in cached.values().flatten() {
+            if let Some(sheet) = song.sheet.as_ref() {
+                sheeted.entry(sheet.clone()).or_default().push(song);
+            }
+        }
+        let readers = &self.readers;
+        let full = self.full_rescan;
+        let (sheets, files): (Vec<_>, Vec<_>) = get_snapshot(&self.record.pos, &readers.extensions())
+            .into_iter()
+            .partition(|p| p.extension().and_then(|e| e.to_str()).map_or(false, |e| e.eq_ignore_ascii_case(CUE_SHEET)));
+        // Files split by a CUE sheet only show up as its tracks, a sheet is
+        // kept as it is if neither it nor the files it splits changed.
+        let (kept, sheets): (Vec<_>, Vec<_>) = sheets
+            .into_iter()
+            .partition(|p| !full && sheeted.get(p).map_or(false, |songs| songs.iter().all(|s| s.is_current())));
+        let tracks = sheets
+            .par_iter()
+            .filter_map(|p| cue::read(p, readers).ok())
+            .flatten()
+            .collect::<Vec<_>>();
+        let split = tracks
+            .iter()
+            .map(|s| s.path())
+            .chain(kept.iter().flat_map(|p| sheeted[p].iter().map(|s| s.path())))
+            .collect::<HashSet<_>>();
+        // Without ffmpeg some formats can't be played, they are left out.
+        // Only what is read again is checked, the rest was checked back then.
+        let tracks = tracks.into_par_iter().filter(|s| decode::playable(s.path())).collect::<Vec<_>>();
+        let (current, stale): (Vec<_>, Vec<_>) = files
+            .into_par_iter()
+            .filter(|p| !split.contains(p))
+            .partition(|p| {
+                // So are single-file albums, their embedded sheet may be all that changed.
+                !full && matches!(cached.get(p).map(Vec::as_slice), Some([s]) if s.track.is_none() && s.is_current())
+            });
+        let stale = stale.into_par_iter().filter(|p| decode::playable(p)).collect::<Vec<_>>();
+        let unchanged = kept
+            .iter()
+            .flat_map(|p| sheeted[p].iter().map(|s| s.path()))
+            .chain(current)
+            .collect::<HashSet<_>>();
+        if DEBUG.load(Relaxed) {
+            trace!("Read {} files and {} sheets again, {} files are unchanged.", stale.len(), sheets.len(), unchanged.len());
+        }
+        let fresh = stale
             .par_iter()
-            .filter_map(|p| Song::new(p).ok())
+            .flat_map(|p| cue::expand(p, readers))
+            .chain(tracks)
+            .map(|mut s| {
+                if let Some(loudness) = analyzed.get(&s.path()) {
+                    if loudness.is_fresh(s.path()) {
+                        s.metadata.loudness = Some(*loudness);
+                    }
+                }
+                s
+            })
             .collect::<Vec<_>>();
+
+        self.record.cache.extend(unchanged.iter().filter_map(|p| cached.remove(p)).flatten());
+        // What is left was read again or is gone.
+        let changed = cached
+            .into_iter()
+            .map(|(path, _)| path)
+            .chain(fresh.iter().map(|s| s.path()))
+            .collect();
+        self.record.cache.extend(fresh);
+        changed
+    }
+
+    /// Measure the loudness of every song that has neither ReplayGain tags nor
+    /// been analyzed yet in a background thread, any analysis already running
+    /// is cancelled first.
+    #[inline]
+    pub fn analyze(&mut self) {
+        self.cancel_analysis();
+        let pending = self
+            .record
+            .cache
+            .iter()
+            .filter(|s| s.metadata.loudness.is_none())
+            .filter(|s| s.metadata.replaygain.track_gain.is_none() && s.metadata.replaygain.album_gain.is_none())
+            .map(|s| s.path())
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+        if pending.is_empty() {
+            return;
+        }
+        if DEBUG.load(Relaxed) {
+            trace!("Analyze the loudness of {} songs.", pending.len());
+        }
+
+        let (tx, rx) = mpsc::channel();
+        let cancel = Arc::new(AtomicBool::new(false));
+        let cancelled = cancel.clone();
+        thread::spawn(move || {
+            for path in pending {
+                if cancelled.load(Relaxed) {
+                    break;
+                }
+                match loudness::analyze(&path, &cancelled) {
+                    Ok(Some(loudness)) => {
+                        if tx.send((path, loudness)).is_err() {
+                            break;
+                        }
+                    }
+                    Ok(None) => break,
+                    Err(e) => {
+                        if DEBUG.load(Relaxed) {
+                            trace!("Unable to analyze {}: {}", path.display(), e);
+                        }
+                    }
+                }
+            }
+        });
+        self.analysis = Some(Analysis {
+            cancel,
+            results: rx,
+            unsaved: Vec::new(),
+        });
+    }
+
+    #[inline]
+    pub fn cancel_analysis(&mut self) {
+        if let Some(analysis) = self.analysis.take() {
+            analysis.cancel.store(true, Relaxed);
+        }
+    }
+
+    /// Collect the results of the background analysis, they are written to the
+    /// database in batches and once the analysis is over.
+    #[inline]
+    pub fn poll_analysis(&mut self) -> Result<()> {
+        let analysis = match self.analysis.as_mut() {
+            Some(analysis) => analysis,
+            None => return Ok(()),
+        };
+        let mut finished = false;
+        loop {
+            match analysis.results.try_recv() {
+                Ok((path, loudness)) => {
+                    // The tracks of a single-file album are analyzed as a whole.
+                    for song in self.record.cache.iter_mut().filter(|s| s.path() == path) {
+                        song.metadata.loudness = Some(loudness);
+                    }
+                    analysis.unsaved.push((path, loudness));
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    finished = true;
+                    break;
+                }
+            }
+        }
+
+        if finished || analysis.unsaved.len() >= ANALYSIS_BATCH {
+            let unsaved = std::mem::take(&mut analysis.unsaved);
+            if finished {
+                if DEBUG.load(Relaxed) {
+                    trace!("Loudness analysis is over.");
+                }
+                self.analysis = None;
+            }
+            let tx = self.connection()?.unchecked_transaction()?;
+            let pos = schema::utf8(&self.record.pos)?;
+            for (path, loudness) in unsaved {
+                let (modified, size) = loudness.stamp;
+                tx.execute(
+                    SAVE_LOUDNESS,
+                    params![
+                        pos,
+                        schema::utf8(&path)?,
+                        loudness.integrated,
+                        loudness.true_peak,
+                        schema::nanos(modified),
+                        size as i64
+                    ],
+                )?;
+            }
+            tx.commit()?;
+        }
+        Ok(())
+    }
+
+    /// Flag the song at `path` as unplayable, until its file changes or the
+    /// next full rescan.
+    #[inline]
+    pub fn mark_broken(&mut self, id: &Path) -> Result<()> {
+        let song = match self.record.cache.iter_mut().find(|s| s.id() == id) {
+            Some(song) => song,
+            None => return Ok(()),
+        };
+        if DEBUG.load(Relaxed) {
+            trace!("Mark {:?} as broken.", id);
+        }
+        song.broken = true;
+        let (path, track) = (song.path(), song.track.map(|t| t.number));
+        self.connection()?.execute(
+            MARK_BROKEN,
+            params![schema::utf8(&self.record.pos)?, schema::utf8(&path)?, track],
+        )?;
+        Ok(())
     }
 
     #[inline]
@@ -191,16 +453,75 @@ impl Library {
         if DEBUG.load(Relaxed) {
             trace!("FETCH cache from database.");
         }
-        Ok(self.connection()?.prepare(FETCH_RECORD)?.query_row(
-            params![bincode::serialize(&self.record.pos)?],
-            |row| {
-                Ok(Record {
-                    pos: bincode::deserialize(&row.get::<_, Vec<u8>>(0)?).unwrap(),
-                    cache: bincode::deserialize(&row.get::<_, Vec<u8>>(1)?).unwrap(),
-                    modified: bincode::deserialize(&row.get::<_, Vec<u8>>(2)?).unwrap(),
-                })
-            },
-        )?)
+        let conn = self.connection()?;
+        let (library, modified) = schema::fetch_library(conn, &self.record.pos)?.ok_or_else(|| anyhow!(Unknown))?;
+        let cache = conn
+            .prepare(FETCH_SONGS)?
+            .query_map(params![library], schema::read_song)?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(Record {
+            pos: self.record.pos.clone(),
+            cache,
+            modified,
+        })
+    }
+
+    #[inline]
+    pub fn save_session(&self, session: &Session) -> Result<()> {
+        if DEBUG.load(Relaxed) {
+            trace!("Save the session.");
+        }
+        self.connection()?.execute(
+            SAVE_SESSION,
+            params![schema::utf8(&self.record.pos)?, bincode::serialize(session)?],
+        )?;
+        Ok(())
+    }
+
+    /// The session saved for this library, `None` if there is none or it can't be read.
+    #[inline]
+    pub fn load_session(&self) -> Result<Option<Session>> {
+        let session = self.connection()?.prepare(FETCH_SESSION)?.query_row(
+            params![schema::utf8(&self.record.pos)?],
+            |row| decode(row, 0),
+        );
+        Ok(session.ok())
+    }
+
+    /// Resume positions of the long songs, keyed by path.
+    #[inline]
+    pub fn bookmarks(&self) -> Result<HashMap<PathBuf, Duration>> {
+        let mut stmt = self.connection()?.prepare(FETCH_ALL_BOOKMARKS)?;
+        let bookmarks = stmt
+            .query_map(NO_PARAMS, |row| {
+                Ok((
+                    PathBuf::from(row.get::<_, String>(0)?),
+                    Duration::from_nanos(row.get::<_, i64>(1)? as u64),
+                ))
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(bookmarks)
+    }
+
+    /// Write the changed bookmarks, `None` deletes one.
+    #[inline]
+    pub fn save_bookmarks(&self, bookmarks: Vec<(PathBuf, Option<Duration>)>) -> Result<()> {
+        for (path, pos) in bookmarks {
+            if DEBUG.load(Relaxed) {
+                trace!("Bookmark {:?} at {:?}.", path, pos);
+            }
+            match pos {
+                Some(pos) => self.connection()?.execute(
+                    SAVE_BOOKMARK,
+                    params![schema::utf8(&path)?, pos.as_nanos() as i64],
+                )?,
+                None => self.connection()?.execute(
+                    DELETE_BOOKMARK,
+                    params![schema::utf8(&path)?],
+                )?,
+            };
+        }
+        Ok(())
     }
 
     #[inline]
@@ -209,18 +530,91 @@ impl Library {
             trace!("Clean up the library.")
         }
         let conn = self.connection()?;
-        conn.prepare(FETCH_ALL_POS)?
-            .query_map(NO_PARAMS, |row| {
-                Ok(bincode::deserialize::<PathBuf>(&row.get::<_, Vec<u8>>(0)?).unwrap())
-            })?
+        let gone = conn
+            .prepare(FETCH_ALL_LIBRARIES)?
+            .query_map(NO_PARAMS, |row| row.get::<_, String>(0))?
             .filter_map(Result::ok)
-            .for_each(|p| {
-                if !p.exists() {
-                    conn.execute(DELETE_RECORD, params![bincode::serialize(&p).unwrap()])
-                        .unwrap();
-                }
-            });
+            .filter(|p| !Path::new(p).exists())
+            .collect::<Vec<_>>();
+        for pos in gone {
+            // Its songs go with it.
+            conn.execute(DELETE_LIBRARY, params![pos])?;
+        }
+        conn.execute_batch(DELETE_ORPHANS)?;
 
         Ok(())
     }
 }
+
+/// Deserialize a bincode column, a record written by an older version of
+/// `Song` fails here instead of panicking so that it can be rebuilt.
+#[inline]
+fn decode<T: DeserializeOwned>(row: &Row, idx: usize) -> rusqlite::Result<T> {
+    bincode::deserialize(&row.get::<_, Vec<u8>>(idx)?)
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(idx, Type::Blob, e))
+}
+
+impl Drop for Library {
+    #[inline]
+    fn drop(&mut self) {
+        self.cancel_analysis();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::app::model::player::Mode;
+
+    /// The library at `pos`, kept in a database in memory.
+    fn library(pos: &str) -> Library {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(ENABLE_FOREIGN_KEYS).unwrap();
+        schema::migrate(&mut conn).unwrap();
+        let mut library = Library::default();
+        library.record.pos = PathBuf::from(pos);
+        library.database = Some(conn);
+        library
+    }
+
+    #[test]
+    fn test_session() {
+        let mut library = library("/music");
+        assert_eq!(library.load_session().unwrap(), None);
+        let session = Session {
+            song: Some(PathBuf::from("/music/album.flac#02")),
+            position: Duration::from_millis(61_250),
+            queue: vec![PathBuf::from("/music/album.flac#01"), PathBuf::from("/music/album.flac#02")],
+            mode: Mode::Random,
+            volume: 40,
+            flag: Flag::Artist,
+            selected: Some(3),
+            history: vec![PathBuf::from("/music/single.mp3")],
+        };
+        library.save_session(&session).unwrap();
+        assert_eq!(library.load_session().unwrap(), Some(session.clone()));
+        // Saving again replaces it.
+        let session = Session { position: Duration::from_secs(0), ..session };
+        library.save_session(&session).unwrap();
+        assert_eq!(library.load_session().unwrap(), Some(session));
+
+        // Every library has a session of its own.
+        library.record.pos = PathBuf::from("/podcasts");
+        assert_eq!(library.load_session().unwrap(), None);
+    }
+
+    #[test]
+    fn test_bookmarks() {
+        let library = library("/music");
+        let book = PathBuf::from("/music/book.mp3");
+        let album = PathBuf::from("/music/album.flac#02");
+        library.save_bookmarks(vec![
+            (book.clone(), Some(Duration::from_millis(5_400_250))),
+            (album.clone(), Some(Duration::from_secs(90))),
+        ]).unwrap();
+        library.save_bookmarks(vec![(album, None)]).unwrap();
+        let expected = vec![(book, Duration::from_millis(5_400_250))].into_iter().collect::<HashMap<_, _>>();
+        assert_eq!(library.bookmarks().unwrap(), expected);
+    }
+}
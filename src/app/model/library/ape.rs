@@ -0,0 +1,153 @@
+use crate::error::{Result, anyhow, Unknown};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+const PREAMBLE: &[u8; 8] = b"APETAGEX";
+const FOOTER_LEN: u64 = 32;
+/// An ID3v1 tag may come after the APE tag.
+const ID3V1_LEN: u64 = 128;
+
+/// Sample rates of WavPack, by the index in the flags of a block.
+const WAVPACK_RATES: [u64; 15] = [
+    6000, 8000, 9600, 11025, 12000, 16000, 22050, 24000, 32000, 44100, 48000, 64000, 88200, 96000, 192000,
+];
+
+/// Read the APEv2 tag at the end of a file, as used by WavPack and Monkey's Audio.
+///
+/// Keys are upper-cased since they are case-insensitive, binary items are left out.
+#[inline]
+pub fn read_tag(path: impl AsRef<Path>) -> Result<HashMap<String, Vec<String>>> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+    for end in [len, len.saturating_sub(ID3V1_LEN)].iter() {
+        if *end < FOOTER_LEN {
+            continue;
+        }
+        let mut footer = [0; FOOTER_LEN as usize];
+        file.seek(SeekFrom::Start(end - FOOTER_LEN))?;
+        file.read_exact(&mut footer)?;
+        if &footer[..8] != PREAMBLE {
+            continue;
+        }
+        // The size covers the items and the footer, not the header.
+        let size = u32::from_le_bytes([footer[12], footer[13], footer[14], footer[15]]) as u64;
+        let count = u32::from_le_bytes([footer[16], footer[17], footer[18], footer[19]]);
+        let start = end.checked_sub(size).ok_or_else(|| anyhow!(Unknown))?;
+        let mut items = vec![0; size.saturating_sub(FOOTER_LEN) as usize];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(&mut items)?;
+        return Ok(parse_items(&items, count));
+    }
+    Err(anyhow!(Unknown))
+}
+
+#[inline]
+fn parse_items(mut items: &[u8], count: u32) -> HashMap<String, Vec<String>> {
+    let mut tag = HashMap::new();
+    for _ in 0..count {
+        if items.len() < 8 {
+            break;
+        }
+        let len = u32::from_le_bytes([items[0], items[1], items[2], items[3]]) as usize;
+        let flags = u32::from_le_bytes([items[4], items[5], items[6], items[7]]);
+        let key_end = match items[8..].iter().position(|&b| b == 0) {
+            Some(i) => 8 + i,
+            None => break,
+        };
+        let key = String::from_utf8_lossy(&items[8..key_end]).to_uppercase();
+        let value = match items.get(key_end + 1..key_end + 1 + len) {
+            Some(value) => value,
+            None => break,
+        };
+        // Bits 1 and 2 tell the kind of item, 0 is UTF-8 text.
+        if flags >> 1 & 0b11 == 0 {
+            let values = String::from_utf8_lossy(value)
+                .split('\0')
+                .map(str::to_owned)
+                .collect();
+            tag.insert(key, values);
+        }
+        items = &items[key_end + 1 + len..];
+    }
+    tag
+}
+
+/// Length in seconds of a WavPack file, from the header of its first block.
+#[inline]
+pub fn wavpack_duration(path: impl AsRef<Path>) -> Result<u64> {
+    let mut header = [0; 32];
+    File::open(path)?.read_exact(&mut header)?;
+    if &header[..4] != b"wvpk" {
+        return Err(anyhow!(Unknown));
+    }
+    let samples = u32::from_le_bytes([header[12], header[13], header[14], header[15]]);
+    let flags = u32::from_le_bytes([header[24], header[25], header[26], header[27]]);
+    // All ones when the encoder didn't know the length, index 15 for rates off the table.
+    let rate = WAVPACK_RATES.get((flags >> 23 & 0xF) as usize).ok_or_else(|| anyhow!(Unknown))?;
+    if samples == u32::MAX {
+        return Err(anyhow!(Unknown));
+    }
+    Ok(samples as u64 / rate)
+}
+
+/// Length in seconds of a Monkey's Audio file, from its header.
+#[inline]
+pub fn monkey_duration(path: impl AsRef<Path>) -> Result<u64> {
+    let mut file = File::open(path)?;
+    let mut head = [0; 32];
+    file.read_exact(&mut head)?;
+    if &head[..4] != b"MAC " {
+        return Err(anyhow!(Unknown));
+    }
+    let u16_at = |b: &[u8], i: usize| u16::from_le_bytes([b[i], b[i + 1]]) as u64;
+    let u32_at = |b: &[u8], i: usize| u32::from_le_bytes([b[i], b[i + 1], b[i + 2], b[i + 3]]) as u64;
+    let version = u16_at(&head, 4);
+    let (blocks_per_frame, final_blocks, frames, rate) = if version >= 3980 {
+        // A descriptor comes first, then the header.
+        let mut header = [0; 24];
+        file.seek(SeekFrom::Start(u32_at(&head, 8)))?;
+        file.read_exact(&mut header)?;
+        (u32_at(&header, 4), u32_at(&header, 8), u32_at(&header, 12), u32_at(&header, 20))
+    } else {
+        let compression = u16_at(&head, 6);
+        let blocks_per_frame = if version >= 3950 {
+            73728 * 4
+        } else if version >= 3900 || (version >= 3800 && compression == 4000) {
+            73728
+        } else {
+            9216
+        };
+        (blocks_per_frame, u32_at(&head, 28), u32_at(&head, 24), u32_at(&head, 12))
+    };
+    if frames == 0 || rate == 0 {
+        return Err(anyhow!(Unknown));
+    }
+    Ok(((frames - 1) * blocks_per_frame + final_blocks) / rate)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::app::model::library::fixture;
+
+    #[test]
+    fn test_wavpack() {
+        let path = fixture("tagged.wv");
+        let tag = read_tag(&path).unwrap();
+        assert_eq!(tag["TITLE"], ["WavPack Title"]);
+        assert_eq!(tag["ARTIST"], ["One", "Two"]);
+        assert!(!tag.contains_key("COVER ART (FRONT)"));
+        assert_eq!(wavpack_duration(&path).unwrap(), 4);
+    }
+
+    #[test]
+    fn test_monkey() {
+        let path = fixture("tagged.ape");
+        // The APE tag is followed by an ID3v1 tag.
+        assert_eq!(read_tag(&path).unwrap()["ALBUM"], ["Monkey Album"]);
+        assert_eq!(monkey_duration(&path).unwrap(), 5);
+    }
+}
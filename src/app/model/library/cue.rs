@@ -0,0 +1,262 @@
+use super::format::Format;
+use super::loudness;
+use super::reader::Readers;
+use super::song::{Song, Track};
+use crate::error::Result;
+use crate::SUPPORT_FORMAT;
+use crate::utils::get_duration;
+use metaflac::Tag as FLACTag;
+use metaflac::Block;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// CUE sheets count time in CD frames, 75 per second.
+const FRAMES_PER_SEC: u64 = 75;
+
+/// Track numbers of the lead-out in a FLAC CUESHEET block, for CDs and others.
+const LEAD_OUT: [u8; 2] = [170, 255];
+
+/// The parts of a CUE sheet needed to split an album into tracks.
+#[derive(Debug, Default, PartialEq)]
+pub struct Sheet {
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    pub entries: Vec<Entry>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Entry {
+    /// The FILE the track lies in, as written in the sheet.
+    pub file: Option<String>,
+    pub number: u32,
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    /// INDEX 01 of the track, INDEX 00 if there is none.
+    pub start: Duration,
+}
+
+/// Parse the text of a CUE sheet, unknown commands are ignored.
+#[inline]
+pub fn parse(text: &str) -> Sheet {
+    let mut sheet = Sheet::default();
+    let mut file = None;
+    // Whether the current track had its INDEX 01 already.
+    let mut indexed = false;
+    for line in text.trim_start_matches('\u{feff}').lines() {
+        let line = line.trim();
+        let (command, args) = match line.find(char::is_whitespace) {
+            Some(i) => (&line[..i], line[i..].trim()),
+            None => (line, ""),
+        };
+        match command.to_ascii_uppercase().as_str() {
+            "FILE" => {
+                // The file type comes last, unless the name is quoted.
+                let name = if args.starts_with('"') {
+                    unquote(args)
+                } else {
+                    args.rsplitn(2, char::is_whitespace).last().unwrap_or(args)
+                };
+                file = Some(name.to_owned());
+            }
+            "TRACK" => {
+                sheet.entries.push(Entry {
+                    file: file.clone(),
+                    number: args.split_whitespace().next().and_then(|n| n.parse().ok()).unwrap_or(0),
+                    ..Default::default()
+                });
+                indexed = false;
+            }
+            "TITLE" | "PERFORMER" => {
+                let value = Some(unquote(args).to_owned());
+                match (sheet.entries.last_mut(), command.to_ascii_uppercase().as_str()) {
+                    (Some(entry), "TITLE") => entry.title = value,
+                    (Some(entry), _) => entry.performer = value,
+                    (None, "TITLE") => sheet.title = value,
+                    (None, _) => sheet.performer = value,
+                }
+            }
+            "INDEX" => {
+                let mut args = args.split_whitespace();
+                let index = args.next().and_then(|i| i.parse::<u32>().ok());
+                let time = args.next().and_then(timestamp);
+                if let (Some(entry), Some(time)) = (sheet.entries.last_mut(), time) {
+                    match index {
+                        Some(0) if !indexed => entry.start = time,
+                        Some(1) => {
+                            entry.start = time;
+                            indexed = true;
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    sheet
+}
+
+/// The tracks of the CUE sheet at `path`, every file it refers to has to exist.
+#[inline]
+pub fn read(path: &Path, readers: &Readers) -> Result<Vec<Song>> {
+    // Taken first, like the stamp of a song.
+    let stamp = loudness::stamp(path).ok();
+    let text = String::from_utf8_lossy(&fs::read(path)?).into_owned();
+    let sheet = parse(&text);
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut songs = Vec::with_capacity(sheet.entries.len());
+    let mut files = sheet.entries.iter().map(|e| e.file.as_deref()).collect::<Vec<_>>();
+    files.dedup();
+    for name in files.into_iter().flatten() {
+        let file = locate(&dir.join(name))?;
+        let mut base = Song::new(&file, readers)?;
+        if base.metadata.duration.is_none() {
+            base.metadata.duration = get_duration(&file).ok();
+        }
+        let entries = sheet.entries.iter().filter(|e| e.file.as_deref() == Some(name)).collect::<Vec<_>>();
+        songs.extend(split(&base, &entries, &sheet));
+    }
+    for song in songs.iter_mut() {
+        song.sheet = Some(path.to_path_buf());
+        song.sheet_stamp = stamp;
+    }
+    Ok(songs)
+}
+
+/// The song at `path`, or its tracks if it's a FLAC with a CUE sheet inside.
+#[inline]
+pub fn expand(path: &Path, readers: &Readers) -> Vec<Song> {
+    let song = match Song::new(path, readers) {
+        Ok(song) => song,
+        Err(_) => return vec![],
+    };
+    match embedded(path) {
+        Some(sheet) if !sheet.entries.is_empty() => {
+            let entries = sheet.entries.iter().collect::<Vec<_>>();
+            split(&song, &entries, &sheet)
+        }
+        _ => vec![song],
+    }
+}
+
+/// The CUE sheet embedded in a FLAC file, either as a CUESHEET comment, which
+/// has the titles, or as a CUESHEET block, which only has the offsets.
+#[inline]
+fn embedded(path: &Path) -> Option<Sheet> {
+    if !matches!(Format::new(path), Ok(Format::FLAC)) {
+        return None;
+    }
+    let tag = FLACTag::read_from_path(path).ok()?;
+    let comment = tag
+        .vorbis_comments()
+        .and_then(|c| c.get("CUESHEET"))
+        .and_then(|v| v.first());
+    if let Some(text) = comment {
+        return Some(parse(text));
+    }
+    let rate = tag.get_streaminfo()?.sample_rate as u64;
+    let entries = tag
+        .blocks()
+        .find_map(|b| match b {
+            Block::CueSheet(sheet) => Some(sheet),
+            _ => None,
+        })?
+        .tracks
+        .iter()
+        .filter(|t| !LEAD_OUT.contains(&t.number) && rate > 0)
+        .map(|t| {
+            let index = t.indices.iter().find(|i| i.point_num == 1).map_or(0, |i| i.offset);
+            Entry {
+                number: t.number as u32,
+                start: Duration::from_nanos((t.offset + index) * 1_000_000_000 / rate),
+                ..Default::default()
+            }
+        })
+        .collect();
+    Some(Sheet { entries, ..Default::default() })
+}
+
+/// Cut `base` into the tracks of `entries`, each one ends where the next begins.
+#[inline]
+fn split(base: &Song, entries: &[&Entry], sheet: &Sheet) -> Vec<Song> {
+    entries
+        .iter()
+        .enumerate()
+        .map(|(i, entry)| {
+            let track = Track {
+                number: entry.number,
+                start: entry.start,
+                end: entries.get(i + 1).map(|e| e.start),
+            };
+            let title = entry.title.clone().or_else(|| Some(format!("Track {:02}", entry.number)));
+            let performer = entry.performer.clone().or_else(|| sheet.performer.clone());
+            base.slice(track, title, performer, sheet.title.clone())
+        })
+        .collect()
+}
+
+/// Sheets often name the file the album was ripped to before it got converted,
+/// look for the same name with the other extensions too.
+#[inline]
+fn locate(file: &Path) -> Result<PathBuf> {
+    if file.is_file() {
+        return Ok(file.to_path_buf());
+    }
+    SUPPORT_FORMAT
+        .iter()
+        .map(|ext| file.with_extension(ext))
+        .find(|f| f.is_file())
+        .ok_or_else(|| std::io::Error::from(std::io::ErrorKind::NotFound).into())
+}
+
+/// `mm:ss:ff` to a duration.
+#[inline]
+fn timestamp(time: &str) -> Option<Duration> {
+    let mut parts = time.split(':').map(|p| p.parse::<u64>().ok());
+    let (m, s, f) = (parts.next()??, parts.next()??, parts.next()??);
+    Some(Duration::from_secs(m * 60 + s) + Duration::from_nanos(f * 1_000_000_000 / FRAMES_PER_SEC))
+}
+
+#[inline]
+fn unquote(s: &str) -> &str {
+    let s = s.trim();
+    match s.strip_prefix('"') {
+        Some(quoted) => quoted.find('"').map_or(quoted, |end| &quoted[..end]),
+        None => s,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let sheet = parse(concat!(
+            "\u{feff}REM GENRE Rock\r\n",
+            "PERFORMER \"The Band\"\r\n",
+            "TITLE \"The Album\"\r\n",
+            "FILE \"The Band - The Album.wav\" WAVE\r\n",
+            "  TRACK 01 AUDIO\r\n",
+            "    TITLE \"Opening\"\r\n",
+            "    INDEX 01 00:00:00\r\n",
+            "  TRACK 02 AUDIO\r\n",
+            "    TITLE \"Second\"\r\n",
+            "    PERFORMER \"The Band feat. Someone\"\r\n",
+            "    INDEX 00 03:59:70\r\n",
+            "    INDEX 01 04:01:15\r\n",
+        ));
+        assert_eq!(sheet.title.as_deref(), Some("The Album"));
+        assert_eq!(sheet.performer.as_deref(), Some("The Band"));
+        assert_eq!(sheet.entries.len(), 2);
+        let second = &sheet.entries[1];
+        assert_eq!(second.file.as_deref(), Some("The Band - The Album.wav"));
+        assert_eq!(second.number, 2);
+        assert_eq!(second.title.as_deref(), Some("Second"));
+        assert_eq!(second.performer.as_deref(), Some("The Band feat. Someone"));
+        assert_eq!(second.start, Duration::from_millis(241_200));
+        assert_eq!(sheet.entries[0].performer, None);
+    }
+}
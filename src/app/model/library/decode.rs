@@ -0,0 +1,462 @@
+use super::format::Format::{self, *};
+use super::{ape, ogg};
+use crate::DEBUG;
+use crate::error::{Result, anyhow, Unknown, MissingDecoder, UnsupportedFormat};
+use lazy_static::lazy_static;
+use rodio::Source;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufReader, ErrorKind, Read};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::sync::atomic::Ordering::Relaxed;
+use std::time::Duration;
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::Decoder as Codec;
+use symphonia::core::errors::{Error as SymphoniaError, SeekErrorKind};
+use symphonia::core::formats::{FormatReader, SeekMode, SeekTo};
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::probe::Hint;
+use symphonia::core::units::{Time, TimeBase};
+use log::trace;
+
+/// What ffmpeg is asked to convert to.
+const FFMPEG_RATE: u32 = 44100;
+const FFMPEG_CHANNELS: u16 = 2;
+
+lazy_static! {
+    /// Whether an `ffmpeg` binary can be run, checked once.
+    static ref FFMPEG: bool = matches!(
+        Command::new("ffmpeg")
+            .arg("-version")
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status(),
+        Ok(s) if s.success()
+    );
+}
+
+/// Whether `format` is only decoded by ffmpeg, symphonia has no decoder for it.
+#[inline]
+pub fn needs_ffmpeg(format: Format) -> bool {
+    matches!(format, OPUS | WAVPACK | APE)
+}
+
+/// Whether the file at `path` can be played here, the formats decoded by
+/// ffmpeg can't be without it.
+#[inline]
+pub fn playable(path: impl AsRef<Path>) -> bool {
+    *FFMPEG || Format::new(path).map_or(true, |f| !needs_ffmpeg(f))
+}
+
+/// Source that can jump to a position without decoding what lies before it.
+pub trait Seekable: Source {
+    fn seek(&mut self, pos: Duration) -> Result<()>;
+}
+
+/// An audio file being decoded by whichever backend handles its format:
+/// symphonia for FLAC, MP3, WAV, Vorbis, MP4, AAC and AIFF, and an ffmpeg
+/// process for Opus, WavPack and Monkey's Audio.
+pub enum Decoded {
+    Symphonia(Symphonia),
+    Ffmpeg(Ffmpeg),
+}
+
+impl Decoded {
+
+    #[inline]
+    pub fn new(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let format = Format::new(path)?;
+        match format {
+            FLAC | MP3 | WAV | OGG | M4A | AAC | AIFF => Symphonia::new(path).map(Decoded::Symphonia),
+            OPUS | WAVPACK | APE => {
+                // ffmpeg would start on a file it can't open all the same, and just end.
+                File::open(path)?;
+                if DEBUG.load(Relaxed) { trace!("Decode {:?} with ffmpeg.", path); }
+                let duration = match format {
+                    OPUS => ogg::read_duration(path).ok(),
+                    WAVPACK => ape::wavpack_duration(path).ok(),
+                    _ => ape::monkey_duration(path).ok(),
+                };
+                Ffmpeg::new(path, duration.map(Duration::from_secs)).map(Decoded::Ffmpeg)
+            }
+            Unsupported => Err(anyhow!(UnsupportedFormat(path.display().to_string()))),
+        }
+    }
+}
+
+impl fmt::Debug for Decoded {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Decoded::Symphonia(s) => s.fmt(f),
+            Decoded::Ffmpeg(s) => s.fmt(f),
+        }
+    }
+}
+
+impl Iterator for Decoded {
+    type Item = i16;
+
+    #[inline]
+    fn next(&mut self) -> Option<i16> {
+        match self {
+            Decoded::Symphonia(s) => s.next(),
+            Decoded::Ffmpeg(s) => s.next(),
+        }
+    }
+}
+
+impl Source for Decoded {
+    #[inline]
+    fn current_frame_len(&self) -> Option<usize> {
+        match self {
+            Decoded::Symphonia(s) => s.current_frame_len(),
+            Decoded::Ffmpeg(s) => s.current_frame_len(),
+        }
+    }
+
+    #[inline]
+    fn channels(&self) -> u16 {
+        match self {
+            Decoded::Symphonia(s) => s.channels(),
+            Decoded::Ffmpeg(s) => s.channels(),
+        }
+    }
+
+    #[inline]
+    fn sample_rate(&self) -> u32 {
+        match self {
+            Decoded::Symphonia(s) => s.sample_rate(),
+            Decoded::Ffmpeg(s) => s.sample_rate(),
+        }
+    }
+
+    #[inline]
+    fn total_duration(&self) -> Option<Duration> {
+        match self {
+            Decoded::Symphonia(s) => s.total_duration(),
+            Decoded::Ffmpeg(s) => s.total_duration(),
+        }
+    }
+}
+
+impl Seekable for Decoded {
+    #[inline]
+    fn seek(&mut self, pos: Duration) -> Result<()> {
+        match self {
+            Decoded::Symphonia(s) => s.seek(pos),
+            Decoded::Ffmpeg(s) => s.seek(pos),
+        }
+    }
+}
+
+/// Decodes the first audio track of a file with symphonia, a packet at a time.
+pub struct Symphonia {
+    reader: Box<dyn FormatReader>,
+    codec: Box<dyn Codec>,
+    track: u32,
+    time_base: Option<TimeBase>,
+    /// Timestamp a seek asked for, what the packets decode before it is dropped.
+    target: Option<u64>,
+    /// Set once a seek went past the end.
+    exhausted: bool,
+    samples: Vec<i16>,
+    cursor: usize,
+    channels: u16,
+    rate: u32,
+    duration: Option<Duration>,
+}
+
+impl Symphonia {
+
+    #[inline]
+    pub fn new(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let mut hint = Hint::new();
+        if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
+            hint.with_extension(ext);
+        }
+        let stream = MediaSourceStream::new(Box::new(File::open(path)?), Default::default());
+        let probed = symphonia::default::get_probe().format(&hint, stream, &Default::default(), &Default::default())?;
+        let reader = probed.format;
+        let track = reader.default_track().ok_or_else(|| anyhow!(Unknown))?.clone();
+        let params = &track.codec_params;
+        let codec = symphonia::default::get_codecs().make(params, &Default::default())?;
+        let duration = params
+            .n_frames
+            .zip(params.sample_rate)
+            .filter(|(_, rate)| *rate > 0)
+            .map(|(frames, rate)| Duration::from_secs_f64(frames as f64 / rate as f64));
+        let mut symphonia = Symphonia {
+            track: track.id,
+            time_base: params.time_base,
+            target: None,
+            exhausted: false,
+            reader,
+            codec,
+            samples: Vec::new(),
+            cursor: 0,
+            channels: params.channels.map_or(0, |c| c.count() as u16),
+            rate: params.sample_rate.unwrap_or(0),
+            duration,
+        };
+        // The first packet tells the layout for sure, AAC may not say it up front.
+        if !symphonia.refill() {
+            return Err(anyhow!(Unknown));
+        }
+        Ok(symphonia)
+    }
+
+    /// Jump to `pos`, the reader lands on a packet at or before it and the
+    /// samples up to `pos` are decoded and dropped.
+    #[inline]
+    pub fn seek(&mut self, pos: Duration) -> Result<()> {
+        let time = Time::new(pos.as_secs(), pos.subsec_nanos() as f64 / 1e9);
+        let to = SeekTo::Time { time, track_id: Some(self.track) };
+        self.samples.clear();
+        self.cursor = 0;
+        match self.reader.seek(SeekMode::Accurate, to) {
+            Ok(seeked) => {
+                self.codec.reset();
+                self.target = Some(seeked.required_ts);
+                self.exhausted = false;
+                self.refill();
+                Ok(())
+            }
+            Err(SymphoniaError::SeekError(SeekErrorKind::OutOfRange)) => {
+                self.exhausted = true;
+                Ok(())
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Decode the next packet of the track, a corrupt one is skipped.
+    #[inline]
+    fn refill(&mut self) -> bool {
+        if self.exhausted {
+            return false;
+        }
+        loop {
+            let packet = match self.reader.next_packet() {
+                Ok(packet) => packet,
+                Err(_) => return false,
+            };
+            if packet.track_id() != self.track {
+                continue;
+            }
+            match self.codec.decode(&packet) {
+                Ok(decoded) => {
+                    let spec = *decoded.spec();
+                    let mut buffer = SampleBuffer::<i16>::new(decoded.capacity() as u64, spec);
+                    buffer.copy_interleaved_ref(decoded);
+                    self.samples.clear();
+                    self.samples.extend_from_slice(buffer.samples());
+                    self.cursor = 0;
+                    self.channels = spec.channels.count() as u16;
+                    self.rate = spec.rate;
+                    if let Some(target) = self.target {
+                        if packet.ts() + packet.dur() <= target {
+                            continue;
+                        }
+                        let frames = self.frames(target.saturating_sub(packet.ts()));
+                        let skipped = (frames * self.channels as usize).min(self.samples.len());
+                        self.samples.drain(..skipped);
+                        self.target = None;
+                    }
+                    if !self.samples.is_empty() {
+                        return true;
+                    }
+                }
+                Err(SymphoniaError::DecodeError(_)) => continue,
+                Err(_) => return false,
+            }
+        }
+    }
+}
+
+impl Symphonia {
+
+    /// Frames lasting as long as `ts` ticks of the time base.
+    #[inline]
+    fn frames(&self, ts: u64) -> usize {
+        match self.time_base {
+            Some(time_base) => {
+                let time = time_base.calc_time(ts);
+                ((time.seconds as f64 + time.frac) * self.rate as f64).round() as usize
+            }
+            None => ts as usize,
+        }
+    }
+}
+
+impl fmt::Debug for Symphonia {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Symphonia")
+            .field("track", &self.track)
+            .field("channels", &self.channels)
+            .field("rate", &self.rate)
+            .field("duration", &self.duration)
+            .finish()
+    }
+}
+
+impl Iterator for Symphonia {
+    type Item = i16;
+
+    #[inline]
+    fn next(&mut self) -> Option<i16> {
+        if self.cursor == self.samples.len() && !self.refill() {
+            return None;
+        }
+        self.cursor += 1;
+        Some(self.samples[self.cursor - 1])
+    }
+}
+
+impl Source for Symphonia {
+    /// The layout may only change between packets.
+    #[inline]
+    fn current_frame_len(&self) -> Option<usize> {
+        Some(self.samples.len() - self.cursor)
+    }
+
+    #[inline]
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    #[inline]
+    fn sample_rate(&self) -> u32 {
+        self.rate
+    }
+
+    #[inline]
+    fn total_duration(&self) -> Option<Duration> {
+        self.duration
+    }
+}
+
+/// Decodes a file with an ffmpeg process into interleaved 16-bit stereo, the
+/// process is killed once dropped.
+#[derive(Debug)]
+pub struct Ffmpeg {
+    path: PathBuf,
+    child: Child,
+    stdout: BufReader<ChildStdout>,
+    duration: Option<Duration>,
+}
+
+impl Ffmpeg {
+
+    #[inline]
+    pub fn new(path: impl AsRef<Path>, duration: Option<Duration>) -> Result<Self> {
+        let path = path.as_ref();
+        let (child, stdout) = Self::spawn(path, Duration::from_secs(0))?;
+        Ok(Ffmpeg {
+            path: path.to_path_buf(),
+            child,
+            stdout,
+            duration,
+        })
+    }
+
+    /// Start ffmpeg over again from `pos`, it seeks in the file by itself.
+    #[inline]
+    pub fn seek(&mut self, pos: Duration) -> Result<()> {
+        let (child, stdout) = Self::spawn(&self.path, pos)?;
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        self.child = child;
+        self.stdout = stdout;
+        Ok(())
+    }
+
+    #[inline]
+    fn spawn(path: &Path, from: Duration) -> Result<(Child, BufReader<ChildStdout>)> {
+        let mut child = Command::new("ffmpeg")
+            .args(["-v", "error", "-nostdin", "-ss", &from.as_secs_f64().to_string(), "-i"])
+            .arg(path)
+            .args(["-f", "s16le", "-ac", &FFMPEG_CHANNELS.to_string(), "-ar", &FFMPEG_RATE.to_string(), "-"])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| match e.kind() {
+                ErrorKind::NotFound => anyhow!(MissingDecoder(path.display().to_string())),
+                _ => e.into(),
+            })?;
+        let stdout = child.stdout.take().ok_or_else(|| anyhow!(Unknown))?;
+        Ok((child, BufReader::new(stdout)))
+    }
+}
+
+impl Drop for Ffmpeg {
+    #[inline]
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+impl Iterator for Ffmpeg {
+    type Item = i16;
+
+    #[inline]
+    fn next(&mut self) -> Option<i16> {
+        let mut sample = [0; 2];
+        self.stdout.read_exact(&mut sample).ok()?;
+        Some(i16::from_le_bytes(sample))
+    }
+}
+
+impl Source for Ffmpeg {
+    #[inline]
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    #[inline]
+    fn channels(&self) -> u16 {
+        FFMPEG_CHANNELS
+    }
+
+    #[inline]
+    fn sample_rate(&self) -> u32 {
+        FFMPEG_RATE
+    }
+
+    #[inline]
+    fn total_duration(&self) -> Option<Duration> {
+        self.duration
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::app::model::library::fixture;
+
+    #[test]
+    fn test_seek() {
+        let path = fixture("info.wav");
+        let whole = Decoded::new(&path).unwrap().collect::<Vec<_>>();
+        let mut decoded = Decoded::new(&path).unwrap();
+        decoded.seek(Duration::from_millis(1500)).unwrap();
+        assert_eq!(decoded.collect::<Vec<_>>(), whole[1500..]);
+        // Going back works just as well.
+        let mut decoded = Decoded::new(&path).unwrap();
+        decoded.seek(Duration::from_millis(2500)).unwrap();
+        decoded.seek(Duration::from_millis(500)).unwrap();
+        assert_eq!(decoded.collect::<Vec<_>>(), whole[500..]);
+        // Past the end there is nothing left to play.
+        let mut decoded = Decoded::new(&path).unwrap();
+        decoded.seek(Duration::from_secs(5)).unwrap();
+        assert_eq!(decoded.next(), None);
+    }
+}
@@ -1,29 +1,130 @@
+use std::fs::File;
+use std::io::Read;
 use std::path::Path;
-use crate::error::{Result, anyhow, Unknown};
+use crate::error::Result;
 use Format::*;
 
-#[derive(Debug, Clone)]
+/// Bytes read from the start of a file to tell its format.
+const SNIFF_LEN: usize = 64;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
 #[allow(non_camel_case_types)]
 pub enum Format {
     FLAC,
     MP3,
     WAV,
     OGG,
+    OPUS,
+    /// AAC or ALAC in an MP4 container.
+    M4A,
+    /// Bare AAC in ADTS frames.
+    AAC,
+    AIFF,
+    WAVPACK,
+    APE,
     Unsupported,
 }
 
 impl Format {
+    /// Tell the format of `path` by its contents, by its extension when the
+    /// contents don't give it away.
     #[inline]
     pub fn new(path: impl AsRef<Path>) -> Result<Self> {
-        match path.as_ref().extension().map(|p| p.to_str()) {
-            Some(Some(ext)) => match ext {
-                "flac" => Ok(FLAC),
-                "mp3" => Ok(MP3),
-                "wav" => Ok(WAV),
-                "ogg" => Ok(OGG),
-                _ => Ok(Unsupported),
-            },
-            _ => Err(anyhow!(Unknown)),
+        let path = path.as_ref();
+        let ext = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(Self::from_extension)
+            .filter(|f| *f != Unsupported);
+        let mut head = Vec::with_capacity(SNIFF_LEN);
+        let sniffed = File::open(path)
+            .and_then(|f| f.take(SNIFF_LEN as u64).read_to_end(&mut head))
+            .ok()
+            .and_then(|_| Self::sniff(&head));
+        match (sniffed, ext) {
+            (Some(format), _) | (None, Some(format)) => Ok(format),
+            (None, None) if head.starts_with(b"ID3") => Ok(MP3),
+            (None, None) => Ok(Unsupported),
+        }
+    }
+
+    /// The format files ending with `ext` are expected to be in, whatever its case.
+    #[inline]
+    pub fn from_extension(ext: &str) -> Self {
+        match ext.to_ascii_lowercase().as_str() {
+            "flac" => FLAC,
+            "mp3" => MP3,
+            "wav" => WAV,
+            "ogg" => OGG,
+            "opus" => OPUS,
+            "m4a" | "alac" => M4A,
+            "aac" => AAC,
+            "aif" | "aiff" => AIFF,
+            "wv" => WAVPACK,
+            "ape" => APE,
+            _ => Unsupported,
+        }
+    }
+
+    /// The format given away by the magic bytes at the start of a file.
+    #[inline]
+    pub fn sniff(head: &[u8]) -> Option<Self> {
+        let at = |i: usize, magic: &[u8]| head.get(i..i + magic.len()) == Some(magic);
+        if at(0, b"fLaC") {
+            Some(FLAC)
+        } else if at(0, b"RIFF") && at(8, b"WAVE") {
+            Some(WAV)
+        } else if at(0, b"FORM") && (at(8, b"AIFF") || at(8, b"AIFC")) {
+            Some(AIFF)
+        } else if at(0, b"OggS") {
+            // The first packet starts right after the segment table.
+            let body = 27 + *head.get(26)? as usize;
+            if at(body, b"OpusHead") { Some(OPUS) } else { Some(OGG) }
+        } else if at(4, b"ftyp") {
+            Some(M4A)
+        } else if at(0, b"wvpk") {
+            Some(WAVPACK)
+        } else if at(0, b"MAC ") {
+            Some(APE)
+        } else if at(0, b"ID3") {
+            // An ID3 tag is mostly found in front of MP3, the extension tells better.
+            None
+        } else if head.len() >= 2 && head[0] == 0xFF && head[1] & 0xF0 == 0xF0 && head[1] & 0x06 == 0 {
+            // Frame sync of ADTS, whose layer is always 0.
+            Some(AAC)
+        } else if head.len() >= 2 && head[0] == 0xFF && head[1] & 0xE0 == 0xE0 && head[1] & 0x06 != 0 {
+            Some(MP3)
+        } else {
+            None
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(Format::from_extension("MP3"), MP3);
+        assert_eq!(Format::from_extension("Flac"), FLAC);
+        assert_eq!(Format::from_extension("AIF"), AIFF);
+        assert_eq!(Format::from_extension("jpg"), Unsupported);
+
+        assert_eq!(Format::sniff(b"fLaC\0\0\0\x22"), Some(FLAC));
+        assert_eq!(Format::sniff(b"\0\0\0\x20ftypM4A "), Some(M4A));
+        assert_eq!(Format::sniff(b"FORM\0\0\0\0AIFC"), Some(AIFF));
+        assert_eq!(Format::sniff(&[0xFF, 0xFB, 0x90, 0x64]), Some(MP3));
+        assert_eq!(Format::sniff(&[0xFF, 0xF1, 0x50, 0x80]), Some(AAC));
+        assert_eq!(Format::sniff(b"ID3\x04\0"), None);
+        assert_eq!(Format::sniff(b"not audio"), None);
+
+        let mut ogg = b"OggS".to_vec();
+        ogg.resize(26, 0);
+        ogg.extend_from_slice(b"\x01\x13OpusHead");
+        assert_eq!(Format::sniff(&ogg), Some(OPUS));
+        ogg[28..].copy_from_slice(b"\x01vorbis\0");
+        assert_eq!(Format::sniff(&ogg), Some(OGG));
+    }
+}
@@ -0,0 +1,346 @@
+use super::decode::Decoded;
+use crate::error::{Result, anyhow, Unknown};
+use rodio::Source;
+use serde::{Deserialize, Serialize};
+use std::f64::consts::PI;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering::Relaxed};
+use std::time::SystemTime;
+
+/// Loudness the ReplayGain 2.0 gain is computed against.
+pub const REFERENCE_LOUDNESS: f64 = -18.0;
+
+const ABSOLUTE_GATE: f64 = -70.0;
+const RELATIVE_GATE: f64 = -10.0;
+
+/// Result of the EBU R128 analysis of a file.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Loudness {
+    /// Integrated loudness in LUFS.
+    pub integrated: f64,
+    /// True peak, linear.
+    pub true_peak: f64,
+    /// Modification time and size of the file the analysis was done on.
+    pub stamp: (SystemTime, u64),
+}
+
+impl Loudness {
+
+    /// Gain in dB that brings the file to the reference loudness.
+    #[inline]
+    pub fn gain(&self) -> f64 {
+        REFERENCE_LOUDNESS - self.integrated
+    }
+
+    /// Whether the analysis still describes the file at `path`.
+    #[inline]
+    pub fn is_fresh(&self, path: impl AsRef<Path>) -> bool {
+        stamp(path).map(|s| s == self.stamp).unwrap_or(false)
+    }
+}
+
+/// Modification time and size of the file at `path`.
+#[inline]
+pub fn stamp(path: impl AsRef<Path>) -> Result<(SystemTime, u64)> {
+    let metadata = fs::metadata(path)?;
+    Ok((metadata.modified()?, metadata.len()))
+}
+
+/// Decode the whole file and measure it, returns `None` if `cancel` was set meanwhile.
+#[inline]
+pub fn analyze(path: impl AsRef<Path>, cancel: &AtomicBool) -> Result<Option<Loudness>> {
+    let stamp = stamp(&path)?;
+    let source = Decoded::new(&path)?;
+    let mut meter = Meter::new(source.channels() as usize, source.sample_rate());
+    for (i, sample) in source.enumerate() {
+        if i % 65536 == 0 && cancel.load(Relaxed) {
+            return Ok(None);
+        }
+        meter.push(sample as f64 / 32768.0);
+    }
+    let integrated = meter.integrated().ok_or(anyhow!(Unknown))?;
+
+    Ok(Some(Loudness {
+        integrated,
+        true_peak: meter.true_peak(),
+        stamp,
+    }))
+}
+
+/// Second order IIR filter in direct form II.
+#[derive(Debug, Clone, Default)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 3],
+    z: [f64; 2],
+}
+
+impl Biquad {
+
+    #[inline]
+    fn process(&mut self, x: f64) -> f64 {
+        let w = x - self.a[1] * self.z[0] - self.a[2] * self.z[1];
+        let y = self.b[0] * w + self.b[1] * self.z[0] + self.b[2] * self.z[1];
+        self.z[1] = self.z[0];
+        self.z[0] = w;
+        y
+    }
+}
+
+/// K-weighting filters of ITU-R BS.1770 for `rate`.
+#[inline]
+fn k_weighting(rate: f64) -> (Biquad, Biquad) {
+    // High shelf modelling the acoustic effects of the head.
+    let f0 = 1681.974450955533;
+    let g = 3.999843853973347;
+    let q = 0.7071752369554196;
+    let k = (PI * f0 / rate).tan();
+    let vh = 10f64.powf(g / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad {
+        b: [
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        a: [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        z: [0.0; 2],
+    };
+
+    // High pass, the RLB weighting curve.
+    let f0 = 38.13547087602444;
+    let q = 0.5003270373238773;
+    let k = (PI * f0 / rate).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad {
+        b: [1.0, -2.0, 1.0],
+        a: [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        z: [0.0; 2],
+    };
+
+    (shelf, high_pass)
+}
+
+/// Weight of each channel when summing their power, for the usual
+/// L, R, C, LFE, Ls, Rs order.
+#[inline]
+fn channel_weight(channel: usize, channels: usize) -> f64 {
+    match (channels, channel) {
+        (6, 3) => 0.0,
+        (6, 4) | (6, 5) | (5, 3) | (5, 4) => 1.41,
+        _ => 1.0,
+    }
+}
+
+/// Polyphase windowed-sinc interpolator used to find inter-sample peaks.
+#[derive(Debug)]
+struct Oversampler {
+    factor: usize,
+    /// `phases[p][t]` is the tap `t` of the phase `p`.
+    phases: Vec<Vec<f64>>,
+    history: Vec<f64>,
+    cursor: usize,
+}
+
+impl Oversampler {
+
+    const TAPS_PER_PHASE: usize = 12;
+
+    #[inline]
+    fn new(factor: usize) -> Self {
+        let len = factor * Self::TAPS_PER_PHASE;
+        let center = (len - 1) as f64 / 2.0;
+        let mut phases = vec![vec![0.0; Self::TAPS_PER_PHASE]; factor];
+        for n in 0..len {
+            let x = (n as f64 - center) / factor as f64;
+            let sinc = if x == 0.0 { 1.0 } else { (PI * x).sin() / (PI * x) };
+            let window = 0.5 - 0.5 * (2.0 * PI * n as f64 / (len - 1) as f64).cos();
+            phases[n % factor][n / factor] = sinc * window;
+        }
+        Oversampler {
+            factor,
+            phases,
+            history: vec![0.0; Self::TAPS_PER_PHASE],
+            cursor: 0,
+        }
+    }
+
+    /// Feed one sample and return the highest absolute value of the interpolated ones.
+    #[inline]
+    fn peak(&mut self, x: f64) -> f64 {
+        if self.factor == 1 {
+            return x.abs();
+        }
+        self.history[self.cursor] = x;
+        self.cursor = (self.cursor + 1) % Self::TAPS_PER_PHASE;
+        let mut peak = 0f64;
+        for phase in &self.phases {
+            let mut y = 0.0;
+            for (t, tap) in phase.iter().enumerate() {
+                let idx = (self.cursor + Self::TAPS_PER_PHASE - 1 - t) % Self::TAPS_PER_PHASE;
+                y += tap * self.history[idx];
+            }
+            peak = peak.max(y.abs());
+        }
+        peak
+    }
+}
+
+/// Loudness meter following EBU R128 / ITU-R BS.1770.
+#[derive(Debug)]
+pub struct Meter {
+    channels: usize,
+    filters: Vec<(Biquad, Biquad)>,
+    oversamplers: Vec<Oversampler>,
+    weights: Vec<f64>,
+    /// Samples per channel in a 100 ms step.
+    step: usize,
+    /// Weighted power of the current step, per channel.
+    power: Vec<f64>,
+    /// Samples per channel accumulated in the current step.
+    filled: usize,
+    /// Channel of the next interleaved sample.
+    channel: usize,
+    /// Mean power of each finished step, summed over the channels.
+    steps: Vec<f64>,
+    /// Mean power of each 400 ms block.
+    blocks: Vec<f64>,
+    peak: f64,
+}
+
+impl Meter {
+
+    #[inline]
+    pub fn new(channels: usize, rate: u32) -> Self {
+        let channels = channels.max(1);
+        let factor = match rate {
+            0..=95_999 => 4,
+            96_000..=191_999 => 2,
+            _ => 1,
+        };
+        Meter {
+            channels,
+            filters: (0..channels).map(|_| k_weighting(rate as f64)).collect(),
+            oversamplers: (0..channels).map(|_| Oversampler::new(factor)).collect(),
+            weights: (0..channels).map(|c| channel_weight(c, channels)).collect(),
+            step: (rate as usize / 10).max(1),
+            power: vec![0.0; channels],
+            filled: 0,
+            channel: 0,
+            steps: Vec::new(),
+            blocks: Vec::new(),
+            peak: 0.0,
+        }
+    }
+
+    /// Feed one interleaved sample in the range of [-1.0, 1.0].
+    #[inline]
+    pub fn push(&mut self, sample: f64) {
+        let c = self.channel;
+        self.peak = self.peak.max(self.oversamplers[c].peak(sample));
+        let (shelf, high_pass) = &mut self.filters[c];
+        let y = high_pass.process(shelf.process(sample));
+        self.power[c] += y * y;
+
+        self.channel += 1;
+        if self.channel == self.channels {
+            self.channel = 0;
+            self.filled += 1;
+            if self.filled == self.step {
+                self.finish_step();
+            }
+        }
+    }
+
+    #[inline]
+    fn finish_step(&mut self) {
+        let step = self.step as f64;
+        let power = self
+            .power
+            .iter()
+            .zip(&self.weights)
+            .map(|(p, w)| p * w / step)
+            .sum();
+        self.steps.push(power);
+        self.power.iter_mut().for_each(|p| *p = 0.0);
+        self.filled = 0;
+
+        // Blocks are 400 ms long and overlap by 75%.
+        if self.steps.len() >= 4 {
+            let block = self.steps[self.steps.len() - 4..].iter().sum::<f64>() / 4.0;
+            self.blocks.push(block);
+        }
+    }
+
+    /// Integrated loudness in LUFS, `None` if everything was below the absolute gate.
+    #[inline]
+    pub fn integrated(&self) -> Option<f64> {
+        let absolute = energy(ABSOLUTE_GATE);
+        let gated = |threshold: f64| {
+            let blocks = self.blocks.iter().filter(|&&b| b > threshold);
+            let (sum, count) = blocks.fold((0.0, 0usize), |(s, c), b| (s + b, c + 1));
+            if count == 0 { None } else { Some(sum / count as f64) }
+        };
+        let relative = energy(loudness(gated(absolute)?) + RELATIVE_GATE);
+        gated(absolute.max(relative)).map(loudness)
+    }
+
+    /// Highest true peak across the channels, linear.
+    #[inline]
+    pub fn true_peak(&self) -> f64 {
+        self.peak
+    }
+}
+
+#[inline(always)]
+fn loudness(energy: f64) -> f64 {
+    -0.691 + 10.0 * energy.log10()
+}
+
+#[inline(always)]
+fn energy(loudness: f64) -> f64 {
+    10f64.powf((loudness + 0.691) / 10.0)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn sine(meter: &mut Meter, channels: usize, rate: u32, amplitude: f64, secs: usize) {
+        for n in 0..rate as usize * secs {
+            let x = amplitude * (2.0 * PI * 1000.0 * n as f64 / rate as f64).sin();
+            for _ in 0..channels {
+                meter.push(x);
+            }
+        }
+    }
+
+    #[test]
+    fn test_integrated_loudness() {
+        // EBU Tech 3341, case 1: a stereo 1 kHz sine at -23 dBFS measures -23 LUFS.
+        let mut meter = Meter::new(2, 48000);
+        sine(&mut meter, 2, 48000, 10f64.powf(-23.0 / 20.0), 5);
+        assert!((meter.integrated().unwrap() + 23.0).abs() < 0.1);
+
+        let mut meter = Meter::new(2, 44100);
+        sine(&mut meter, 2, 44100, 10f64.powf(-33.0 / 20.0), 5);
+        assert!((meter.integrated().unwrap() + 33.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn test_silence_is_gated() {
+        let mut meter = Meter::new(2, 48000);
+        sine(&mut meter, 2, 48000, 0.0, 2);
+        assert_eq!(meter.integrated(), None);
+    }
+
+    #[test]
+    fn test_true_peak() {
+        let mut meter = Meter::new(1, 48000);
+        sine(&mut meter, 1, 48000, 0.5, 1);
+        assert!((meter.true_peak() - 0.5).abs() < 0.01);
+    }
+}
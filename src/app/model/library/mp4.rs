@@ -0,0 +1,193 @@
+use crate::error::{Result, anyhow, Unknown};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// Sample rates of AAC, by the index in an ADTS header.
+const ADTS_RATES: [u64; 13] = [96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350];
+
+/// What the atoms of an MP4 file tell about it.
+#[derive(Debug, Default)]
+pub struct Mp4 {
+    /// Length in seconds, from the movie header.
+    pub duration: Option<u64>,
+    /// The iTunes-style tags, named like Vorbis comments. Freeform tags
+    /// like ReplayGain keep their own name.
+    pub fields: HashMap<String, Vec<String>>,
+}
+
+/// Read the movie header and the tags of the MP4 file at `path`.
+#[inline]
+pub fn read(path: impl AsRef<Path>) -> Result<Mp4> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let len = reader.get_ref().metadata()?.len();
+    // Only the movie atom is loaded, the media data can be anywhere around it.
+    let mut pos = 0;
+    while pos + 8 <= len {
+        let mut header = [0; 8];
+        reader.read_exact(&mut header)?;
+        let mut size = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as u64;
+        let mut header_len = 8;
+        if size == 1 {
+            let mut large = [0; 8];
+            reader.read_exact(&mut large)?;
+            size = u64::from_be_bytes(large);
+            header_len = 16;
+        } else if size == 0 {
+            size = len - pos;
+        }
+        if size < header_len {
+            break;
+        }
+        if &header[4..] == b"moov" {
+            let mut moov = vec![0; (size - header_len) as usize];
+            reader.read_exact(&mut moov)?;
+            let mut mp4 = Mp4::default();
+            parse_moov(&moov, &mut mp4);
+            return Ok(mp4);
+        }
+        pos += size;
+        reader.seek(SeekFrom::Start(pos))?;
+    }
+    Err(anyhow!(Unknown))
+}
+
+/// The child atoms in `body`, as their type and body.
+#[inline]
+fn atoms(mut body: &[u8]) -> impl Iterator<Item = (&[u8], &[u8])> {
+    std::iter::from_fn(move || {
+        if body.len() < 8 {
+            return None;
+        }
+        let size = u32::from_be_bytes([body[0], body[1], body[2], body[3]]) as usize;
+        let size = if size == 0 { body.len() } else { size };
+        if size < 8 || size > body.len() {
+            return None;
+        }
+        let atom = (&body[4..8], &body[8..size]);
+        body = &body[size..];
+        Some(atom)
+    })
+}
+
+#[inline]
+fn parse_moov(moov: &[u8], mp4: &mut Mp4) {
+    for (kind, body) in atoms(moov) {
+        match kind {
+            b"mvhd" => mp4.duration = movie_duration(body),
+            b"udta" => {
+                for (kind, body) in atoms(body) {
+                    if kind == b"meta" {
+                        // A full atom, except in some QuickTime files.
+                        let children = if body.get(4..8) == Some(&b"hdlr"[..]) { body } else { body.get(4..).unwrap_or_default() };
+                        for (kind, body) in atoms(children) {
+                            if kind == b"ilst" {
+                                parse_ilst(body, &mut mp4.fields);
+                            }
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[inline]
+fn movie_duration(mvhd: &[u8]) -> Option<u64> {
+    let u32_at = |i: usize| mvhd.get(i..i + 4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as u64);
+    let (timescale, duration) = match mvhd.first()? {
+        0 => (u32_at(12)?, u32_at(16)?),
+        _ => (u32_at(20)?, (u32_at(24)? << 32) | u32_at(28)?),
+    };
+    duration.checked_div(timescale)
+}
+
+#[inline]
+fn parse_ilst(ilst: &[u8], fields: &mut HashMap<String, Vec<String>>) {
+    for (kind, body) in atoms(ilst) {
+        let name = match kind {
+            b"\xa9nam" => "TITLE".to_owned(),
+            b"\xa9ART" => "ARTIST".to_owned(),
+            b"\xa9alb" => "ALBUM".to_owned(),
+            b"aART" => "ALBUMARTIST".to_owned(),
+            b"\xa9gen" => "GENRE".to_owned(),
+            b"\xa9day" => "DATE".to_owned(),
+            // Freeform, named by their `name` atom.
+            b"----" => match atoms(body).find(|(k, _)| *k == b"name") {
+                Some((_, name)) => String::from_utf8_lossy(name.get(4..).unwrap_or_default()).to_uppercase(),
+                None => continue,
+            },
+            _ => continue,
+        };
+        let values = atoms(body)
+            .filter(|(kind, _)| *kind == b"data")
+            // Type 1 is UTF-8 text, after the type and the locale.
+            .filter(|(_, data)| data.get(..4) == Some(&[0, 0, 0, 1][..]))
+            .filter_map(|(_, data)| data.get(8..))
+            .map(|text| String::from_utf8_lossy(text).into_owned())
+            .collect::<Vec<_>>();
+        if !values.is_empty() {
+            fields.entry(name).or_default().extend(values);
+        }
+    }
+}
+
+/// Length in seconds of a bare AAC file, counting the samples of its ADTS frames.
+#[inline]
+pub fn adts_duration(path: impl AsRef<Path>) -> Result<u64> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut header = [0; 10];
+    reader.read_exact(&mut header)?;
+    // Skip an ID3 tag in front of the frames.
+    let start = if &header[..3] == b"ID3" {
+        let size = header[6..10].iter().fold(0u64, |size, &b| size << 7 | (b & 0x7F) as u64);
+        10 + size
+    } else {
+        0
+    };
+    reader.seek(SeekFrom::Start(start))?;
+
+    let mut samples = 0;
+    let mut rate = None;
+    let mut header = [0; 7];
+    while reader.read_exact(&mut header).is_ok() {
+        if header[0] != 0xFF || header[1] & 0xF6 != 0xF0 {
+            break;
+        }
+        rate = rate.or_else(|| ADTS_RATES.get((header[2] >> 2 & 0xF) as usize).copied());
+        let len = ((header[3] & 0x3) as i64) << 11 | (header[4] as i64) << 3 | (header[5] >> 5) as i64;
+        samples += 1024 * ((header[6] & 0x3) as u64 + 1);
+        if len < 7 {
+            break;
+        }
+        reader.seek(SeekFrom::Current(len - 7))?;
+    }
+    match rate {
+        Some(rate) if samples > 0 => Ok(samples / rate),
+        _ => Err(anyhow!(Unknown)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::app::model::library::fixture;
+
+    #[test]
+    fn test_read() {
+        let mp4 = read(fixture("tagged.m4a")).unwrap();
+        assert_eq!(mp4.duration, Some(3));
+        assert_eq!(mp4.fields["TITLE"], ["M4A Title"]);
+        assert_eq!(mp4.fields["ARTIST"], ["M4A Artist"]);
+        assert_eq!(mp4.fields["ALBUM"], ["M4A Album"]);
+        assert_eq!(mp4.fields["REPLAYGAIN_TRACK_GAIN"], ["-4.20 dB"]);
+    }
+
+    #[test]
+    fn test_adts_duration() {
+        assert_eq!(adts_duration(fixture("frames.aac")).unwrap(), 2);
+    }
+}
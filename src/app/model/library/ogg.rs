@@ -0,0 +1,216 @@
+use crate::error::{Result, anyhow, Unknown};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+const CAPTURE_PATTERN: &[u8; 4] = b"OggS";
+
+const MAX_PAGE: usize = 27 + 255 + 255 * 255;
+
+/// Reassembles the packets of the first logical stream of an Ogg file.
+#[derive(Debug)]
+pub struct Packets<R> {
+    reader: R,
+    serial: Option<u32>,
+    segments: Vec<u8>,
+    cursor: usize,
+    body: Vec<u8>,
+    offset: usize,
+}
+
+impl<R: Read> Packets<R> {
+
+    #[inline]
+    pub fn new(reader: R) -> Self {
+        Packets {
+            reader,
+            serial: None,
+            segments: Vec::new(),
+            cursor: 0,
+            body: Vec::new(),
+            offset: 0,
+        }
+    }
+
+    /// Read the next page of the stream, pages of other logical streams are skipped.
+    #[inline]
+    fn next_page(&mut self) -> Result<bool> {
+        loop {
+            let mut header = [0; 27];
+            match self.reader.read_exact(&mut header) {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(false),
+                Err(e) => return Err(e.into()),
+            }
+            if &header[..4] != CAPTURE_PATTERN {
+                return Err(anyhow!(Unknown));
+            }
+            let serial = u32::from_le_bytes([header[14], header[15], header[16], header[17]]);
+            let mut segments = vec![0; header[26] as usize];
+            self.reader.read_exact(&mut segments)?;
+            let mut body = vec![0; segments.iter().map(|&s| s as usize).sum()];
+            self.reader.read_exact(&mut body)?;
+
+            if *self.serial.get_or_insert(serial) == serial {
+                self.segments = segments;
+                self.cursor = 0;
+                self.body = body;
+                self.offset = 0;
+                return Ok(true);
+            }
+        }
+    }
+}
+
+impl<R: Read> Iterator for Packets<R> {
+    type Item = Vec<u8>;
+
+    #[inline]
+    fn next(&mut self) -> Option<Vec<u8>> {
+        let mut packet = Vec::new();
+        loop {
+            while self.cursor < self.segments.len() {
+                let len = self.segments[self.cursor] as usize;
+                packet.extend_from_slice(&self.body[self.offset..self.offset + len]);
+                self.cursor += 1;
+                self.offset += len;
+                if len < 255 {
+                    return Some(packet);
+                }
+            }
+            if !self.next_page().ok()? {
+                return None;
+            }
+        }
+    }
+}
+
+/// Read the Vorbis comments of an Ogg Vorbis or Ogg Opus file.
+///
+/// Field names are upper-cased since they are case-insensitive.
+#[inline]
+pub fn read_comments(path: impl AsRef<Path>) -> Result<HashMap<String, Vec<String>>> {
+    let packets = Packets::new(BufReader::new(File::open(path)?));
+    for packet in packets.take(3) {
+        if let Some(body) = packet
+            .strip_prefix(b"\x03vorbis")
+            .or_else(|| packet.strip_prefix(b"OpusTags"))
+        {
+            return parse_comments(body);
+        }
+    }
+    Err(anyhow!(Unknown))
+}
+
+/// Length in seconds of an Ogg Vorbis or Ogg Opus file, from the sample rate
+/// of its identification header and the granule position of its last page.
+#[inline]
+pub fn read_duration(path: impl AsRef<Path>) -> Result<u64> {
+    let mut packets = Packets::new(BufReader::new(File::open(&path)?));
+    let first = packets.next().ok_or_else(|| anyhow!(Unknown))?;
+    let (rate, pre_skip) = if let Some(id) = first.strip_prefix(b"\x01vorbis") {
+        let rate = Bytes(id.get(5..).unwrap_or_default()).u32()?;
+        (rate as u64, 0)
+    } else if let Some(head) = first.strip_prefix(b"OpusHead") {
+        // Opus granule positions always count at 48 kHz.
+        let pre_skip = head.get(2..4).map_or(0, |b| u16::from_le_bytes([b[0], b[1]]));
+        (48000, pre_skip as u64)
+    } else {
+        return Err(anyhow!(Unknown));
+    };
+    let serial = packets.serial.ok_or_else(|| anyhow!(Unknown))?;
+    if rate == 0 {
+        return Err(anyhow!(Unknown));
+    }
+
+    // A page is at most 27 + 255 + 255 * 255 bytes long.
+    let mut file = File::open(&path)?;
+    let len = file.metadata()?.len();
+    file.seek(SeekFrom::Start(len.saturating_sub(MAX_PAGE as u64)))?;
+    let mut tail = Vec::with_capacity(MAX_PAGE);
+    file.read_to_end(&mut tail)?;
+    let granule = (0..tail.len().saturating_sub(26))
+        .rev()
+        .filter(|&i| &tail[i..i + 4] == CAPTURE_PATTERN)
+        .filter(|&i| tail[i + 14..i + 18] == serial.to_le_bytes())
+        .map(|i| {
+            let mut granule = [0; 8];
+            granule.copy_from_slice(&tail[i + 6..i + 14]);
+            u64::from_le_bytes(granule)
+        })
+        // Pages where no packet ends have no granule position.
+        .find(|&g| g != u64::MAX)
+        .ok_or_else(|| anyhow!(Unknown))?;
+    Ok(granule.saturating_sub(pre_skip) / rate)
+}
+
+/// Parse the body of a comment header, right after its packet signature.
+#[inline]
+pub fn parse_comments(body: &[u8]) -> Result<HashMap<String, Vec<String>>> {
+    let mut body = Bytes(body);
+    let vendor = body.u32()? as usize;
+    body.take(vendor)?;
+    let count = body.u32()?;
+    let mut comments = HashMap::new();
+    for _ in 0..count {
+        let len = body.u32()? as usize;
+        let comment = String::from_utf8_lossy(body.take(len)?);
+        if let Some(eq) = comment.find('=') {
+            comments
+                .entry(comment[..eq].to_uppercase())
+                .or_insert_with(Vec::new)
+                .push(comment[eq + 1..].to_owned());
+        }
+    }
+    Ok(comments)
+}
+
+/// Little-endian reader over a byte slice.
+struct Bytes<'a>(&'a [u8]);
+
+impl<'a> Bytes<'a> {
+
+    #[inline]
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.0.len() < len {
+            return Err(anyhow!(Unknown));
+        }
+        let (head, tail) = self.0.split_at(len);
+        self.0 = tail;
+        Ok(head)
+    }
+
+    #[inline]
+    fn u32(&mut self) -> Result<u32> {
+        let b = self.take(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::app::model::library::fixture;
+
+    #[test]
+    fn test_vorbis() {
+        let path = fixture("vorbis.ogg");
+        let comments = read_comments(&path).unwrap();
+        assert_eq!(comments["TITLE"], ["Vorbis Title"]);
+        assert_eq!(comments["ARTIST"], ["Vorbis Artist"]);
+        assert_eq!(comments["ALBUM"], ["Vorbis Album"]);
+        assert_eq!(read_duration(&path).unwrap(), 3);
+    }
+
+    #[test]
+    fn test_opus() {
+        let path = fixture("opus.ogg");
+        let comments = read_comments(&path).unwrap();
+        assert_eq!(comments["TITLE"], ["Opus Title"]);
+        assert_eq!(comments["REPLAYGAIN_TRACK_GAIN"], ["-3.00 dB"]);
+        // The pre-skip isn't part of the song.
+        assert_eq!(read_duration(&path).unwrap(), 2);
+    }
+}
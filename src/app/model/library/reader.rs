@@ -0,0 +1,413 @@
+use super::format::Format::{self, *};
+use super::replaygain::ReplayGain;
+use super::song::Metadata;
+use super::{ape, mp4, ogg, wav};
+use crate::error::{Result, anyhow, Unknown, UnsupportedFormat};
+use crate::utils::get_duration;
+use id3::Tag as MP3Tag;
+use metaflac::Tag as FLACTag;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// A field of `Metadata` a reader may fail to read.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Field {
+    Title,
+    Artist,
+    Album,
+    Duration,
+}
+
+impl Field {
+
+    #[inline]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Field::Title => "title",
+            Field::Artist => "artist",
+            Field::Album => "album",
+            Field::Duration => "duration",
+        }
+    }
+}
+
+impl FromStr for Field {
+    type Err = anyhow::Error;
+
+    #[inline]
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "title" => Ok(Field::Title),
+            "artist" => Ok(Field::Artist),
+            "album" => Ok(Field::Album),
+            "duration" => Ok(Field::Duration),
+            _ => Err(anyhow!(Unknown)),
+        }
+    }
+}
+
+/// Reads the tags and the duration of the files of some container.
+///
+/// Crates embedding `ultra` can add their own with `App::register_reader`.
+pub trait MetadataReader: fmt::Debug + Send + Sync {
+    /// Lower-cased extensions of the files the library should pick up for this reader.
+    fn extensions(&self) -> &[&str];
+
+    /// Whether the file at `path` is for this reader, `format` is what the
+    /// built-in detection makes of it.
+    fn probe(&self, path: &Path, format: Format) -> bool;
+
+    /// The fields the reader couldn't find are listed in `Metadata::missing`.
+    fn read(&self, path: &Path) -> Result<Metadata>;
+}
+
+/// The readers the library asks in turn, the ones registered last come first
+/// so that they can take over the formats of the built-in ones.
+#[derive(Debug, Clone)]
+pub struct Readers(Vec<Arc<dyn MetadataReader>>);
+
+impl Default for Readers {
+    #[inline]
+    fn default() -> Self {
+        Readers(vec![
+            Arc::new(FlacReader),
+            Arc::new(Id3Reader),
+            Arc::new(RiffReader),
+            Arc::new(OggReader),
+            Arc::new(Mp4Reader),
+            Arc::new(AdtsReader),
+            Arc::new(WavPackReader),
+            Arc::new(MonkeyReader),
+        ])
+    }
+}
+
+impl Readers {
+
+    #[inline]
+    pub fn register(&mut self, reader: impl MetadataReader + 'static) {
+        self.0.insert(0, Arc::new(reader));
+    }
+
+    /// Extensions of every reader, without duplicates.
+    #[inline]
+    pub fn extensions(&self) -> Vec<&str> {
+        let mut extensions = Vec::new();
+        for ext in self.0.iter().flat_map(|r| r.extensions()) {
+            if !extensions.contains(ext) {
+                extensions.push(*ext);
+            }
+        }
+        extensions
+    }
+
+    /// Read `path` with the first reader that takes it.
+    #[inline]
+    pub fn read(&self, path: impl AsRef<Path>) -> Result<Metadata> {
+        let path = path.as_ref();
+        let format = Format::new(path)?;
+        self.0
+            .iter()
+            .find(|r| r.probe(path, format))
+            .ok_or_else(|| anyhow!(UnsupportedFormat(path.display().to_string())))?
+            .read(path)
+    }
+}
+
+/// Reads the Vorbis comments of FLAC files.
+#[derive(Debug)]
+pub struct FlacReader;
+
+impl MetadataReader for FlacReader {
+    #[inline]
+    fn extensions(&self) -> &[&str] {
+        &["flac"]
+    }
+
+    #[inline]
+    fn probe(&self, _: &Path, format: Format) -> bool {
+        format == FLAC
+    }
+
+    #[inline]
+    fn read(&self, path: &Path) -> Result<Metadata> {
+        // A file without a comment block is still a song, only its tags are missing.
+        let tag = FLACTag::read_from_path(path).ok();
+        let mut metadata = Metadata::default();
+        if let Some(vbscmt) = tag.as_ref().and_then(FLACTag::vorbis_comments) {
+            metadata.title = vbscmt.title().map(|v| v.join(" "));
+            metadata.artist = vbscmt.artist().map(|v| v.join(" "));
+            metadata.album = vbscmt.album().map(|v| v.join(" "));
+            metadata.replaygain = ReplayGain::from_fields(
+                vbscmt
+                    .comments
+                    .iter()
+                    .filter_map(|(k, v)| v.first().map(|v| (k.as_str(), v.as_str()))),
+            );
+        }
+        metadata.duration = get_duration(path).ok();
+        Ok(metadata.with_missing())
+    }
+}
+
+/// Reads the ID3 tag in front of MP3 files.
+#[derive(Debug)]
+pub struct Id3Reader;
+
+impl MetadataReader for Id3Reader {
+    #[inline]
+    fn extensions(&self) -> &[&str] {
+        &["mp3"]
+    }
+
+    #[inline]
+    fn probe(&self, _: &Path, format: Format) -> bool {
+        format == MP3
+    }
+
+    #[inline]
+    fn read(&self, path: &Path) -> Result<Metadata> {
+        // Plenty of MP3 files have no ID3 tag at all, they are songs all the same.
+        let (mut metadata, duration) = match MP3Tag::read_from_path(path) {
+            Ok(tag) => (from_id3(&tag), tag.duration().map(|t| t as u64)),
+            Err(_) => (Metadata::default(), None),
+        };
+        metadata.duration = duration.or_else(|| get_duration(path).ok());
+        Ok(metadata.with_missing())
+    }
+}
+
+/// Reads the chunks of WAVE and AIFF files.
+#[derive(Debug)]
+pub struct RiffReader;
+
+impl MetadataReader for RiffReader {
+    #[inline]
+    fn extensions(&self) -> &[&str] {
+        &["wav", "aif", "aiff"]
+    }
+
+    #[inline]
+    fn probe(&self, _: &Path, format: Format) -> bool {
+        matches!(format, WAV | AIFF)
+    }
+
+    #[inline]
+    fn read(&self, path: &Path) -> Result<Metadata> {
+        let riff = wav::read(path)?;
+        let mut metadata = riff.id3.as_ref().map(from_id3).unwrap_or_default();
+        metadata.title = riff.field(MP3Tag::title, "INAM");
+        metadata.artist = riff.field(MP3Tag::artist, "IART");
+        metadata.album = riff.field(MP3Tag::album, "IPRD");
+        metadata.duration = riff.duration;
+        Ok(metadata.with_missing())
+    }
+}
+
+/// Reads the Vorbis comments of Ogg Vorbis and Ogg Opus files.
+#[derive(Debug)]
+pub struct OggReader;
+
+impl MetadataReader for OggReader {
+    #[inline]
+    fn extensions(&self) -> &[&str] {
+        &["ogg", "opus"]
+    }
+
+    #[inline]
+    fn probe(&self, _: &Path, format: Format) -> bool {
+        matches!(format, OGG | OPUS)
+    }
+
+    #[inline]
+    fn read(&self, path: &Path) -> Result<Metadata> {
+        let mut metadata = ogg::read_comments(path).map(|c| from_fields(&c)).unwrap_or_default();
+        metadata.duration = ogg::read_duration(path).ok();
+        Ok(metadata.with_missing())
+    }
+}
+
+/// Reads the iTunes-style atoms of MP4 audio files.
+#[derive(Debug)]
+pub struct Mp4Reader;
+
+impl MetadataReader for Mp4Reader {
+    #[inline]
+    fn extensions(&self) -> &[&str] {
+        &["m4a", "alac"]
+    }
+
+    #[inline]
+    fn probe(&self, _: &Path, format: Format) -> bool {
+        format == M4A
+    }
+
+    #[inline]
+    fn read(&self, path: &Path) -> Result<Metadata> {
+        let mp4 = mp4::read(path)?;
+        let mut metadata = from_fields(&mp4.fields);
+        metadata.duration = mp4.duration;
+        Ok(metadata.with_missing())
+    }
+}
+
+/// Reads bare AAC files, which carry an ID3 tag in front or an APE tag at
+/// the end, if any.
+#[derive(Debug)]
+pub struct AdtsReader;
+
+impl MetadataReader for AdtsReader {
+    #[inline]
+    fn extensions(&self) -> &[&str] {
+        &["aac"]
+    }
+
+    #[inline]
+    fn probe(&self, _: &Path, format: Format) -> bool {
+        format == AAC
+    }
+
+    #[inline]
+    fn read(&self, path: &Path) -> Result<Metadata> {
+        let mut metadata = match MP3Tag::read_from_path(path) {
+            Ok(tag) => from_id3(&tag),
+            Err(_) => ape::read_tag(path).map(|t| from_fields(&t)).unwrap_or_default(),
+        };
+        metadata.duration = mp4::adts_duration(path).ok();
+        Ok(metadata.with_missing())
+    }
+}
+
+/// Reads the APEv2 tag of WavPack files.
+#[derive(Debug)]
+pub struct WavPackReader;
+
+impl MetadataReader for WavPackReader {
+    #[inline]
+    fn extensions(&self) -> &[&str] {
+        &["wv"]
+    }
+
+    #[inline]
+    fn probe(&self, _: &Path, format: Format) -> bool {
+        format == WAVPACK
+    }
+
+    #[inline]
+    fn read(&self, path: &Path) -> Result<Metadata> {
+        let mut metadata = ape::read_tag(path).map(|t| from_fields(&t)).unwrap_or_default();
+        metadata.duration = ape::wavpack_duration(path).ok();
+        Ok(metadata.with_missing())
+    }
+}
+
+/// Reads the APEv2 tag of Monkey's Audio files.
+#[derive(Debug)]
+pub struct MonkeyReader;
+
+impl MetadataReader for MonkeyReader {
+    #[inline]
+    fn extensions(&self) -> &[&str] {
+        &["ape"]
+    }
+
+    #[inline]
+    fn probe(&self, _: &Path, format: Format) -> bool {
+        format == APE
+    }
+
+    #[inline]
+    fn read(&self, path: &Path) -> Result<Metadata> {
+        let mut metadata = ape::read_tag(path).map(|t| from_fields(&t)).unwrap_or_default();
+        metadata.duration = ape::monkey_duration(path).ok();
+        Ok(metadata.with_missing())
+    }
+}
+
+/// The title, artist, album and ReplayGain of an ID3 tag.
+#[inline]
+fn from_id3(tag: &MP3Tag) -> Metadata {
+    Metadata {
+        title: tag.title().map(str::to_string),
+        artist: tag.artist().map(str::to_string),
+        album: tag.album().map(str::to_string),
+        replaygain: ReplayGain::from_fields(
+            tag.extended_texts()
+                .map(|t| (t.description.as_str(), t.value.as_str())),
+        ),
+        ..Default::default()
+    }
+}
+
+/// The title, artist, album and ReplayGain of tag fields named like Vorbis
+/// comments, upper-cased, with every value of a field.
+#[inline]
+fn from_fields(fields: &HashMap<String, Vec<String>>) -> Metadata {
+    let field = |name: &str| fields.get(name).map(|v| v.join(" "));
+    Metadata {
+        title: field("TITLE"),
+        artist: field("ARTIST"),
+        album: field("ALBUM"),
+        replaygain: ReplayGain::from_fields(
+            fields
+                .iter()
+                .filter_map(|(k, v)| v.first().map(|v| (k.as_str(), v.as_str()))),
+        ),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::app::model::library::fixture;
+
+    const MANIFEST: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml");
+
+    #[derive(Debug)]
+    struct TomlReader;
+
+    impl MetadataReader for TomlReader {
+        fn extensions(&self) -> &[&str] {
+            &["toml"]
+        }
+
+        fn probe(&self, path: &Path, _: Format) -> bool {
+            path.extension() == Some("toml".as_ref())
+        }
+
+        fn read(&self, _: &Path) -> Result<Metadata> {
+            let metadata = Metadata { title: Some("Manifest".to_owned()), ..Default::default() };
+            Ok(metadata.with_missing())
+        }
+    }
+
+    #[test]
+    fn test_registry() {
+        let mut readers = Readers::default();
+        let aiff = readers.read(fixture("tagged.aiff")).unwrap();
+        assert_eq!(aiff.title.as_deref(), Some("AIFF Title"));
+        assert_eq!(aiff.duration, Some(2));
+        assert_eq!(aiff.missing, [Field::Album]);
+        assert!(readers.read(MANIFEST).is_err());
+
+        readers.register(TomlReader);
+        let manifest = readers.read(MANIFEST).unwrap();
+        assert_eq!(manifest.title.as_deref(), Some("Manifest"));
+        assert_eq!(manifest.missing, [Field::Artist, Field::Album, Field::Duration]);
+        assert!(readers.extensions().contains(&"toml"));
+        assert_eq!(readers.extensions().iter().filter(|e| **e == "flac").count(), 1);
+    }
+
+    #[test]
+    fn test_untagged() {
+        let mp3 = Readers::default().read(fixture("untagged.mp3")).unwrap();
+        assert_eq!(mp3.duration, Some(1));
+        assert_eq!(mp3.missing, [Field::Title, Field::Artist, Field::Album]);
+    }
+}
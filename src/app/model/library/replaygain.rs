@@ -0,0 +1,133 @@
+use crate::error::{Result, anyhow, InvalidReplayGainMode};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+pub const REPLAYGAIN_MODES: [&str; 3] = ["off", "track", "album"];
+
+/// Which of the ReplayGain values the player applies.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum GainMode {
+    Off,
+    Track,
+    Album,
+}
+
+impl Default for GainMode {
+    #[inline]
+    fn default() -> Self {
+        GainMode::Off
+    }
+}
+
+impl FromStr for GainMode {
+    type Err = anyhow::Error;
+
+    #[inline]
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "off" => Ok(GainMode::Off),
+            "track" => Ok(GainMode::Track),
+            "album" => Ok(GainMode::Album),
+            _ => Err(anyhow!(InvalidReplayGainMode(s.into()))),
+        }
+    }
+}
+
+/// ReplayGain values of a song, gains are in dB and peaks are linear.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ReplayGain {
+    pub track_gain: Option<f32>,
+    pub track_peak: Option<f32>,
+    pub album_gain: Option<f32>,
+    pub album_peak: Option<f32>,
+}
+
+impl ReplayGain {
+
+    /// Collect the values from `REPLAYGAIN_*` fields, names are matched case-insensitively.
+    #[inline]
+    pub fn from_fields<'a>(fields: impl IntoIterator<Item = (&'a str, &'a str)>) -> Self {
+        let mut gain = ReplayGain::default();
+        for (name, value) in fields {
+            let slot = match name.to_uppercase().as_str() {
+                "REPLAYGAIN_TRACK_GAIN" => &mut gain.track_gain,
+                "REPLAYGAIN_TRACK_PEAK" => &mut gain.track_peak,
+                "REPLAYGAIN_ALBUM_GAIN" => &mut gain.album_gain,
+                "REPLAYGAIN_ALBUM_PEAK" => &mut gain.album_peak,
+                _ => continue,
+            };
+            *slot = parse_value(value);
+        }
+        gain
+    }
+
+    /// Linear factor to apply to the samples, lowered if needed so that the peak doesn't clip.
+    ///
+    /// Falls back to the other kind of gain when the requested one is missing.
+    #[inline]
+    pub fn factor(&self, mode: GainMode) -> f32 {
+        let (gain, peak) = match mode {
+            GainMode::Off => return 1.0,
+            GainMode::Track => (
+                self.track_gain.or(self.album_gain),
+                self.track_peak.or(self.album_peak),
+            ),
+            GainMode::Album => (
+                self.album_gain.or(self.track_gain),
+                self.album_peak.or(self.track_peak),
+            ),
+        };
+        let factor = gain.map(|g| 10f32.powf(g / 20.0)).unwrap_or(1.0);
+        match peak {
+            Some(peak) if peak > 0.0 && peak * factor > 1.0 => 1.0 / peak,
+            _ => factor,
+        }
+    }
+}
+
+/// Parse values like `-6.48 dB` or `0.988553`.
+#[inline]
+fn parse_value(value: &str) -> Option<f32> {
+    let value = value.trim();
+    let value = value
+        .strip_suffix("dB")
+        .or_else(|| value.strip_suffix("db"))
+        .or_else(|| value.strip_suffix("DB"))
+        .unwrap_or(value);
+    value.trim().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_from_fields() {
+        let gain = ReplayGain::from_fields(vec![
+            ("REPLAYGAIN_TRACK_GAIN", "-6.48 dB"),
+            ("replaygain_track_peak", "0.988553"),
+            ("REPLAYGAIN_ALBUM_GAIN", "+1.5dB"),
+            ("TITLE", "Whatever"),
+        ]);
+        assert_eq!(gain.track_gain, Some(-6.48));
+        assert_eq!(gain.track_peak, Some(0.988553));
+        assert_eq!(gain.album_gain, Some(1.5));
+        assert_eq!(gain.album_peak, None);
+    }
+
+    #[test]
+    fn test_factor() {
+        let gain = ReplayGain {
+            track_gain: Some(-6.0),
+            track_peak: Some(0.5),
+            album_gain: Some(6.0),
+            album_peak: Some(0.9),
+        };
+        assert_eq!(gain.factor(GainMode::Off), 1.0);
+        assert!((gain.factor(GainMode::Track) - 0.501).abs() < 0.001);
+        // +6 dB would push the 0.9 peak over full scale.
+        assert!((gain.factor(GainMode::Album) - 1.0 / 0.9).abs() < 0.001);
+        assert_eq!(ReplayGain::default().factor(GainMode::Album), 1.0);
+    }
+}
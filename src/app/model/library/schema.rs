@@ -0,0 +1,507 @@
+use super::loudness::Loudness;
+use super::replaygain::ReplayGain;
+use super::song::{Metadata, Song, Track};
+use super::sql::*;
+use crate::DEBUG;
+use crate::error::{Result, anyhow, InvalidUTF8Path};
+use rusqlite::{params, Connection, OptionalExtension, Row, NO_PARAMS};
+use serde::Deserialize;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering::Relaxed;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use log::trace;
+
+/// Forward migrations, the one at index `i` takes the database to version `i + 1`.
+const MIGRATIONS: [fn(&Connection) -> Result<()>; 4] = [normalize, add_inode, add_sheet, add_sessions];
+
+/// Bring the database up to the latest version, each migration runs in its own
+/// transaction. The libraries, sessions and bookmarks stored the old way are
+/// moved into the tables last, so that the rows are written the way the latest
+/// version does.
+#[inline]
+pub fn migrate(conn: &mut Connection) -> Result<()> {
+    conn.execute(BUILD_SCHEMA_VERSION, NO_PARAMS)?;
+    let version = conn.query_row(FETCH_SCHEMA_VERSION, NO_PARAMS, |row| row.get::<_, Option<i64>>(0))?;
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version.unwrap_or(0) as usize) {
+        if DEBUG.load(Relaxed) { trace!("Migrate the database to version {}.", i + 1); }
+        let tx = conn.transaction()?;
+        migration(&tx)?;
+        tx.execute(SET_SCHEMA_VERSION, params![i as i64 + 1])?;
+        tx.commit()?;
+    }
+    let tx = conn.transaction()?;
+    import_legacy(&tx)?;
+    tx.commit()?;
+    Ok(())
+}
+
+/// `Song` as it was stored before version 1.
+#[derive(Deserialize)]
+struct LegacySong {
+    pos: PathBuf,
+    f_name: OsString,
+    metadata: LegacyMetadata,
+}
+
+/// `Metadata` as it was stored before version 1.
+#[derive(Deserialize)]
+struct LegacyMetadata {
+    title: Option<String>,
+    artist: Option<String>,
+    album: Option<String>,
+    duration: Option<u64>,
+}
+
+/// Version 1, one row per song, album and artist instead of one blob per library.
+#[inline]
+fn normalize(conn: &Connection) -> Result<()> {
+    conn.execute_batch(BUILD_LIBRARY)?;
+    Ok(())
+}
+
+/// Version 2, the songs read before it have no inode and are read again on
+/// the next scan.
+#[inline]
+fn add_inode(conn: &Connection) -> Result<()> {
+    conn.execute_batch(ADD_INODE)?;
+    Ok(())
+}
+
+/// Version 3, the tracks read from a CUE sheet before it don't know their
+/// sheet, which is read again on the next scan.
+#[inline]
+fn add_sheet(conn: &Connection) -> Result<()> {
+    conn.execute_batch(ADD_SHEET)?;
+    Ok(())
+}
+
+/// Version 4, sessions and bookmarks get tables of their own, the rows of the
+/// tables they had before are moved by `import_legacy`.
+#[inline]
+fn add_sessions(conn: &Connection) -> Result<()> {
+    conn.execute_batch(BUILD_SESSIONS)?;
+    Ok(())
+}
+
+/// Move what older versions stored the old way into the tables, if there is
+/// still any.
+#[inline]
+fn import_legacy(conn: &Connection) -> Result<()> {
+    if has_table(conn, LEGACY_RECORD)? {
+        import_records(conn)?;
+    }
+    if has_table(conn, LEGACY_SESSION)? {
+        import_sessions(conn)?;
+    }
+    if has_table(conn, LEGACY_BOOKMARK)? {
+        import_bookmarks(conn)?;
+    }
+    Ok(())
+}
+
+#[inline]
+fn has_table(conn: &Connection, name: &str) -> Result<bool> {
+    Ok(conn.query_row(FIND_TABLE, params![name], |_| Ok(())).optional()?.is_some())
+}
+
+/// Split the blob of every library into rows of songs, albums and artists. A
+/// blob that can't be read anymore is dropped, the next scan rebuilds it.
+#[inline]
+fn import_records(conn: &Connection) -> Result<()> {
+
+    let records = conn
+        .prepare(FETCH_LEGACY_RECORDS)?
+        .query_map(NO_PARAMS, |row| {
+            Ok((row.get::<_, Vec<u8>>(0)?, row.get::<_, Vec<u8>>(1)?, row.get::<_, Vec<u8>>(2)?))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    for (pos, cache, modified) in records {
+        let record = bincode::deserialize::<PathBuf>(&pos).and_then(|pos| {
+            let cache = bincode::deserialize::<Vec<LegacySong>>(&cache)?;
+            let modified = bincode::deserialize::<Option<SystemTime>>(&modified)?;
+            Ok((pos, cache, modified))
+        });
+        let (pos, cache, modified) = match record {
+            Ok(record) => record,
+            Err(e) => {
+                if DEBUG.load(Relaxed) { trace!("Drop an unreadable record: {}", e); }
+                continue;
+            }
+        };
+        let library = save_library(conn, &pos, modified)?;
+        for legacy in cache {
+            let LegacyMetadata { title, artist, album, duration } = legacy.metadata;
+            let metadata = Metadata { title, artist, album, duration, ..Default::default() }.with_missing();
+            let song = Song::with_metadata(legacy.pos.join(legacy.f_name), metadata)?;
+            insert_song(conn, library, &song)?;
+        }
+    }
+    conn.execute(DROP_LEGACY_RECORDS, NO_PARAMS)?;
+    Ok(())
+}
+
+/// Key the sessions by the path of their library as text. A session whose
+/// path can't be read anymore is dropped.
+#[inline]
+fn import_sessions(conn: &Connection) -> Result<()> {
+    let sessions = conn
+        .prepare(FETCH_LEGACY_SESSIONS)?
+        .query_map(NO_PARAMS, |row| Ok((row.get::<_, Vec<u8>>(0)?, row.get::<_, Vec<u8>>(1)?)))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    for (pos, state) in sessions {
+        match bincode::deserialize::<PathBuf>(&pos) {
+            Ok(pos) => {
+                conn.execute(SAVE_SESSION, params![utf8(&pos)?, state])?;
+            }
+            Err(e) => {
+                if DEBUG.load(Relaxed) { trace!("Drop an unreadable session: {}", e); }
+            }
+        }
+    }
+    conn.execute(DROP_LEGACY_SESSIONS, NO_PARAMS)?;
+    Ok(())
+}
+
+/// Key the bookmarks by their path as text, with the position in nanoseconds.
+/// A bookmark that can't be read anymore is dropped.
+#[inline]
+fn import_bookmarks(conn: &Connection) -> Result<()> {
+    let bookmarks = conn
+        .prepare(FETCH_LEGACY_BOOKMARKS)?
+        .query_map(NO_PARAMS, |row| Ok((row.get::<_, Vec<u8>>(0)?, row.get::<_, Vec<u8>>(1)?)))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    for (path, pos) in bookmarks {
+        let bookmark = bincode::deserialize::<PathBuf>(&path)
+            .and_then(|path| Ok((path, bincode::deserialize::<Duration>(&pos)?)));
+        match bookmark {
+            Ok((path, pos)) => {
+                conn.execute(SAVE_BOOKMARK, params![utf8(&path)?, pos.as_nanos() as i64])?;
+            }
+            Err(e) => {
+                if DEBUG.load(Relaxed) { trace!("Drop an unreadable bookmark: {}", e); }
+            }
+        }
+    }
+    conn.execute(DROP_LEGACY_BOOKMARKS, NO_PARAMS)?;
+    Ok(())
+}
+
+/// Create or update the library at `pos`, returns its id.
+#[inline]
+pub fn save_library(conn: &Connection, pos: &Path, modified: Option<SystemTime>) -> Result<i64> {
+    let pos = utf8(pos)?;
+    conn.execute(SAVE_LIBRARY, params![pos, modified.map(nanos)])?;
+    Ok(conn.query_row(FETCH_LIBRARY, params![pos], |row| row.get(0))?)
+}
+
+/// Id and last modified time of the library at `pos`.
+#[inline]
+pub fn fetch_library(conn: &Connection, pos: &Path) -> Result<Option<(i64, Option<SystemTime>)>> {
+    Ok(conn
+        .query_row(FETCH_LIBRARY, params![utf8(pos)?], |row| {
+            Ok((row.get(0)?, row.get::<_, Option<i64>>(1)?.map(time)))
+        })
+        .optional()?)
+}
+
+#[inline]
+pub fn insert_song(conn: &Connection, library: i64, song: &Song) -> Result<()> {
+    let metadata = &song.metadata;
+    let artist = match metadata.artist.as_ref() {
+        Some(name) => Some(artist_id(conn, name)?),
+        None => None,
+    };
+    let album = match metadata.album.as_ref() {
+        Some(title) => Some(album_id(conn, title, artist)?),
+        None => None,
+    };
+    let track = song.track;
+    let (mtime, size) = stamp_columns(song.stamp);
+    let loudness = metadata.loudness;
+    let (analyzed_mtime, analyzed_size) = stamp_columns(loudness.map(|l| l.stamp));
+    let sheet = match song.sheet.as_ref() {
+        Some(sheet) => Some(utf8(sheet)?),
+        None => None,
+    };
+    let (sheet_mtime, sheet_size) = stamp_columns(song.sheet_stamp);
+    let gain = metadata.replaygain;
+    let missing = metadata.missing.iter().map(|f| f.as_str()).collect::<Vec<_>>().join(",");
+    conn.prepare_cached(INSERT_SONG)?.execute(params![
+        library,
+        utf8(&song.path())?,
+        track.map(|t| t.number),
+        track.map(|t| t.start.as_nanos() as i64),
+        track.and_then(|t| t.end).map(|e| e.as_nanos() as i64),
+        metadata.title,
+        artist,
+        album,
+        metadata.duration.map(|d| d as i64),
+        mtime,
+        size,
+        song.broken,
+        gain.track_gain.map(f64::from),
+        gain.track_peak.map(f64::from),
+        gain.album_gain.map(f64::from),
+        gain.album_peak.map(f64::from),
+        loudness.map(|l| l.integrated),
+        loudness.map(|l| l.true_peak),
+        analyzed_mtime,
+        analyzed_size,
+        missing,
+        song.inode.map(|i| i as i64),
+        sheet,
+        sheet_mtime,
+        sheet_size,
+    ])?;
+    Ok(())
+}
+
+/// A row of `FETCH_SONGS`.
+#[inline]
+pub fn read_song(row: &Row) -> rusqlite::Result<Song> {
+    let path = PathBuf::from(row.get::<_, String>(0)?);
+    let track = row.get::<_, Option<u32>>(1)?.map(|number| -> rusqlite::Result<Track> {
+        Ok(Track {
+            number,
+            start: Duration::from_nanos(row.get::<_, Option<i64>>(2)?.unwrap_or(0) as u64),
+            end: row.get::<_, Option<i64>>(3)?.map(|e| Duration::from_nanos(e as u64)),
+        })
+    });
+    let stamp = stamp_of(row.get(8)?, row.get(9)?);
+    let loudness = match (row.get::<_, Option<f64>>(15)?, row.get::<_, Option<f64>>(16)?) {
+        (Some(integrated), Some(true_peak)) => stamp_of(row.get(17)?, row.get(18)?).map(|stamp| Loudness {
+            integrated,
+            true_peak,
+            stamp,
+        }),
+        _ => None,
+    };
+    let gain = |i| row.get::<_, Option<f64>>(i).map(|g| g.map(|g| g as f32));
+    let metadata = Metadata {
+        title: row.get(4)?,
+        artist: row.get(5)?,
+        album: row.get(6)?,
+        duration: row.get::<_, Option<i64>>(7)?.map(|d| d as u64),
+        replaygain: ReplayGain {
+            track_gain: gain(11)?,
+            track_peak: gain(12)?,
+            album_gain: gain(13)?,
+            album_peak: gain(14)?,
+        },
+        loudness,
+        missing: row
+            .get::<_, String>(19)?
+            .split(',')
+            .filter_map(|f| f.parse().ok())
+            .collect(),
+    };
+    let mut song = Song::with_metadata(&path, metadata).map_err(|_| rusqlite::Error::InvalidPath(path))?;
+    song.track = track.transpose()?;
+    song.broken = row.get(10)?;
+    song.stamp = stamp;
+    song.inode = row.get::<_, Option<i64>>(20)?.map(|i| i as u64);
+    song.sheet = row.get::<_, Option<String>>(21)?.map(PathBuf::from);
+    song.sheet_stamp = stamp_of(row.get(22)?, row.get(23)?);
+    Ok(song)
+}
+
+#[inline]
+fn artist_id(conn: &Connection, name: &str) -> Result<i64> {
+    let found = conn.prepare_cached(FETCH_ARTIST)?.query_row(params![name], |row| row.get(0)).optional()?;
+    match found {
+        Some(id) => Ok(id),
+        None => {
+            conn.prepare_cached(INSERT_ARTIST)?.execute(params![name])?;
+            Ok(conn.last_insert_rowid())
+        }
+    }
+}
+
+/// Albums are told apart by their title and their artist.
+#[inline]
+fn album_id(conn: &Connection, title: &str, artist: Option<i64>) -> Result<i64> {
+    let found = conn.prepare_cached(FETCH_ALBUM)?.query_row(params![title, artist], |row| row.get(0)).optional()?;
+    match found {
+        Some(id) => Ok(id),
+        None => {
+            conn.prepare_cached(INSERT_ALBUM)?.execute(params![title, artist])?;
+            Ok(conn.last_insert_rowid())
+        }
+    }
+}
+
+#[inline]
+pub fn utf8(path: &Path) -> Result<&str> {
+    path.to_str().ok_or_else(|| anyhow!(InvalidUTF8Path))
+}
+
+/// Times are stored as nanoseconds since the epoch.
+#[inline]
+pub fn nanos(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos() as i64)
+}
+
+#[inline]
+fn time(nanos: i64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_nanos(nanos as u64)
+}
+
+#[inline]
+fn stamp_columns(stamp: Option<(SystemTime, u64)>) -> (Option<i64>, Option<i64>) {
+    match stamp {
+        Some((modified, size)) => (Some(nanos(modified)), Some(size as i64)),
+        None => (None, None),
+    }
+}
+
+#[inline]
+fn stamp_of(mtime: Option<i64>, size: Option<i64>) -> Option<(SystemTime, u64)> {
+    Some((time(mtime?), size? as u64))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use super::super::reader::Field;
+    use serde::Serialize;
+
+    /// `Song` as the baseline stored it.
+    #[derive(Serialize)]
+    struct Legacy<'a> {
+        pos: &'a Path,
+        f_name: OsString,
+        metadata: LegacyMeta<'a>,
+    }
+
+    #[derive(Serialize)]
+    struct LegacyMeta<'a> {
+        title: Option<&'a str>,
+        artist: Option<&'a str>,
+        album: Option<&'a str>,
+        duration: Option<u64>,
+    }
+
+    #[test]
+    fn test_migrate() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(r#"
+            CREATE TABLE record (
+                pos           BLOB NOT NULL UNIQUE,
+                cache         BLOB NOT NULL,
+                modified_time BLOB NOT NULL
+            )"#).unwrap();
+        let cache = vec![
+            Legacy {
+                pos: Path::new("/music"),
+                f_name: "album.flac".into(),
+                metadata: LegacyMeta { title: Some("Opening"), artist: Some("The Band"), album: Some("The Album"), duration: Some(241) },
+            },
+            Legacy {
+                pos: Path::new("/music"),
+                f_name: "single.mp3".into(),
+                metadata: LegacyMeta { title: None, artist: None, album: None, duration: None },
+            },
+        ];
+        let modified = Some(UNIX_EPOCH + Duration::from_nanos(1_600_000_000_123_456_789));
+        conn.execute(
+            "INSERT INTO record VALUES (?1, ?2, ?3)",
+            params![
+                bincode::serialize(Path::new("/music")).unwrap(),
+                bincode::serialize(&cache).unwrap(),
+                bincode::serialize(&modified).unwrap(),
+            ],
+        ).unwrap();
+        conn.execute("INSERT INTO record VALUES (x'00', x'00', x'00')", NO_PARAMS).unwrap();
+
+        migrate(&mut conn).unwrap();
+        // A second run has nothing left to do.
+        migrate(&mut conn).unwrap();
+        let version: i64 = conn.query_row(FETCH_SCHEMA_VERSION, NO_PARAMS, |row| row.get(0)).unwrap();
+        assert_eq!(version, MIGRATIONS.len() as i64);
+        assert!(conn.query_row(FIND_TABLE, params![LEGACY_RECORD], |_| Ok(())).optional().unwrap().is_none());
+
+        let (id, stored) = fetch_library(&conn, Path::new("/music")).unwrap().unwrap();
+        assert_eq!(stored, modified);
+        let songs = conn
+            .prepare(FETCH_SONGS).unwrap()
+            .query_map(params![id], read_song).unwrap()
+            .collect::<rusqlite::Result<Vec<_>>>().unwrap();
+        assert_eq!(songs.len(), 2);
+        assert_eq!(songs[0].path(), Path::new("/music/album.flac"));
+        assert_eq!(songs[0].metadata, Metadata {
+            title: Some("Opening".to_owned()),
+            artist: Some("The Band".to_owned()),
+            album: Some("The Album".to_owned()),
+            duration: Some(241),
+            ..Default::default()
+        });
+        assert_eq!(songs[0].track, None);
+        assert!(!songs[1].broken);
+        assert_eq!(songs[1].metadata.artist, None);
+        assert_eq!(songs[1].metadata.missing, vec![Field::Title, Field::Artist, Field::Album, Field::Duration]);
+
+        let albums: i64 = conn.query_row("SELECT COUNT(*) FROM albums WHERE title = 'The Album'", NO_PARAMS, |row| row.get(0)).unwrap();
+        assert_eq!(albums, 1);
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+        let library = save_library(&conn, Path::new("/music"), None).unwrap();
+        let mut song = Song::with_metadata("/music/single.mp3", Default::default()).unwrap();
+        song.stamp = Some((UNIX_EPOCH + Duration::from_secs(1_600_000_000), 4096));
+        song.inode = Some(1234);
+        insert_song(&conn, library, &song).unwrap();
+        let stored = conn.query_row(FETCH_SONGS, params![library], read_song).unwrap();
+        assert_eq!(stored, song);
+        // The file isn't there anymore.
+        assert!(!stored.is_current());
+
+        let mut track = song.slice(Track { number: 1, start: Duration::from_secs(0), end: None }, None, None, None);
+        track.sheet = Some(PathBuf::from("/music/album.cue"));
+        track.sheet_stamp = Some((UNIX_EPOCH + Duration::from_secs(1_600_000_001), 512));
+        conn.execute("DELETE FROM songs", NO_PARAMS).unwrap();
+        insert_song(&conn, library, &track).unwrap();
+        assert_eq!(conn.query_row(FETCH_SONGS, params![library], read_song).unwrap(), track);
+    }
+
+    #[test]
+    fn test_import_sessions() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(r#"
+            CREATE TABLE session (
+                pos   BLOB NOT NULL UNIQUE,
+                state BLOB NOT NULL
+            );
+            CREATE TABLE bookmark (
+                path     BLOB NOT NULL UNIQUE,
+                position BLOB NOT NULL
+            );"#).unwrap();
+        conn.execute(
+            "INSERT INTO session VALUES (?1, x'2a')",
+            params![bincode::serialize(Path::new("/music")).unwrap()],
+        ).unwrap();
+        conn.execute(
+            "INSERT INTO bookmark VALUES (?1, ?2)",
+            params![
+                bincode::serialize(Path::new("/music/book.mp3")).unwrap(),
+                bincode::serialize(&Duration::from_millis(90_500)).unwrap(),
+            ],
+        ).unwrap();
+        conn.execute("INSERT INTO bookmark VALUES (x'00', x'00')", NO_PARAMS).unwrap();
+
+        migrate(&mut conn).unwrap();
+        assert!(conn.query_row(FIND_TABLE, params![LEGACY_SESSION], |_| Ok(())).optional().unwrap().is_none());
+        assert!(conn.query_row(FIND_TABLE, params![LEGACY_BOOKMARK], |_| Ok(())).optional().unwrap().is_none());
+        let state: Vec<u8> = conn.query_row(FETCH_SESSION, params!["/music"], |row| row.get(0)).unwrap();
+        assert_eq!(state, vec![0x2a]);
+        let bookmarks = conn
+            .prepare(FETCH_ALL_BOOKMARKS).unwrap()
+            .query_map(NO_PARAMS, |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))).unwrap()
+            .collect::<rusqlite::Result<Vec<_>>>().unwrap();
+        assert_eq!(bookmarks, vec![("/music/book.mp3".to_owned(), 90_500_000_000)]);
+    }
+}
@@ -1,30 +1,72 @@
-use super::format::Format::{self, *};
+use super::loudness::{self, Loudness};
+use super::reader::{Field, Readers};
+use super::replaygain::{GainMode, ReplayGain};
 use crate::error::{Result, anyhow, Unknown};
-use crate::utils::{display_duration, get_duration};
-use id3::Tag as MP3Tag;
-use metaflac::Tag as FLACTag;
+use crate::utils::{display_duration, get_inode};
 use serde::{Deserialize, Serialize};
 use std::ffi::OsString;
 use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Put in front of the fields of a row the reader couldn't find.
+const MISSING_MARK: &str = "? ";
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Song {
     pos: PathBuf,
     f_name: OsString,
     pub metadata: Metadata,
+    /// The song couldn't be opened or decoded when it was played.
+    pub broken: bool,
+    /// Set for the tracks of a single-file album, which share their file.
+    pub track: Option<Track>,
+    /// Modification time and size of the file when it was read.
+    pub stamp: Option<(SystemTime, u64)>,
+    /// Inode of the file when it was read, `None` where there is no such thing.
+    pub inode: Option<u64>,
+    /// The CUE sheet the track was read from, `None` unless it has a file of its own.
+    pub sheet: Option<PathBuf>,
+    /// Modification time and size of the sheet when it was read.
+    pub sheet_stamp: Option<(SystemTime, u64)>,
 }
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// Where a track of a single-file album lies in its file.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Track {
+    pub number: u32,
+    pub start: Duration,
+    /// `None` for the last track, which lasts until the end of the file.
+    pub end: Option<Duration>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct Metadata {
     pub title: Option<String>,
     pub artist: Option<String>,
     pub album: Option<String>,
     pub duration: Option<u64>,
+    pub replaygain: ReplayGain,
+    pub loudness: Option<Loudness>,
+    /// Fields the reader couldn't find in the file, as opposed to a file no
+    /// reader supports, which doesn't make a song at all.
+    pub missing: Vec<Field>,
 }
 
 impl Song {
     #[inline]
-    pub fn new(path: impl AsRef<Path>) -> Result<Self> {
+    pub fn new(path: impl AsRef<Path>, readers: &Readers) -> Result<Self> {
+        // Taken first, so that a change made while reading shows up on the next scan.
+        let stamp = loudness::stamp(&path).ok();
+        let inode = get_inode(&path);
+        let mut song = Self::with_metadata(&path, readers.read(&path)?)?;
+        song.stamp = stamp;
+        song.inode = inode;
+        Ok(song)
+    }
+
+    /// The song at `path`, described by `metadata` read earlier.
+    #[inline]
+    pub fn with_metadata(path: impl AsRef<Path>, metadata: Metadata) -> Result<Self> {
         let mut pos = path.as_ref().to_path_buf();
         let f_name;
         if let Some(val) = pos.file_name() {
@@ -34,14 +76,64 @@ impl Song {
         }
         pos.pop();
 
-        let metadata = Metadata::new(path)?;
         Ok(Song {
             pos,
             f_name,
             metadata,
+            broken: false,
+            track: None,
+            stamp: None,
+            inode: None,
+            sheet: None,
+            sheet_stamp: None,
         })
     }
 
+    /// The file is as it was when the song was read from it, as far as its
+    /// modification time, size and inode tell, and so is its CUE sheet if it
+    /// has one.
+    #[inline]
+    pub fn is_current(&self) -> bool {
+        let path = self.path();
+        let sheet = match self.sheet.as_ref() {
+            Some(sheet) => self.sheet_stamp.is_some() && loudness::stamp(sheet).ok() == self.sheet_stamp,
+            None => true,
+        };
+        sheet && self.stamp.is_some() && loudness::stamp(&path).ok() == self.stamp && get_inode(&path) == self.inode
+    }
+
+    /// Track `track` of the single-file album `self` is the whole of.
+    #[inline]
+    pub fn slice(&self, track: Track, title: Option<String>, artist: Option<String>, album: Option<String>) -> Self {
+        let total = self.metadata.duration.map(Duration::from_secs);
+        let duration = match track.end {
+            Some(end) => end.checked_sub(track.start),
+            None => total.and_then(|t| t.checked_sub(track.start)),
+        };
+        let mut song = self.clone();
+        song.metadata.title = title.or(song.metadata.title);
+        song.metadata.artist = artist.or(song.metadata.artist);
+        song.metadata.album = album.or(song.metadata.album);
+        song.metadata.duration = duration.map(|d| d.as_secs());
+        song.metadata = song.metadata.with_missing();
+        song.track = Some(track);
+        song
+    }
+
+    /// Tells the songs apart, the path with the track number of a virtual
+    /// song appended.
+    #[inline]
+    pub fn id(&self) -> PathBuf {
+        match self.track {
+            Some(track) => {
+                let mut name = self.f_name.clone();
+                name.push(format!("#{:02}", track.number));
+                self.pos.join(name)
+            }
+            None => self.path(),
+        }
+    }
+
     #[inline(always)]
     pub fn path(&self) -> PathBuf {
         self.pos.join(&self.f_name)
@@ -49,71 +141,63 @@ impl Song {
 
     #[inline]
     pub fn row(&self) -> Vec<String> {
+        // Fields the reader couldn't find are marked, they show a stand-in.
+        let mark = |field, val: String| {
+            if self.metadata.missing.contains(&field) { format!("{}{}", MISSING_MARK, val) } else { val }
+        };
         vec![
-            self.metadata.title.clone().unwrap_or_else(|| {
+            mark(Field::Title, self.metadata.title.clone().unwrap_or_else(|| {
                 self.path()
                     .file_stem()
                     .map(|p| p.to_str().unwrap())
                     .unwrap_or("Unknown")
                     .to_owned()
-            }),
-            self.metadata
+            })),
+            mark(Field::Album, self.metadata
                 .album
                 .clone()
-                .unwrap_or_else(|| "Unknown".to_owned()),
-            self.metadata
+                .unwrap_or_else(|| "Unknown".to_owned())),
+            mark(Field::Artist, self.metadata
                 .artist
                 .clone()
-                .unwrap_or_else(|| "Unknown".to_owned()),
-            display_duration(self.metadata.duration),
+                .unwrap_or_else(|| "Unknown".to_owned())),
+            mark(Field::Duration, display_duration(self.metadata.duration)),
         ]
     }
 }
 
 impl Metadata {
+
+    /// List the fields left empty as missing.
     #[inline]
-    fn new(path: impl AsRef<Path>) -> Result<Self> {
-        let mut title = None;
-        let mut artist = None;
-        let mut album = None;
-        let mut duration = None;
-
-        match Format::new(&path)? {
-            FLAC => {
-                let tag = FLACTag::read_from_path(&path)?;
-                if let Some(vbscmt) = tag.vorbis_comments() {
-                    title = vbscmt.title().map(|v| v.join(" "));
-                    artist = vbscmt.artist().map(|v| v.join(" "));
-                    album = vbscmt.album().map(|v| v.join(" "));
-                    duration = get_duration(&path).ok();
-                }
-            }
-            MP3 => {
-                let tag = MP3Tag::read_from_path(&path)?;
-                title = tag.title().map(str::to_string);
-                artist = tag.artist().map(str::to_string);
-                album = tag.album().map(str::to_string);
-                duration = tag.duration().map(|t| t as u64);
-                if duration.is_none() {
-                    duration = get_duration(&path).ok();
-                }
-            }
-            WAV => {
-                // TODO
-            }
-            OGG => {
-                // TODO
-            }
-            Unsupported => {
-                // TODO
+    pub fn with_missing(mut self) -> Self {
+        self.missing = [
+            (Field::Title, self.title.is_none()),
+            (Field::Artist, self.artist.is_none()),
+            (Field::Album, self.album.is_none()),
+            (Field::Duration, self.duration.is_none()),
+        ]
+        .iter()
+        .filter(|(_, missing)| *missing)
+        .map(|(field, _)| *field)
+        .collect();
+        self
+    }
+
+    /// Linear gain to apply at playback, taken from the ReplayGain tags if
+    /// there are any and from the loudness analysis otherwise.
+    #[inline]
+    pub fn gain(&self, mode: GainMode) -> f32 {
+        let tagged = self.replaygain.track_gain.is_some() || self.replaygain.album_gain.is_some();
+        match self.loudness {
+            Some(loudness) if !tagged => ReplayGain {
+                track_gain: Some(loudness.gain() as f32),
+                track_peak: Some(loudness.true_peak as f32),
+                ..Default::default()
             }
+            .factor(mode),
+            _ => self.replaygain.factor(mode),
         }
-
-        Ok(Metadata {
-            title,
-            artist,
-            album,
-            duration,
-        })
     }
 }
+
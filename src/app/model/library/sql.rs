@@ -1,32 +1,284 @@
-pub const BUILD_RECORD: &str = r#"
-    CREATE TABLE IF NOT EXISTS record (
-        pos           BLOB NOT NULL UNIQUE,
-        cache         BLOB NOT NULL,
-        modified_time BLOB NOT NULL
+pub const ENABLE_FOREIGN_KEYS: &str = r#"
+    PRAGMA foreign_keys = ON
+"#;
+pub const BUILD_SCHEMA_VERSION: &str = r#"
+    CREATE TABLE IF NOT EXISTS schema_version (
+        version INTEGER NOT NULL
     )"#;
-pub const INSERT_RECORD: &str = r#"
-    INSERT INTO record
-        (pos, cache, modified_time)
+pub const FETCH_SCHEMA_VERSION: &str = r#"
+    SELECT
+        MAX(version)
+    FROM
+        schema_version
+"#;
+pub const SET_SCHEMA_VERSION: &str = r#"
+    INSERT INTO schema_version
+        (version)
     VALUES
-        (?1, ?2, ?3)
+        (?1)
     "#;
-pub const DELETE_RECORD: &str = r#"
-    DELETE FROM
-        record
+pub const FIND_TABLE: &str = r#"
+    SELECT
+        name
+    FROM
+        sqlite_master
     WHERE
-        pos = (?1)
+        type = 'table' AND name = (?1)
+    "#;
+/// Version 1, one row per song instead of one blob per library.
+pub const BUILD_LIBRARY: &str = r#"
+    CREATE TABLE libraries (
+        id       INTEGER PRIMARY KEY,
+        pos      TEXT NOT NULL UNIQUE,
+        modified INTEGER
+    );
+    CREATE TABLE artists (
+        id   INTEGER PRIMARY KEY,
+        name TEXT NOT NULL UNIQUE
+    );
+    CREATE TABLE albums (
+        id     INTEGER PRIMARY KEY,
+        title  TEXT NOT NULL,
+        artist INTEGER REFERENCES artists (id)
+    );
+    CREATE INDEX albums_title ON albums (title);
+    CREATE TABLE songs (
+        id             INTEGER PRIMARY KEY,
+        library        INTEGER NOT NULL REFERENCES libraries (id) ON DELETE CASCADE,
+        path           TEXT NOT NULL,
+        track          INTEGER,
+        track_start    INTEGER,
+        track_end      INTEGER,
+        title          TEXT,
+        artist         INTEGER REFERENCES artists (id),
+        album          INTEGER REFERENCES albums (id),
+        duration       INTEGER,
+        mtime          INTEGER,
+        size           INTEGER,
+        broken         INTEGER NOT NULL DEFAULT 0,
+        track_gain     REAL,
+        track_peak     REAL,
+        album_gain     REAL,
+        album_peak     REAL,
+        loudness       REAL,
+        true_peak      REAL,
+        analyzed_mtime INTEGER,
+        analyzed_size  INTEGER,
+        missing        TEXT NOT NULL DEFAULT ''
+    );
+    CREATE INDEX songs_library ON songs (library);
+    CREATE INDEX songs_path ON songs (path);
+    CREATE INDEX songs_title ON songs (title);
+    CREATE INDEX songs_artist ON songs (artist);
+    CREATE INDEX songs_album ON songs (album);
+    CREATE INDEX songs_duration ON songs (duration);
+    CREATE INDEX songs_mtime ON songs (mtime);
+    CREATE INDEX songs_size ON songs (size);
     "#;
-pub const FETCH_RECORD: &str = r#"
+/// Version 2, the inode joins the modification time and the size in telling
+/// whether a file changed since it was read.
+pub const ADD_INODE: &str = r#"
+    ALTER TABLE songs ADD COLUMN inode INTEGER
+"#;
+/// Version 3, a track read from a CUE sheet is read again when its sheet changes.
+pub const ADD_SHEET: &str = r#"
+    ALTER TABLE songs ADD COLUMN sheet TEXT;
+    ALTER TABLE songs ADD COLUMN sheet_mtime INTEGER;
+    ALTER TABLE songs ADD COLUMN sheet_size INTEGER;
+"#;
+/// Version 4, the session of every library and the bookmarks are keyed by
+/// their path as text, positions are in nanoseconds.
+pub const BUILD_SESSIONS: &str = r#"
+    CREATE TABLE sessions (
+        pos   TEXT NOT NULL UNIQUE,
+        state BLOB NOT NULL
+    );
+    CREATE TABLE bookmarks (
+        path     TEXT NOT NULL UNIQUE,
+        position INTEGER NOT NULL
+    );
+    "#;
+/// The table holding every library as one blob before version 1.
+pub const LEGACY_RECORD: &str = "record";
+pub const FETCH_LEGACY_RECORDS: &str = r#"
     SELECT
-        *
+        pos, cache, modified_time
     FROM
         record
+"#;
+pub const DROP_LEGACY_RECORDS: &str = r#"
+    DROP TABLE record
+"#;
+/// The tables holding the sessions and the bookmarks keyed by serialized
+/// paths before version 4.
+pub const LEGACY_SESSION: &str = "session";
+pub const FETCH_LEGACY_SESSIONS: &str = r#"
+    SELECT
+        pos, state
+    FROM
+        session
+"#;
+pub const DROP_LEGACY_SESSIONS: &str = r#"
+    DROP TABLE session
+"#;
+pub const LEGACY_BOOKMARK: &str = "bookmark";
+pub const FETCH_LEGACY_BOOKMARKS: &str = r#"
+    SELECT
+        path, position
+    FROM
+        bookmark
+"#;
+pub const DROP_LEGACY_BOOKMARKS: &str = r#"
+    DROP TABLE bookmark
+"#;
+pub const SAVE_LIBRARY: &str = r#"
+    INSERT INTO libraries
+        (pos, modified)
+    VALUES
+        (?1, ?2)
+    ON CONFLICT (pos) DO UPDATE SET
+        modified = excluded.modified
+    "#;
+pub const FETCH_LIBRARY: &str = r#"
+    SELECT
+        id, modified
+    FROM
+        libraries
     WHERE
         pos = (?1)
     "#;
-pub const FETCH_ALL_POS: &str = r#"
+pub const FETCH_ALL_LIBRARIES: &str = r#"
     SELECT
         pos
     FROM
-        record
+        libraries
+"#;
+pub const DELETE_LIBRARY: &str = r#"
+    DELETE FROM
+        libraries
+    WHERE
+        pos = (?1)
+    "#;
+pub const FETCH_ARTIST: &str = r#"
+    SELECT
+        id
+    FROM
+        artists
+    WHERE
+        name = (?1)
+    "#;
+pub const INSERT_ARTIST: &str = r#"
+    INSERT INTO artists
+        (name)
+    VALUES
+        (?1)
+    "#;
+pub const FETCH_ALBUM: &str = r#"
+    SELECT
+        id
+    FROM
+        albums
+    WHERE
+        title = (?1) AND artist IS (?2)
+    "#;
+pub const INSERT_ALBUM: &str = r#"
+    INSERT INTO albums
+        (title, artist)
+    VALUES
+        (?1, ?2)
+    "#;
+/// Albums and artists no song refers to anymore.
+pub const DELETE_ORPHANS: &str = r#"
+    DELETE FROM albums WHERE id NOT IN (
+        SELECT album FROM songs WHERE album IS NOT NULL
+    );
+    DELETE FROM artists WHERE id NOT IN (
+        SELECT artist FROM songs WHERE artist IS NOT NULL
+        UNION
+        SELECT artist FROM albums WHERE artist IS NOT NULL
+    );
+    "#;
+pub const INSERT_SONG: &str = r#"
+    INSERT INTO songs
+        (library, path, track, track_start, track_end, title, artist, album,
+         duration, mtime, size, broken, track_gain, track_peak, album_gain,
+         album_peak, loudness, true_peak, analyzed_mtime, analyzed_size, missing,
+         inode, sheet, sheet_mtime, sheet_size)
+    VALUES
+        (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16,
+         ?17, ?18, ?19, ?20, ?21, ?22, ?23, ?24, ?25)
+    "#;
+pub const FETCH_SONGS: &str = r#"
+    SELECT
+        songs.path, songs.track, songs.track_start, songs.track_end,
+        songs.title, artists.name, albums.title, songs.duration, songs.mtime,
+        songs.size, songs.broken, songs.track_gain, songs.track_peak,
+        songs.album_gain, songs.album_peak, songs.loudness, songs.true_peak,
+        songs.analyzed_mtime, songs.analyzed_size, songs.missing, songs.inode,
+        songs.sheet, songs.sheet_mtime, songs.sheet_size
+    FROM
+        songs
+        LEFT JOIN artists ON artists.id = songs.artist
+        LEFT JOIN albums ON albums.id = songs.album
+    WHERE
+        songs.library = (?1)
+    ORDER BY
+        songs.id
+    "#;
+/// All the songs of a file, the tracks of a single-file album included.
+pub const DELETE_FILE: &str = r#"
+    DELETE FROM
+        songs
+    WHERE
+        library = (?1) AND path = (?2)
+    "#;
+pub const MARK_BROKEN: &str = r#"
+    UPDATE
+        songs
+    SET
+        broken = 1
+    WHERE
+        library = (SELECT id FROM libraries WHERE pos = (?1))
+        AND path = (?2) AND track IS (?3)
+    "#;
+pub const SAVE_LOUDNESS: &str = r#"
+    UPDATE
+        songs
+    SET
+        loudness = (?3), true_peak = (?4), analyzed_mtime = (?5), analyzed_size = (?6)
+    WHERE
+        library = (SELECT id FROM libraries WHERE pos = (?1))
+        AND path = (?2)
+    "#;
+pub const SAVE_SESSION: &str = r#"
+    INSERT OR REPLACE INTO sessions
+        (pos, state)
+    VALUES
+        (?1, ?2)
+    "#;
+pub const FETCH_SESSION: &str = r#"
+    SELECT
+        state
+    FROM
+        sessions
+    WHERE
+        pos = (?1)
+    "#;
+pub const SAVE_BOOKMARK: &str = r#"
+    INSERT OR REPLACE INTO bookmarks
+        (path, position)
+    VALUES
+        (?1, ?2)
+    "#;
+pub const DELETE_BOOKMARK: &str = r#"
+    DELETE FROM
+        bookmarks
+    WHERE
+        path = (?1)
+    "#;
+pub const FETCH_ALL_BOOKMARKS: &str = r#"
+    SELECT
+        path, position
+    FROM
+        bookmarks
 "#;
@@ -0,0 +1,178 @@
+use crate::error::{Result, anyhow, Unknown};
+use id3::Tag as ID3Tag;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// What the chunks of a RIFF WAVE or an IFF AIFF file tell about it.
+#[derive(Debug, Default)]
+pub struct Riff {
+    /// Length in seconds, from the size of the data chunk or the frame count of the COMM chunk.
+    pub duration: Option<u64>,
+    /// Fields of the LIST INFO chunk keyed by their id, like `INAM` for the title.
+    /// The text chunks of AIFF are filed under the INFO id they match.
+    pub info: HashMap<String, String>,
+    /// An ID3 tag embedded in an `id3 ` chunk.
+    pub id3: Option<ID3Tag>,
+}
+
+impl Riff {
+
+    /// Field of the ID3 tag if there is one, of the INFO chunk otherwise.
+    #[inline]
+    pub fn field(&self, id3: impl Fn(&ID3Tag) -> Option<&str>, info: &str) -> Option<String> {
+        self.id3
+            .as_ref()
+            .and_then(id3)
+            .map(str::to_owned)
+            .or_else(|| self.info.get(info).cloned())
+    }
+}
+
+/// Walk the chunks of the WAVE or AIFF file at `path`, the audio data is skipped.
+#[inline]
+pub fn read(path: impl AsRef<Path>) -> Result<Riff> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut header = [0; 12];
+    reader.read_exact(&mut header)?;
+    // AIFF is the same thing in big-endian.
+    let big_endian = match (&header[..4], &header[8..]) {
+        (b"RIFF", b"WAVE") => false,
+        (b"FORM", b"AIFF") | (b"FORM", b"AIFC") => true,
+        _ => return Err(anyhow!(Unknown)),
+    };
+
+    let mut riff = Riff::default();
+    let mut byte_rate = None;
+    let mut data_len = None;
+    loop {
+        let mut chunk = [0; 8];
+        match reader.read_exact(&mut chunk) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        }
+        let len = [chunk[4], chunk[5], chunk[6], chunk[7]];
+        let len = if big_endian { u32::from_be_bytes(len) } else { u32::from_le_bytes(len) } as u64;
+        // Chunks are padded to an even length.
+        let padded = len + len % 2;
+        match &chunk[..4] {
+            b"fmt " => {
+                let body = body(&mut reader, padded)?;
+                if body.len() >= 12 {
+                    byte_rate = Some(u32::from_le_bytes([body[8], body[9], body[10], body[11]]) as u64);
+                }
+            }
+            b"LIST" => {
+                let body = body(&mut reader, padded)?;
+                if body.starts_with(b"INFO") {
+                    riff.info = parse_info(&body[4..]);
+                }
+            }
+            b"id3 " | b"ID3 " => {
+                let body = body(&mut reader, padded)?;
+                riff.id3 = ID3Tag::read_from(&body[..]).ok();
+            }
+            b"data" => {
+                data_len = Some(len);
+                reader.seek(SeekFrom::Current(padded as i64))?;
+            }
+            b"COMM" => {
+                let body = body(&mut reader, padded)?;
+                if body.len() >= 18 {
+                    let frames = u32::from_be_bytes([body[2], body[3], body[4], body[5]]) as f64;
+                    let rate = extended(&body[8..18]);
+                    if rate > 0.0 {
+                        riff.duration = Some((frames / rate) as u64);
+                    }
+                }
+            }
+            b"NAME" | b"AUTH" => {
+                let id = if &chunk[..4] == b"NAME" { "INAM" } else { "IART" };
+                let body = body(&mut reader, padded)?;
+                let value = String::from_utf8_lossy(&body).trim_end_matches('\0').trim().to_owned();
+                riff.info.insert(id.to_owned(), value);
+            }
+            _ => {
+                reader.seek(SeekFrom::Current(padded as i64))?;
+            }
+        }
+    }
+
+    if let Some(duration) = data_len.zip(byte_rate).and_then(|(len, rate)| len.checked_div(rate)) {
+        riff.duration = Some(duration);
+    }
+    Ok(riff)
+}
+
+/// An 80-bit IEEE 754 extended precision number, as the sample rate of AIFF.
+#[inline]
+fn extended(bytes: &[u8]) -> f64 {
+    let exponent = ((bytes[0] as i32 & 0x7F) << 8 | bytes[1] as i32) - 16383;
+    let mut mantissa = [0; 8];
+    mantissa.copy_from_slice(&bytes[2..10]);
+    let value = u64::from_be_bytes(mantissa) as f64 * 2f64.powi(exponent - 63);
+    if bytes[0] & 0x80 == 0 { value } else { -value }
+}
+
+#[inline]
+fn body(reader: &mut impl Read, len: u64) -> Result<Vec<u8>> {
+    let mut body = Vec::new();
+    reader.take(len).read_to_end(&mut body)?;
+    Ok(body)
+}
+
+/// The subchunks of a LIST INFO chunk, NUL terminated strings.
+#[inline]
+fn parse_info(mut body: &[u8]) -> HashMap<String, String> {
+    let mut info = HashMap::new();
+    while body.len() >= 8 {
+        let id = String::from_utf8_lossy(&body[..4]).into_owned();
+        let len = u32::from_le_bytes([body[4], body[5], body[6], body[7]]) as usize;
+        let end = (8 + len).min(body.len());
+        let value = String::from_utf8_lossy(&body[8..end]).trim_end_matches('\0').trim().to_owned();
+        if !value.is_empty() {
+            info.insert(id, value);
+        }
+        body = &body[(end + len % 2).min(body.len())..];
+    }
+    info
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::app::model::library::fixture;
+
+    #[test]
+    fn test_read_info() {
+        let riff = read(fixture("info.wav")).unwrap();
+        assert_eq!(riff.duration, Some(3));
+        assert!(riff.id3.is_none());
+        assert_eq!(riff.field(ID3Tag::title, "INAM").as_deref(), Some("Info Title"));
+        assert_eq!(riff.field(ID3Tag::artist, "IART").as_deref(), Some("Info Artist"));
+        assert_eq!(riff.field(ID3Tag::album, "IPRD").as_deref(), Some("Info Album"));
+    }
+
+    #[test]
+    fn test_read_id3() {
+        let riff = read(fixture("id3.wav")).unwrap();
+        assert_eq!(riff.duration, Some(2));
+        // The ID3 tag comes first, the INFO chunk fills in what it lacks.
+        assert_eq!(riff.field(ID3Tag::title, "INAM").as_deref(), Some("ID3 Title"));
+        assert_eq!(riff.field(ID3Tag::artist, "IART").as_deref(), Some("ID3 Artist"));
+        assert_eq!(riff.field(ID3Tag::album, "IPRD").as_deref(), Some("Info Album"));
+        let gain = riff.id3.unwrap().extended_texts().find(|t| t.description == "REPLAYGAIN_TRACK_GAIN").map(|t| t.value.clone());
+        assert_eq!(gain.as_deref(), Some("-6.50 dB"));
+    }
+
+    #[test]
+    fn test_read_aiff() {
+        let riff = read(fixture("tagged.aiff")).unwrap();
+        assert_eq!(riff.duration, Some(2));
+        assert_eq!(riff.field(ID3Tag::title, "INAM").as_deref(), Some("AIFF Title"));
+        assert_eq!(riff.field(ID3Tag::artist, "IART").as_deref(), Some("AIFF Artist"));
+    }
+}
@@ -1,28 +1,72 @@
+pub mod bookmark;
+pub mod clock;
+pub mod equalizer;
+pub mod output;
+pub mod queue;
+pub mod section;
+pub mod speed;
+pub mod stream;
+pub mod timer;
+pub mod volume;
+
 use crate::{DEBUG, Launch};
 use crate::config::Config;
-use crate::error::Result;
-use std::thread;
-use std::time::Duration;
-use std::fs::File;
+use crate::error::{Result, anyhow, Unknown};
+use std::time::{Duration, Instant};
 use std::sync::Arc;
-use std::sync::atomic::{AtomicBool, AtomicU64, Ordering::{Relaxed, SeqCst}};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::sync::atomic::{AtomicBool, Ordering::{Relaxed, SeqCst}};
+use super::library::decode::{Decoded, Seekable};
 use super::library::song::Song;
-use std::io::BufReader;
-use rodio::{Sink, OutputStream, Decoder, OutputStreamHandle};
+use super::library::replaygain::GainMode;
+use super::radio::Station;
+use rodio::{Sink, Source, Sample, Decoder};
+use rodio::source::Amplify;
 use std::fmt;
+use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
 use log::{info, trace};
+use bookmark::Bookmarks;
+use clock::{Clock, Tracked};
+use equalizer::{Equalizer, Equalized};
+use output::{Output, devices};
+use queue::Queue;
+use section::{Section, Slice};
+use speed::{Speed, SpeedMode, Varispeed, SPEED_STEP};
+use stream::{NowPlaying, Stream};
+use timer::Timer;
+use volume::{Balance, Panned, BALANCE_STEP, DEFAULT_VOLUME};
+
+/// How often the output device is checked for being unplugged.
+const DEVICE_CHECK_PERIOD: Duration = Duration::from_secs(3);
 
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Mode {
-    Sequential,
+    /// Play the list in order, starting over after the last song.
+    RepeatAll,
     SingleCycle,
+    /// Play the list in a shuffled order, shuffled again once every song was played.
     Random,
+    /// Play the list in order and stop after the last song.
+    RepeatOff,
 }
 
 impl Default for Mode {
     #[inline]
     fn default() -> Self {
-        Mode::Sequential
+        Mode::RepeatAll
+    }
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mode::RepeatAll => write!(f, "Repeat all"),
+            Mode::SingleCycle => write!(f, "Repeat one"),
+            Mode::Random => write!(f, "Shuffle"),
+            Mode::RepeatOff => write!(f, "Repeat off"),
+        }
     }
 }
 
@@ -30,15 +74,49 @@ impl Default for Mode {
 pub struct Player {
     pub mode: Mode,
     volume: u64,
+    muted: bool,
+    balance: Arc<Balance>,
+    seek_step: Duration,
+    crossfade: Duration,
+    crossfade_single_cycle: bool,
+    gain_mode: GainMode,
+    pub equalizer: Equalizer,
+    speed: Arc<Speed>,
+    speed_mode: SpeedMode,
     pub current: Option<Song>,
-    pub elapsed: Arc<AtomicU64>,
+    /// The radio station playing instead of a song, and what it's playing.
+    station: Option<(Station, Arc<NowPlaying>)>,
+    /// The station being connected to in the background, its decoder comes
+    /// through the receiver once it's known what it streams.
+    tuning: Option<(Station, Arc<NowPlaying>, Receiver<Result<Decoder<Stream>>>)>,
+    pub bookmarks: Bookmarks,
+    clock: Arc<Clock>,
+    /// Points A and B of the A-B loop, the section between them repeats once both are set.
+    marks: Option<(Duration, Option<Duration>)>,
+    timer: Option<Timer>,
+    /// How long the volume takes to fade out before the sleep timer stops the playback.
+    timer_fade: Duration,
+    /// Clock and position at the last tick, to measure how much was played since.
+    metered: Option<(Arc<Clock>, Duration)>,
+    /// Next song of the queue, already appended right behind the current one.
+    upcoming: Option<(Song, Arc<Clock>)>,
     paused: Arc<AtomicBool>,
     occupied: Arc<AtomicBool>,
-    switched: Arc<AtomicBool>,
-    backend: Option<Arc<Sink>>,
-    _stream: Option<OutputStream>,
-    handle: Option<OutputStreamHandle>,
+    backend: Option<Sink>,
+    /// Sink of the previous song while it fades out under the current one.
+    fading: Option<(Sink, Arc<Clock>)>,
+    output: Option<Output>,
+    /// When the output device was last checked.
+    device_check: Option<Instant>,
+    /// Result of the check running in the background, the device it's about
+    /// and whether it's gone.
+    device_probe: Option<Receiver<(String, bool)>>,
+    /// Message for the user about something the player did by itself.
+    notice: Option<String>,
+    /// Songs found unplayable since the last `take_broken`.
+    broken: Vec<PathBuf>,
     pub history: Vec<Song>,
+    pub queue: Queue,
 }
 
 impl std::fmt::Debug for Player {
@@ -54,36 +132,28 @@ impl Launch for Player {
             info!("Start to bootstrap player");
         }
 
-        self.volume = config.volume.unwrap();
-        let (_stream, handle) = OutputStream::try_default()?;
-        self._stream = Some(_stream);
-        let backend = Arc::new(Sink::try_new(&handle)?);
-        self.handle = Some(handle);
-        backend.set_volume(self.volume as f32 / 100.0);
-        self.backend = Some(backend);
-
-        let elapsed = self.elapsed.clone();
-        let switched = self.switched.clone();
-        thread::spawn(move || {
-            loop {
-                if switched.load(SeqCst) {
-                    switched.store(false, SeqCst);
-                    elapsed.store(0, SeqCst);
-                }
-                thread::sleep(Duration::from_millis(200));
-            }
-        });
-
-        let paused = self.paused.clone();
-        let elapsed = self.elapsed.clone();
-        thread::spawn(move || {
-            loop {
-                if !paused.load(SeqCst) {
-                    elapsed.fetch_add(1, SeqCst);
-                }
-                thread::sleep(Duration::from_secs(1));
-            }
+        self.volume = config.volume.unwrap_or(DEFAULT_VOLUME);
+        self.balance.set_pan(config.balance.unwrap());
+        self.balance.set_mono(config.mono.unwrap());
+        self.seek_step = Duration::from_secs(config.seek_step.unwrap());
+        self.crossfade = Duration::from_secs(config.crossfade.unwrap());
+        self.crossfade_single_cycle = config.crossfade_single_cycle.unwrap();
+        self.gain_mode = config.replaygain.as_ref().unwrap().parse()?;
+        self.equalizer = Equalizer::new(config.equalizer.as_ref().unwrap())?;
+        self.speed.set(config.speed.unwrap());
+        self.speed_mode = config.speed_mode.as_ref().unwrap().parse()?;
+        self.timer_fade = Duration::from_secs(config.sleep_fade.unwrap());
+        self.bookmarks = Bookmarks::new(
+            Duration::from_secs(config.resume_threshold.unwrap() * 60),
+            config.resume_dirs.as_ref().unwrap().iter().map(PathBuf::from).collect(),
+        );
+        let output = config.output.as_ref().unwrap();
+        self.output = Some(match Output::new(output, config.device.as_deref(), config.output_file.as_deref()) {
+            Ok(output) => output,
+            Err(e) if output == "device" => self.fallback(e),
+            Err(e) => return Err(e),
         });
+        self.backend = Some(self.sink()?);
 
         Ok(())
     }
@@ -92,34 +162,33 @@ impl Launch for Player {
 impl Player {
 
     #[inline(always)]
-    fn backend(&self) -> &Arc<Sink> {
+    fn backend(&self) -> &Sink {
         self.backend.as_ref().unwrap()
     }
 
+    #[inline]
+    fn sink(&self) -> Result<Sink> {
+        let sink = self.output.as_ref().unwrap().sink()?;
+        sink.set_volume(self.effective_volume());
+        Ok(sink)
+    }
+
     #[inline]
     pub fn handle(&mut self, song: &Song) -> Result<()> {
         if DEBUG.load(Relaxed) { trace!("Current song is: {:?}", self.current); }
-        if !self.history.contains(song) {
-            self.history.push(song.clone());
-            if DEBUG.load(Relaxed) {
-                trace!(
-                    "History update! {:#?}",
-                    self.history
-                        .iter()
-                        .map(|s| s.path())
-                        .collect::<Vec<_>>()
-                )
-            }
-        }
+        self.remember(song);
 
         if let Some(current) = self.current.as_ref() {
-            if current == song {
+            if current.id() == song.id() {
                 if DEBUG.load(Relaxed) { trace!("Press the play button on the same song"); }
                 self.play()?;
             } else {
                 if DEBUG.load(Relaxed) { trace!("Switch to the song: {:?}", song.path()); }
                 self.switch(song)?;
             }
+        } else if let Some(station) = self.station().or_else(|| self.tuning.as_ref().map(|(s, _, _)| s)) {
+            if DEBUG.load(Relaxed) { trace!("Leave {} for the song: {:?}", station.name, song.path()); }
+            self.switch(song)?;
         } else {
             if DEBUG.load(Relaxed) { trace!("Play the first song {:?}", song.path()); }
             self.current = Some(song.clone());
@@ -129,24 +198,658 @@ impl Player {
         Ok(())
     }
 
+    /// Follow the sink across song boundaries.
+    ///
+    /// The upcoming song is promoted as soon as its first sample is played,
+    /// if nothing was preloaded the queue is advanced once the current song ends.
     #[inline]
-    pub fn ratio(&self) -> Option<f64> {
-        if let Some(current) = self.current.as_ref() {
-            if let Some(duration) = current.metadata.duration {
-                let val = self.elapsed.load(SeqCst) as f64 / duration as f64;
-                if val > 1.0 {
-                    Some(0.0)
-                } else {
-                    Some(val)
+    pub fn tick(&mut self) -> Result<()> {
+        self.check_device();
+        if let Some(e) = self.output.as_ref().and_then(Output::take_failure) {
+            self.notify(format!("Stopped writing the output file: {}", e));
+        }
+        self.poll_tuning();
+        self.run_timer()?;
+        self.bookmark();
+        if matches!(&self.fading, Some((_, clock)) if clock.ended()) {
+            if DEBUG.load(Relaxed) { trace!("The previous song faded out."); }
+            self.fading = None;
+        }
+
+        if let Some(length) = self.crossfade_length() {
+            self.crossfade(length)?;
+        } else if matches!(&self.upcoming, Some((_, clock)) if clock.started()) {
+            let (song, clock) = self.upcoming.take().unwrap();
+            if DEBUG.load(Relaxed) { trace!("Gapless transition to: {:?}", song.path()); }
+            self.finish();
+            self.queue.next(self.mode, true);
+            self.remember(&song);
+            self.current = Some(song);
+            self.clock = clock;
+            self.marks = None;
+            self.prepare();
+        } else if self.occupied.load(SeqCst) && self.upcoming.is_none() && self.clock.ended() {
+            if DEBUG.load(Relaxed) { trace!("The song is naturally end."); }
+            self.occupied.store(false, SeqCst);
+            self.finish();
+            if let Some((station, _)) = self.station.take() {
+                self.notify(format!("{} stopped streaming", station.name));
+            } else {
+                // Nothing may have been preloaded because it failed, so the
+                // queue tells whether the timer ends the playback here.
+                match self.queue.peek(self.mode).cloned() {
+                    Some(song) if self.may_follow(&song) => {
+                        self.queue.next(self.mode, true);
+                        self.remember(&song);
+                        // Nobody pressed anything, so it's told instead of failing the UI.
+                        if let Err(e) = self.switch(&song) {
+                            self.notify(format!("Unable to carry on playing: {}", e));
+                        }
+                    }
+                    _ if matches!(self.timer, Some(Timer::EndOfSong) | Some(Timer::EndOfAlbum)) => {
+                        if DEBUG.load(Relaxed) { trace!("The sleep timer stops the playback."); }
+                        self.timer = None;
+                        self.queue.forget();
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Move to the default device once the output device went away. The
+    /// devices are listed in the background, a check that can't tell is
+    /// dropped until the next one.
+    #[inline]
+    fn check_device(&mut self) {
+        let probe = match self.device_probe.as_ref().map(Receiver::try_recv) {
+            Some(Err(TryRecvError::Empty)) => return,
+            Some(Ok(probe)) => Some(probe),
+            Some(Err(TryRecvError::Disconnected)) | None => None,
+        };
+        self.device_probe = None;
+        let lost = match probe {
+            // The output may have changed while it was checked.
+            Some((name, true)) if self.output.as_ref().and_then(|o| o.device_name()) == Some(name.as_str()) => name,
+            Some(_) => return,
+            None => {
+                if !matches!(self.device_check, Some(last) if last.elapsed() < DEVICE_CHECK_PERIOD) {
+                    self.probe_device();
+                }
+                return;
+            }
+        };
+        if DEBUG.load(Relaxed) { trace!("Lost the output device {}.", lost); }
+        let output = match Output::new("device", None, None) {
+            Ok(output) => {
+                self.notify(format!("{} is gone, playing to {}", lost, output.device_name().unwrap()));
+                output
+            }
+            Err(e) => self.fallback(e),
+        };
+        if let Err(e) = self.reopen(output) {
+            self.notify(format!("Unable to carry on playing: {}", e));
+        }
+    }
+
+    /// Check the output device in the background, for `check_device` to pick up.
+    #[inline]
+    fn probe_device(&mut self) {
+        self.device_check = Some(Instant::now());
+        let (name, pinned) = match self.output.as_ref().and_then(Output::watched) {
+            Some(watched) => watched,
+            None => return,
+        };
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            if let Some(lost) = output::lost(&name, pinned) {
+                let _ = tx.send((name, lost));
+            }
+        });
+        self.device_probe = Some(rx);
+    }
+
+    /// Play to the next output device.
+    #[inline]
+    pub fn cycle_device(&mut self) -> Result<()> {
+        let current = match self.output.as_ref().and_then(|o| o.device_name()) {
+            Some(name) => name.to_owned(),
+            None => return Ok(()),
+        };
+        let devices = devices();
+        let next = devices
+            .iter()
+            .position(|d| *d == current)
+            .map(|i| (i + 1) % devices.len())
+            .and_then(|i| devices.get(i))
+            .or_else(|| devices.first());
+        if let Some(next) = next {
+            match Output::new("device", Some(next), None) {
+                Ok(output) => {
+                    self.notify(format!("Playing to {}", next));
+                    self.reopen(output)?;
+                }
+                Err(e) => self.notify(format!("Unable to play to {}: {}", next, e)),
+            }
+        }
+        Ok(())
+    }
+
+    /// The default device when the chosen one can't be opened, no output at
+    /// all when there is no device.
+    #[inline]
+    fn fallback(&mut self, e: anyhow::Error) -> Output {
+        if DEBUG.load(Relaxed) { trace!("Unable to open the output device: {}", e); }
+        match Output::new("device", None, None) {
+            Ok(output) => {
+                self.notify(format!("{}, playing to {}", e, output.device_name().unwrap()));
+                output
+            }
+            Err(e) => {
+                self.notify(format!("{}, nothing will be heard", e));
+                Output::null()
+            }
+        }
+    }
+
+    /// Move the playback to `output`, carrying on from the same position.
+    #[inline]
+    fn reopen(&mut self, output: Output) -> Result<()> {
+        let pos = self.position();
+        let paused = self.paused.load(SeqCst);
+        let occupied = self.occupied.load(SeqCst);
+        let station = match (&self.station, &self.tuning) {
+            (Some((station, _)), _) if occupied => Some(station.clone()),
+            (_, Some((station, _, _))) => Some(station.clone()),
+            _ => None,
+        };
+        self.output = Some(output);
+        self.rebuild()?;
+        if let Some(station) = station {
+            // A live stream can only be picked up where it is now.
+            self.tune(&station)?;
+            if paused {
+                self.paused.store(true, SeqCst);
+                self.backend().pause();
+            }
+        } else if occupied && self.current.is_some() {
+            self.launch(pos, Duration::from_secs(0))?;
+            if paused {
+                self.paused.store(true, SeqCst);
+                self.backend().pause();
+            }
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn notify(&mut self, notice: String) {
+        if DEBUG.load(Relaxed) { info!("{}", notice); }
+        self.notice = Some(notice);
+    }
+
+    /// The songs that couldn't be played since the last call.
+    #[inline]
+    pub fn take_broken(&mut self) -> Vec<PathBuf> {
+        std::mem::take(&mut self.broken)
+    }
+
+    /// The latest message for the user, if it wasn't taken yet.
+    #[inline]
+    pub fn take_notice(&mut self) -> Option<String> {
+        self.notice.take()
+    }
+
+    /// Keep the bookmark of the current song in step with the playback.
+    #[inline]
+    fn bookmark(&mut self) {
+        if !self.occupied.load(SeqCst) {
+            return;
+        }
+        let pos = self.position();
+        if let Some(song) = self.current.as_ref() {
+            self.bookmarks.set(song, pos);
+        }
+    }
+
+    /// The current song played to its end, there is nothing left to resume.
+    #[inline]
+    fn finish(&mut self) {
+        if let Some(song) = self.current.as_ref() {
+            self.bookmarks.clear(song);
+        }
+    }
+
+    /// Where `song` starts, its bookmark if it has one.
+    #[inline]
+    fn resume_point(&self, song: &Song) -> Duration {
+        self.bookmarks.get(song).unwrap_or_default()
+    }
+
+    /// Play the current song again from its beginning, dropping its bookmark.
+    #[inline]
+    pub fn restart(&mut self) -> Result<()> {
+        if let Some(song) = self.current.as_ref() {
+            if DEBUG.load(Relaxed) { trace!("Restart {:?}.", song.path()); }
+            self.bookmarks.clear(song);
+            self.seek(Duration::from_secs(0))?;
+        }
+        Ok(())
+    }
+
+    /// Count the playback since the last tick down from the sleep timer, fading
+    /// the volume out over its last seconds and stopping once it runs out.
+    #[inline]
+    fn run_timer(&mut self) -> Result<()> {
+        let played = self.played();
+        if let Some(Timer::After(left)) = self.timer {
+            let left = left.checked_sub(played).unwrap_or_default();
+            if left == Duration::from_secs(0) {
+                if DEBUG.load(Relaxed) { trace!("The sleep timer stops the playback."); }
+                self.timer = None;
+                if self.occupied.load(SeqCst) {
+                    self.stop()?;
                 }
             } else {
-                None
+                self.timer = Some(Timer::After(left));
             }
+            self.apply_volume();
+        }
+        Ok(())
+    }
+
+    /// How much was played since the last call, as measured by the playback
+    /// clock and brought back to real time. Jumps of the clock, like seeking or
+    /// the A-B loop going around, are not counted.
+    #[inline]
+    fn played(&mut self) -> Duration {
+        let now = self.position();
+        let last = self.metered.replace((self.clock.clone(), now));
+        if !self.occupied.load(SeqCst) || self.paused.load(SeqCst) {
+            return Duration::from_secs(0);
+        }
+        match last {
+            Some((clock, then)) if Arc::ptr_eq(&clock, &self.clock) => now
+                .checked_sub(then)
+                .filter(|d| *d < Duration::from_secs(1))
+                .map(|d| d.div_f32(self.speed()))
+                .unwrap_or_default(),
+            _ => Duration::from_secs(0),
+        }
+    }
+
+    #[inline]
+    pub fn timer(&self) -> Option<Timer> {
+        self.timer
+    }
+
+    #[inline]
+    pub fn set_timer(&mut self, timer: Option<Timer>) {
+        if DEBUG.load(Relaxed) { trace!("Sleep timer: {:?}.", timer); }
+        self.timer = timer;
+        self.apply_volume();
+        // Whether the next song may be preloaded depends on the timer.
+        self.discard();
+        if self.occupied.load(SeqCst) {
+            self.prepare();
+        }
+    }
+
+    #[inline]
+    pub fn cycle_timer(&mut self) {
+        self.set_timer(Timer::cycle(self.timer));
+    }
+
+    /// How much of the current song the crossfade into the upcoming one should
+    /// cover, if it's time to start one.
+    #[inline]
+    fn crossfade_length(&self) -> Option<Duration> {
+        if self.crossfade == Duration::from_secs(0)
+            || self.fading.is_some()
+            || self.paused.load(SeqCst)
+            || !self.occupied.load(SeqCst)
+            || self.section().is_some()
+        {
+            return None;
+        }
+        let (song, _) = self.upcoming.as_ref()?;
+        if self.current.as_ref().map(Song::id) == Some(song.id()) && !self.crossfade_single_cycle {
+            return None;
+        }
+        let remaining = self.duration()?.checked_sub(self.position())?;
+        if remaining.div_f32(self.speed()) <= self.crossfade {
+            Some(remaining)
         } else {
             None
         }
     }
 
+    /// Fade the current song out in its own sink while the upcoming one fades
+    /// in on a fresh sink.
+    #[inline]
+    fn crossfade(&mut self, length: Duration) -> Result<()> {
+        let (song, preloaded) = self.upcoming.take().unwrap();
+        if DEBUG.load(Relaxed) { trace!("Crossfade into {:?} over {:?}.", song.path(), length); }
+        preloaded.cancel();
+        self.clock.fade_out(length);
+        let sink = self.sink()?;
+        let outgoing = self.backend.replace(sink).unwrap();
+        self.fading = Some((outgoing, self.clock.clone()));
+        self.finish();
+        self.queue.next(self.mode, true);
+        self.remember(&song);
+        let from = self.resume_point(&song);
+        self.current = Some(song);
+        self.marks = None;
+        self.launch(from, length.div_f32(self.speed()))
+    }
+
+    #[inline]
+    pub fn set_mode(&mut self, mode: Mode) {
+        if self.mode != mode {
+            self.mode = mode;
+            self.discard();
+            if mode == Mode::Random {
+                self.queue.reshuffle();
+            }
+            if self.occupied.load(SeqCst) {
+                self.prepare();
+            }
+        }
+    }
+
+    /// Follow the board after its contents changed, the preloaded song may
+    /// not be the one coming next anymore.
+    #[inline]
+    pub fn update_queue(&mut self, songs: Vec<Song>) {
+        if !self.queue.update(songs) {
+            return;
+        }
+        self.discard();
+        if self.occupied.load(SeqCst) {
+            self.prepare();
+        }
+    }
+
+    #[inline]
+    pub fn next(&mut self) -> Result<()> {
+        if let Some(song) = self.queue.next(self.mode, false).cloned() {
+            if DEBUG.load(Relaxed) { trace!("Skip to the next song: {:?}", song.path()); }
+            self.remember(&song);
+            self.switch(&song)?;
+        }
+        Ok(())
+    }
+
+    #[inline]
+    pub fn previous(&mut self) -> Result<()> {
+        if let Some(song) = self.queue.previous(self.mode).cloned() {
+            if DEBUG.load(Relaxed) { trace!("Back to the previous song: {:?}", song.path()); }
+            self.remember(&song);
+            self.switch(&song)?;
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn remember(&mut self, song: &Song) {
+        if !self.history.iter().any(|s| s.id() == song.id()) {
+            self.history.push(song.clone());
+            if DEBUG.load(Relaxed) {
+                trace!(
+                    "History update! {:#?}",
+                    self.history
+                        .iter()
+                        .map(|s| s.path())
+                        .collect::<Vec<_>>()
+                )
+            }
+        }
+    }
+
+    /// Jump to `pos` of the current song, the pause state is kept.
+    #[inline]
+    pub fn seek(&mut self, pos: Duration) -> Result<()> {
+        if self.current.is_none() {
+            return Ok(());
+        }
+        let pos = match self.duration() {
+            Some(duration) if pos > duration => duration,
+            _ => pos,
+        };
+        if DEBUG.load(Relaxed) { trace!("Seek to {:?}.", pos); }
+        if matches!(self.section(), Some((a, b)) if pos < a || pos > b) {
+            if DEBUG.load(Relaxed) { trace!("Seek out of the A-B loop, clear it."); }
+            self.marks = None;
+        }
+
+        let paused = self.paused.load(SeqCst);
+        self.rebuild()?;
+        self.launch(pos, Duration::from_secs(0))?;
+        if paused {
+            self.paused.store(true, SeqCst);
+            self.backend().pause();
+        }
+        Ok(())
+    }
+
+    #[inline]
+    pub fn forward(&mut self) -> Result<()> {
+        self.seek(self.position() + self.seek_step)
+    }
+
+    #[inline]
+    pub fn rewind(&mut self) -> Result<()> {
+        self.seek(self.position().checked_sub(self.seek_step).unwrap_or_default())
+    }
+
+    /// Position in the current song, counted by the samples already played
+    /// whatever the speed is.
+    #[inline]
+    pub fn position(&self) -> Duration {
+        self.clock.elapsed()
+    }
+
+    #[inline]
+    pub fn duration(&self) -> Option<Duration> {
+        let current = self.current.as_ref()?;
+        self.clock
+            .total()
+            .or_else(|| current.metadata.duration.map(Duration::from_secs))
+    }
+
+    #[inline]
+    pub fn ratio(&self) -> Option<f64> {
+        let duration = self.duration()?.as_secs_f64();
+        if duration == 0.0 {
+            return None;
+        }
+        let val = self.position().as_secs_f64() / duration;
+        if val > 1.0 {
+            Some(0.0)
+        } else {
+            Some(val)
+        }
+    }
+
+    /// Set point A, then point B which starts the A-B loop, then clear the loop.
+    #[inline]
+    pub fn mark(&mut self, pos: Duration) -> Result<()> {
+        if self.current.is_none() {
+            return Ok(());
+        }
+        match self.marks {
+            None => {
+                if DEBUG.load(Relaxed) { trace!("Set point A at {:?}.", pos); }
+                self.marks = Some((pos, None));
+            }
+            Some((a, None)) if a != pos => {
+                let (a, b) = if a < pos { (a, pos) } else { (pos, a) };
+                if DEBUG.load(Relaxed) { trace!("Loop from {:?} to {:?}.", a, b); }
+                self.marks = Some((a, Some(b)));
+                // Playing on from where we are, so marking B at the current
+                // position jumps right back to A.
+                self.seek(self.position().max(a).min(b))?;
+            }
+            Some((_, None)) => {}
+            Some((_, Some(_))) => self.clear_loop()?,
+        }
+        Ok(())
+    }
+
+    #[inline]
+    pub fn clear_loop(&mut self) -> Result<()> {
+        if self.marks.take().is_some() && self.occupied.load(SeqCst) {
+            if DEBUG.load(Relaxed) { trace!("Clear the A-B loop."); }
+            self.seek(self.position())?;
+        }
+        Ok(())
+    }
+
+    #[inline]
+    pub fn marks(&self) -> Option<(Duration, Option<Duration>)> {
+        self.marks
+    }
+
+    /// The section repeated by the A-B loop, if both points are set.
+    #[inline]
+    fn section(&self) -> Option<(Duration, Duration)> {
+        match self.marks {
+            Some((a, Some(b))) => Some((a, b)),
+            _ => None,
+        }
+    }
+
+    /// Time left before the current song ends, at the current speed.
+    #[inline]
+    pub fn remaining(&self) -> Option<Duration> {
+        let left = self.duration()?.checked_sub(self.position()).unwrap_or_default();
+        Some(left.div_f32(self.speed()))
+    }
+
+    #[inline]
+    pub fn speed(&self) -> f32 {
+        self.speed.get()
+    }
+
+    #[inline]
+    pub fn faster(&mut self) {
+        self.speed.set(self.speed() + SPEED_STEP);
+        if DEBUG.load(Relaxed) { trace!("Speed up to {}x.", self.speed()) }
+    }
+
+    #[inline]
+    pub fn slower(&mut self) {
+        self.speed.set(self.speed() - SPEED_STEP);
+        if DEBUG.load(Relaxed) { trace!("Slow down to {}x.", self.speed()) }
+    }
+
+    #[inline]
+    pub fn reset_speed(&mut self) {
+        self.speed.set(1.0);
+    }
+
+    #[inline]
+    pub fn speed_mode(&self) -> SpeedMode {
+        self.speed_mode
+    }
+
+    /// Load `song` paused at `pos`, playing resumes it from there.
+    #[inline]
+    pub fn cue(&mut self, song: &Song, pos: Duration) -> Result<()> {
+        if DEBUG.load(Relaxed) { trace!("Cue {:?} at {:?}.", song.path(), pos); }
+        self.rebuild()?;
+        self.marks = None;
+        self.remember(song);
+        self.current = Some(song.clone());
+        self.launch(pos, Duration::from_secs(0))?;
+        self.paused.store(true, SeqCst);
+        self.backend().pause();
+        Ok(())
+    }
+
+    /// Play the radio `station` instead of the songs, tuning in to the one
+    /// playing pauses or resumes it. A station that can't be reached is
+    /// reported and nothing plays.
+    #[inline]
+    pub fn tune(&mut self, station: &Station) -> Result<()> {
+        if matches!(&self.station, Some((s, _)) if s == station) && self.occupied.load(SeqCst) {
+            return self.play();
+        }
+        if matches!(&self.tuning, Some((s, _, _)) if s == station) {
+            return Ok(());
+        }
+        if DEBUG.load(Relaxed) { trace!("Tune in to {} at {}.", station.name, station.url); }
+        self.rebuild()?;
+        self.marks = None;
+        self.current = None;
+        self.paused.store(false, SeqCst);
+        // Connecting and probing the format block for as long as the station
+        // takes to answer, so they're done off the UI thread.
+        let now_playing = Arc::new(NowPlaying::default());
+        let (tx, rx) = mpsc::channel();
+        let url = station.url.clone();
+        let metadata = now_playing.clone();
+        thread::spawn(move || {
+            let source = Stream::open(&url, metadata).and_then(|s| Ok(Decoder::new(s)?));
+            let _ = tx.send(source);
+        });
+        self.notify(format!("Tuning in to {}", station.name));
+        self.tuning = Some((station.clone(), now_playing, rx));
+        Ok(())
+    }
+
+    /// Start the station being tuned in to once it's connected.
+    #[inline]
+    fn poll_tuning(&mut self) {
+        let source = match self.tuning.as_ref().map(|(_, _, rx)| rx.try_recv()) {
+            Some(Ok(source)) => source,
+            Some(Err(TryRecvError::Disconnected)) => Err(anyhow!(Unknown)),
+            Some(Err(TryRecvError::Empty)) | None => return,
+        };
+        let (station, now_playing, _) = self.tuning.take().unwrap();
+        let source = match source {
+            Ok(source) => source,
+            Err(e) => {
+                self.notify(format!("Unable to tune in to {}: {}", station.name, e));
+                return;
+            }
+        };
+        let clock = Arc::new(Clock::new(Duration::from_secs(0), None));
+        self.append(self.chain(source, 1.0, clock.clone()), Duration::from_secs(0));
+        self.clock = clock;
+        self.occupied.store(true, SeqCst);
+        self.station = Some((station, now_playing));
+    }
+
+    /// The radio station playing, if any.
+    #[inline]
+    pub fn station(&self) -> Option<&Station> {
+        self.station.as_ref().map(|(s, _)| s)
+    }
+
+    /// Title of what the radio station is playing, if it tells.
+    #[inline]
+    pub fn stream_title(&self) -> Option<String> {
+        self.station.as_ref().and_then(|(_, now_playing)| now_playing.get())
+    }
+
+    /// Switch between keeping and following the pitch, the current song is
+    /// reopened at the same position since its source is built for one mode.
+    #[inline]
+    pub fn toggle_speed_mode(&mut self) -> Result<()> {
+        self.speed_mode = match self.speed_mode {
+            SpeedMode::Stretch => SpeedMode::Resample,
+            SpeedMode::Resample => SpeedMode::Stretch,
+        };
+        if DEBUG.load(Relaxed) { trace!("Speed mode: {:?}.", self.speed_mode) }
+        if self.occupied.load(SeqCst) {
+            self.seek(self.position())?;
+        }
+        Ok(())
+    }
+
     #[inline]
     fn play(&mut self) -> Result<()> {
         if DEBUG.load(Relaxed) {
@@ -157,71 +860,504 @@ impl Player {
             if self.paused.load(SeqCst) {
                 self.paused.store(false, SeqCst);
                 self.backend().play();
+                if let Some((fading, _)) = self.fading.as_ref() {
+                    fading.play();
+                }
                 if DEBUG.load(Relaxed) { trace!("Resume the player."); }
             } else {
                 self.paused.store(true, SeqCst);
                 self.backend().pause();
+                if let Some((fading, _)) = self.fading.as_ref() {
+                    fading.pause();
+                }
                 if DEBUG.load(Relaxed) { trace!("Pause the player."); }
             }
         } else {
-            self.paused.store(false, SeqCst);
-            self.occupied.store(true, SeqCst);
-            if DEBUG.load(Relaxed) { trace!("No. The player is occupied from now"); }
-            self.backend().append(Decoder::new(BufReader::new(File::open(self.current.as_ref().unwrap().path())?))?);
-            if DEBUG.load(Relaxed) { trace!("Append song: {:?} to the queue", self.current); }
-            let notify_end = self.backend().clone();
-            let occupied = self.occupied.clone();
-            thread::spawn(move || {
-                loop {
-                    if !occupied.load(SeqCst) {
-                        if DEBUG.load(Relaxed) { trace!("Thread exits.") }
-                        break
-                    }
-                    if notify_end.is_end() {
-                        if DEBUG.load(Relaxed) { trace!("The song is naturally end."); }
-                        occupied.store(false, SeqCst);
-                    }
-                    thread::sleep(Duration::from_millis(100));
+            let from = self.current.as_ref().map(|s| self.resume_point(s)).unwrap_or_default();
+            self.launch(from, Duration::from_secs(0))?;
+        }
+
+        Ok(())
+    }
+
+    /// Start the current song like `start`. A song that can't be opened or
+    /// decoded is reported and marked as broken, the queue moves on to the
+    /// next one instead.
+    #[inline]
+    fn launch(&mut self, mut from: Duration, fade_in: Duration) -> Result<()> {
+        let mut tries = self.queue.songs().len().max(1);
+        loop {
+            let e = match self.start(from, fade_in) {
+                Ok(()) => return Ok(()),
+                Err(e) => e,
+            };
+            self.occupied.store(false, SeqCst);
+            let song = match self.current.take() {
+                Some(song) => song,
+                None => return Ok(()),
+            };
+            if DEBUG.load(Relaxed) { trace!("Unable to play {:?}: {}", song.path(), e); }
+            let name = song.path().file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+            self.notify(format!("Skipped {}: {}", name, e));
+            self.broken.push(song.id());
+
+            tries -= 1;
+            match self.queue.next(self.mode, false).cloned() {
+                Some(next) if tries > 0 && next.id() != song.id() => {
+                    self.remember(&next);
+                    from = self.resume_point(&next);
+                    self.current = Some(next);
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    /// Append the current song to the sink, starting from `from` and fading in over `fade_in`.
+    #[inline]
+    fn start(&mut self, from: Duration, fade_in: Duration) -> Result<()> {
+        self.paused.store(false, SeqCst);
+        self.occupied.store(true, SeqCst);
+        if DEBUG.load(Relaxed) { trace!("No. The player is occupied from now"); }
+        let current = self.current.as_ref().unwrap();
+        let gain = current.metadata.gain(self.gain_mode);
+        let mut source = Slice::new(Decoded::new(current.path())?, current.track)?;
+        let clock = Arc::new(Clock::new(from, source.total_duration()));
+        match self.section() {
+            Some((a, b)) => {
+                source.seek(from.max(a))?;
+                let source = Section::new(source, a, b, from, clock.clone());
+                self.append(self.chain(source, gain, clock.clone()), fade_in);
+            }
+            None => {
+                // The decoder jumps there, rather than decoding everything before it.
+                if from > Duration::from_secs(0) {
+                    source.seek(from)?;
                 }
-            });
+                self.append(self.chain(source, gain, clock.clone()), fade_in);
+            }
         }
+        self.clock = clock;
+        if DEBUG.load(Relaxed) { trace!("Append song: {:?} to the queue", self.current); }
+        self.prepare();
+
+        Ok(())
+    }
+
+    /// Run a decoded song through the gain, the equalizer, the balance, its
+    /// clock and the speed.
+    #[inline]
+    fn chain<S>(&self, source: S, gain: f32, clock: Arc<Clock>) -> Varispeed<Tracked<Panned<Equalized<Amplify<S>>>>>
+    where
+        S: Source,
+        S::Item: Sample,
+    {
+        let source = Equalized::new(source.amplify(gain), self.equalizer.bands.clone());
+        let source = Panned::new(source, self.balance.clone());
+        Varispeed::new(Tracked::new(source, clock), self.speed.clone(), self.speed_mode)
+    }
 
+    #[inline]
+    fn append<S>(&self, source: S, fade_in: Duration)
+    where
+        S: Source<Item = f32> + Send + 'static,
+    {
+        if fade_in == Duration::from_secs(0) {
+            self.backend().append(source);
+        } else {
+            self.backend().append(source.fade_in(fade_in));
+        }
+    }
+
+    /// Preload the next song of the queue, a failure is not fatal here since
+    /// the queue will be advanced again once the current song ends.
+    #[inline]
+    fn prepare(&mut self) {
+        if let Err(e) = self.preload() {
+            if DEBUG.load(Relaxed) { trace!("Unable to preload the next song: {}", e); }
+        }
+    }
+
+    /// Open the next song of the queue and append it to the sink, so that it
+    /// starts right after the last sample of the current one. Nothing comes
+    /// after the A-B loop, nor after a radio station.
+    #[inline]
+    fn preload(&mut self) -> Result<()> {
+        if self.section().is_some() || self.station.is_some() {
+            return Ok(());
+        }
+        if let Some(song) = self.queue.peek(self.mode).cloned() {
+            if !self.may_follow(&song) {
+                self.queue.forget();
+                return Ok(());
+            }
+            let gain = song.metadata.gain(self.gain_mode);
+            let from = self.resume_point(&song);
+            let mut source = Slice::new(Decoded::new(song.path())?, song.track)?;
+            let clock = Arc::new(Clock::new(from, source.total_duration()));
+            if from > Duration::from_secs(0) {
+                source.seek(from)?;
+            }
+            self.backend().append(self.chain(source, gain, clock.clone()));
+            if DEBUG.load(Relaxed) { trace!("Preload song: {:?}", song.path()); }
+            self.upcoming = Some((song, clock));
+        }
         Ok(())
     }
 
+    /// Whether `song` may play after the current one with the sleep timer set.
+    #[inline]
+    fn may_follow(&self, song: &Song) -> bool {
+        match self.timer {
+            Some(Timer::EndOfSong) => false,
+            Some(Timer::EndOfAlbum) => {
+                let album = self.current.as_ref().and_then(|s| s.metadata.album.as_ref());
+                album.is_some() && album == song.metadata.album.as_ref()
+            }
+            _ => true,
+        }
+    }
+
+    /// Cancel the preloaded song, the sink skips it right away.
+    #[inline]
+    fn discard(&mut self) {
+        if let Some((_, clock)) = self.upcoming.take() {
+            clock.cancel();
+        }
+        self.queue.forget();
+    }
+
     #[inline]
     fn switch(&mut self, song: &Song) -> Result<()> {
-        self.switched.store(true, SeqCst);
-        self.backend().stop();
-        self.backend = Some(Arc::new(Sink::try_new(self.handle.as_ref().unwrap())?));
+        self.rebuild()?;
+        self.marks = None;
         self.current = Some(song.clone());
-        self.occupied.store(false, SeqCst);
-        if DEBUG.load(Relaxed) { trace!("Cleanup the previous thread."); }
-        thread::sleep(Duration::from_millis(200));
         self.play()?;
         Ok(())
     }
 
+    /// Stop the playback where it is, the current song stays and plays from
+    /// its bookmark, if it has one, once played again.
+    #[inline]
+    fn stop(&mut self) -> Result<()> {
+        self.bookmark();
+        self.rebuild()
+    }
+
+    /// Replace the sink with a fresh one, dropping everything queued in the old one.
+    #[inline]
+    fn rebuild(&mut self) -> Result<()> {
+        self.discard();
+        self.fading = None;
+        self.station = None;
+        self.tuning = None;
+        self.backend().stop();
+        self.backend = Some(self.sink()?);
+        self.occupied.store(false, SeqCst);
+        Ok(())
+    }
+
+    #[inline]
+    pub fn volume(&self) -> u64 {
+        self.volume
+    }
+
+    #[inline]
+    pub fn set_volume(&mut self, volume: u64) {
+        self.volume = volume.min(100);
+        self.apply_volume();
+    }
+
+    #[inline]
+    pub fn muted(&self) -> bool {
+        self.muted
+    }
+
+    /// Silence the playback, or bring it back to the volume it had.
+    #[inline]
+    pub fn toggle_mute(&mut self) {
+        self.muted = !self.muted;
+        self.apply_volume();
+        if DEBUG.load(Relaxed) { trace!("Muted: {}.", self.muted) }
+    }
+
+    #[inline]
+    pub fn balance(&self) -> f32 {
+        self.balance.pan()
+    }
+
+    /// Move the balance towards the right by `delta`, towards the left if negative.
+    #[inline]
+    pub fn shift_balance(&mut self, delta: f32) {
+        self.balance.set_pan(self.balance.pan() + delta);
+        if DEBUG.load(Relaxed) { trace!("Balance: {}.", self.balance.pan()) }
+    }
+
+    #[inline]
+    pub fn balance_left(&mut self) {
+        self.shift_balance(-BALANCE_STEP);
+    }
+
+    #[inline]
+    pub fn balance_right(&mut self) {
+        self.shift_balance(BALANCE_STEP);
+    }
+
+    #[inline]
+    pub fn center_balance(&mut self) {
+        self.balance.set_pan(0.0);
+    }
+
+    #[inline]
+    pub fn mono(&self) -> bool {
+        self.balance.mono()
+    }
+
+    #[inline]
+    pub fn toggle_mono(&mut self) {
+        self.balance.set_mono(!self.balance.mono());
+    }
+
     #[inline]
     pub fn increase_volume(&mut self) {
+        self.muted = false;
         if self.volume < 99 {
             self.volume += 2;
         } else {
             self.volume = 100;
         }
-        self.backend().set_volume(self.volume as f32 / 100.0);
+        self.apply_volume();
         if DEBUG.load(Relaxed) { trace!("Increase volume. volume: {} .", self.volume) }
     }
 
     #[inline]
     pub fn decrease_volume(&mut self) {
+        self.muted = false;
         if self.volume > 1 {
             self.volume -= 2;
         }  else {
             self.volume = 0;
         }
-        self.backend().set_volume(self.volume as f32 / 100.0);
+        self.apply_volume();
         if DEBUG.load(Relaxed) { trace!("Decrease volume. volume: {} .", self.volume) }
     }
 
+    #[inline]
+    fn apply_volume(&self) {
+        let volume = self.effective_volume();
+        self.backend().set_volume(volume);
+        if let Some((fading, _)) = self.fading.as_ref() {
+            fading.set_volume(volume);
+        }
+    }
+
+    /// The volume as an amplitude factor, lowered over the last seconds of the sleep timer.
+    #[inline]
+    fn effective_volume(&self) -> f32 {
+        if self.muted {
+            return 0.0;
+        }
+        let volume = volume::amplitude(self.volume);
+        match self.timer {
+            Some(Timer::After(left)) if left < self.timer_fade => {
+                volume * left.as_secs_f32() / self.timer_fade.as_secs_f32()
+            }
+            _ => volume,
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::app::model::library::fixture;
+    use crate::app::model::library::song::Metadata;
+
+    /// A player mixing to nowhere, at the pace of a real device.
+    fn player() -> Player {
+        let mut player = Player {
+            volume: 100,
+            seek_step: Duration::from_secs(1),
+            output: Some(Output::null()),
+            ..Default::default()
+        };
+        player.backend = Some(player.sink().unwrap());
+        player
+    }
+
+    fn song(name: &str) -> Song {
+        Song::with_metadata(fixture(name), Metadata::default()).unwrap()
+    }
+
+    fn current(player: &Player) -> Option<PathBuf> {
+        player.current.as_ref().map(Song::id)
+    }
+
+    /// Tick like the UI does until `done` holds, `false` if it didn't within `limit`.
+    fn tick_until(player: &mut Player, limit: Duration, done: impl Fn(&Player) -> bool) -> bool {
+        let start = Instant::now();
+        while start.elapsed() < limit {
+            player.tick().unwrap();
+            if done(player) {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        false
+    }
+
+    #[test]
+    fn test_null_output() {
+        let mut player = player();
+        let songs = vec![song("info.wav"), song("id3.wav")];
+        player.queue.load(songs.clone(), 0);
+        player.handle(&songs[0]).unwrap();
+        assert!(tick_until(&mut player, Duration::from_secs(2), |p| p.position() > Duration::from_millis(100)));
+
+        // Playing the same song again pauses it, the clock stands still meanwhile.
+        player.handle(&songs[0]).unwrap();
+        let paused = player.position();
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(player.position(), paused);
+        player.handle(&songs[0]).unwrap();
+        assert!(tick_until(&mut player, Duration::from_secs(2), |p| p.position() > paused));
+
+        player.next().unwrap();
+        assert_eq!(current(&player), Some(songs[1].id()));
+        assert!(player.position() < Duration::from_secs(1));
+
+        // Once it ends the queue moves on by itself, around to the first song.
+        player.seek(Duration::from_millis(1800)).unwrap();
+        assert!(tick_until(&mut player, Duration::from_secs(3), |p| current(p) == Some(songs[0].id())));
+        assert!(player.position() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_timer() {
+        let mut player = player();
+        let album = |name, title: &str| {
+            let mut song = song(name);
+            song.metadata.album = Some(title.to_owned());
+            song
+        };
+        let songs = vec![album("info.wav", "One"), album("id3.wav", "One"), album("untagged.mp3", "Two")];
+        player.queue.load(songs.clone(), 0);
+        player.handle(&songs[0]).unwrap();
+        player.set_timer(Some(Timer::EndOfAlbum));
+
+        // The next song of the album follows even if it couldn't be preloaded.
+        player.seek(Duration::from_millis(1800)).unwrap();
+        player.discard();
+        assert!(tick_until(&mut player, Duration::from_secs(3), |p| current(p) == Some(songs[1].id())));
+        assert_eq!(player.timer(), Some(Timer::EndOfAlbum));
+
+        // The playback stops with the album.
+        player.seek(Duration::from_millis(1800)).unwrap();
+        assert!(tick_until(&mut player, Duration::from_secs(3), |p| !p.occupied.load(SeqCst)));
+        assert_eq!(current(&player), Some(songs[1].id()));
+        assert_eq!(player.timer(), None);
+
+        // Once the countdown runs out the playback stops rather than pauses.
+        player.handle(&songs[1]).unwrap();
+        player.set_timer(Some(Timer::After(Duration::from_millis(300))));
+        assert!(tick_until(&mut player, Duration::from_secs(2), |p| p.timer().is_none()));
+        assert!(!player.occupied.load(SeqCst));
+        assert!(!player.paused.load(SeqCst));
+        assert_eq!(current(&player), Some(songs[1].id()));
+    }
+
+    #[test]
+    fn test_gapless() {
+        let mut player = player();
+        let songs = vec![song("info.wav"), song("id3.wav")];
+        player.queue.load(songs.clone(), 0);
+        player.handle(&songs[0]).unwrap();
+        player.seek(Duration::from_millis(1800)).unwrap();
+        // The next song waits in the same sink, behind the current one.
+        let (upcoming, clock) = player.upcoming.clone().unwrap();
+        assert_eq!(upcoming.id(), songs[1].id());
+        assert!(!clock.started());
+
+        // It takes over from its first sample, without being started again.
+        assert!(tick_until(&mut player, Duration::from_secs(3), |p| current(p) == Some(songs[1].id())));
+        assert!(Arc::ptr_eq(&player.clock, &clock));
+        assert!(player.occupied.load(SeqCst));
+        assert!(player.position() < Duration::from_secs(1));
+        assert_eq!(player.queue.current().map(Song::id), Some(songs[1].id()));
+        // And the song after it is preloaded in turn.
+        assert_eq!(player.upcoming.as_ref().map(|(s, _)| s.id()), Some(songs[0].id()));
+    }
+
+    #[test]
+    fn test_crossfade() {
+        let mut player = player();
+        player.crossfade = Duration::from_secs(1);
+        let songs = vec![song("info.wav"), song("id3.wav")];
+        player.queue.load(songs.clone(), 0);
+        player.handle(&songs[0]).unwrap();
+        player.seek(Duration::from_millis(1500)).unwrap();
+        assert!(tick_until(&mut player, Duration::from_secs(2), |p| current(p) == Some(songs[1].id())));
+        // The previous song fades out in its own sink meanwhile.
+        assert!(player.fading.is_some());
+        assert!(player.occupied.load(SeqCst));
+        assert!(tick_until(&mut player, Duration::from_secs(2), |p| p.fading.is_none()));
+
+        // A song cycling doesn't fade into itself, even if the copy in the
+        // queue was read again since.
+        player.set_mode(Mode::SingleCycle);
+        let mut played = songs[1].clone();
+        played.metadata.title = Some("Read again".to_owned());
+        player.current = Some(played);
+        player.seek(Duration::from_millis(1500)).unwrap();
+        assert_eq!(player.upcoming.as_ref().map(|(s, _)| s.id()), Some(songs[1].id()));
+        assert_eq!(player.crossfade_length(), None);
+    }
+
+    #[test]
+    fn test_reopen() {
+        let mut player = player();
+        let songs = vec![song("info.wav"), song("id3.wav")];
+        player.queue.load(songs.clone(), 0);
+        player.handle(&songs[0]).unwrap();
+        player.seek(Duration::from_secs(1)).unwrap();
+        player.handle(&songs[0]).unwrap();
+        let pos = player.position();
+
+        // Without the device there is still somewhere to play to, and the user is told.
+        let output = player.fallback(anyhow!(Unknown));
+        assert!(player.take_notice().is_some());
+        player.reopen(output).unwrap();
+        assert_eq!(current(&player), Some(songs[0].id()));
+        assert!(player.occupied.load(SeqCst));
+        assert!(player.paused.load(SeqCst));
+        assert!(player.position() >= pos && player.position() < pos + Duration::from_millis(100));
+
+        // The song carries on from there.
+        player.handle(&songs[0]).unwrap();
+        assert!(tick_until(&mut player, Duration::from_secs(2), |p| p.position() > pos + Duration::from_millis(100)));
+        assert_eq!(current(&player), Some(songs[0].id()));
+    }
+
+    #[test]
+    fn test_skip_broken() {
+        let mut player = player();
+        let songs = vec![song("gone.wav"), song("info.wav")];
+        player.queue.load(songs.clone(), 0);
+        player.handle(&songs[0]).unwrap();
+        // The missing song is reported and marked, the next one plays instead.
+        assert_eq!(current(&player), Some(songs[1].id()));
+        assert!(player.occupied.load(SeqCst));
+        assert!(player.take_notice().unwrap().starts_with("Skipped gone.wav"));
+        assert_eq!(player.take_broken(), vec![songs[0].id()]);
+
+        // Once nothing in the queue plays, it gives up instead of going around forever.
+        let broken = vec![song("gone.wav"), song("missing.mp3")];
+        player.queue.load(broken.clone(), 0);
+        player.handle(&broken[0]).unwrap();
+        assert_eq!(current(&player), None);
+        assert!(!player.occupied.load(SeqCst));
+        assert_eq!(player.take_broken(), vec![broken[0].id(), broken[1].id()]);
+    }
 }
@@ -0,0 +1,150 @@
+use super::super::library::song::Song;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// A song left this close to its end counts as finished, its bookmark is dropped.
+const FINISHED_MARGIN: Duration = Duration::from_secs(10);
+
+/// Where to resume long songs from, like audiobooks or podcasts.
+#[derive(Debug, Default)]
+pub struct Bookmarks {
+    /// Songs at least this long are bookmarked.
+    threshold: Duration,
+    /// Songs under these directories are bookmarked whatever their length.
+    dirs: Vec<PathBuf>,
+    /// Keyed by `Song::id`.
+    positions: HashMap<PathBuf, Duration>,
+    /// Paths whose bookmark changed since the last `drain`.
+    changed: HashSet<PathBuf>,
+}
+
+impl Bookmarks {
+
+    #[inline]
+    pub fn new(threshold: Duration, dirs: Vec<PathBuf>) -> Self {
+        Bookmarks {
+            threshold,
+            dirs,
+            ..Default::default()
+        }
+    }
+
+    /// Take the bookmarks saved in the database.
+    #[inline]
+    pub fn load(&mut self, positions: HashMap<PathBuf, Duration>) {
+        self.positions = positions;
+        self.changed.clear();
+    }
+
+    /// Whether the position in `song` is worth remembering.
+    #[inline]
+    pub fn applies(&self, song: &Song) -> bool {
+        let path = song.path();
+        self.dirs.iter().any(|d| path.starts_with(d))
+            || matches!(duration(song), Some(d) if d >= self.threshold)
+    }
+
+    #[inline]
+    pub fn get(&self, song: &Song) -> Option<Duration> {
+        self.positions.get(&song.id()).copied()
+    }
+
+    /// Remember `pos` for `song`, or forget it once the song is about to end.
+    #[inline]
+    pub fn set(&mut self, song: &Song, pos: Duration) {
+        if !self.applies(song) {
+            return;
+        }
+        let finished = matches!(duration(song), Some(d) if pos + FINISHED_MARGIN >= d);
+        if finished || pos == Duration::from_secs(0) {
+            self.clear(song);
+        } else if self.get(song) != Some(pos) {
+            self.positions.insert(song.id(), pos);
+            self.changed.insert(song.id());
+        }
+    }
+
+    #[inline]
+    pub fn clear(&mut self, song: &Song) {
+        if self.positions.remove(&song.id()).is_some() {
+            self.changed.insert(song.id());
+        }
+    }
+
+    /// How far into `song` its bookmark is, between 0 and 1.
+    #[inline]
+    pub fn progress(&self, song: &Song) -> Option<f64> {
+        let pos = self.get(song)?;
+        let duration = duration(song).filter(|d| *d > Duration::from_secs(0))?;
+        Some((pos.as_secs_f64() / duration.as_secs_f64()).min(1.0))
+    }
+
+    /// The bookmarks changed since the last call, `None` for the removed ones.
+    #[inline]
+    pub fn drain(&mut self) -> Vec<(PathBuf, Option<Duration>)> {
+        let positions = &self.positions;
+        self.changed
+            .drain()
+            .map(|p| {
+                let pos = positions.get(&p).copied();
+                (p, pos)
+            })
+            .collect()
+    }
+}
+
+#[inline]
+fn duration(song: &Song) -> Option<Duration> {
+    song.metadata.duration.map(Duration::from_secs)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::app::model::library::song::Metadata;
+
+    fn song(path: &str, minutes: u64) -> Song {
+        let metadata = Metadata {
+            duration: Some(minutes * 60),
+            ..Default::default()
+        };
+        Song::with_metadata(path, metadata).unwrap()
+    }
+
+    #[test]
+    fn test_bookmarks() {
+        let mut bookmarks = Bookmarks::new(Duration::from_secs(20 * 60), vec![PathBuf::from("/podcasts")]);
+        let book = song("/books/book.mp3", 300);
+        let short = song("/music/single.mp3", 4);
+        let episode = song("/podcasts/episode.mp3", 4);
+
+        bookmarks.set(&book, Duration::from_secs(90));
+        bookmarks.set(&short, Duration::from_secs(90));
+        bookmarks.set(&episode, Duration::from_secs(90));
+        assert_eq!(bookmarks.get(&book), Some(Duration::from_secs(90)));
+        assert_eq!(bookmarks.get(&short), None);
+        assert_eq!(bookmarks.get(&episode), Some(Duration::from_secs(90)));
+        assert_eq!(bookmarks.progress(&episode), Some(90.0 / 240.0));
+
+        let mut changed = bookmarks.drain();
+        changed.sort();
+        assert_eq!(changed, vec![
+            (book.id(), Some(Duration::from_secs(90))),
+            (episode.id(), Some(Duration::from_secs(90))),
+        ]);
+        assert!(bookmarks.drain().is_empty());
+
+        // Close to its end the song counts as finished.
+        bookmarks.set(&episode, Duration::from_secs(235));
+        assert_eq!(bookmarks.get(&episode), None);
+        assert_eq!(bookmarks.drain(), vec![(episode.id(), None)]);
+
+        // What was saved comes back as it was.
+        let mut saved = Bookmarks::new(Duration::from_secs(20 * 60), vec![]);
+        saved.load(vec![(book.id(), Duration::from_secs(90))].into_iter().collect());
+        assert_eq!(saved.get(&book), Some(Duration::from_secs(90)));
+        assert!(saved.drain().is_empty());
+    }
+}
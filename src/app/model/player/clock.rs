@@ -0,0 +1,253 @@
+use rodio::{Sample, Source};
+use std::sync::Arc;
+use std::time::Duration;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering::Relaxed};
+
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+
+/// Playback position of a single song, driven by the samples that the sink
+/// actually pulled out of its source.
+#[derive(Debug, Default)]
+pub struct Clock {
+    /// Position in nanoseconds that `samples` are counted from.
+    base: AtomicU64,
+    /// Samples consumed since `base`, across all channels.
+    samples: AtomicU64,
+    /// Samples per second across all channels, zero until the first frame.
+    rate: AtomicU64,
+    /// Length of the song in nanoseconds, zero if the decoder can't tell.
+    total: AtomicU64,
+    /// Set once the sink pulled the first sample.
+    started: AtomicBool,
+    /// Set once the source ran out of samples.
+    ended: AtomicBool,
+    /// Makes the source end early without touching the rest of the sink.
+    cancelled: AtomicBool,
+    /// Length of the fade out in nanoseconds requested by the player, zero for none.
+    fade_out: AtomicU64,
+}
+
+impl Clock {
+
+    #[inline]
+    pub fn new(base: Duration, total: Option<Duration>) -> Self {
+        Clock {
+            base: AtomicU64::new(base.as_nanos() as u64),
+            total: AtomicU64::new(total.map(|t| t.as_nanos() as u64).unwrap_or(0)),
+            ..Default::default()
+        }
+    }
+
+    #[inline]
+    pub fn elapsed(&self) -> Duration {
+        let base = self.base.load(Relaxed) as u128;
+        let rate = self.rate.load(Relaxed) as u128;
+        if rate == 0 {
+            return Duration::from_nanos(base as u64);
+        }
+        let played = self.samples.load(Relaxed) as u128 * NANOS_PER_SEC / rate;
+        Duration::from_nanos((base + played) as u64)
+    }
+
+    #[inline]
+    pub fn total(&self) -> Option<Duration> {
+        match self.total.load(Relaxed) {
+            0 => None,
+            t => Some(Duration::from_nanos(t)),
+        }
+    }
+
+    #[inline]
+    pub fn started(&self) -> bool {
+        self.started.load(Relaxed)
+    }
+
+    #[inline]
+    pub fn ended(&self) -> bool {
+        self.ended.load(Relaxed)
+    }
+
+    #[inline]
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Relaxed);
+    }
+
+    /// Fade the source out over `length` from the next sample on, the source
+    /// ends once it is silent.
+    #[inline]
+    pub fn fade_out(&self, length: Duration) {
+        self.fade_out.store((length.as_nanos() as u64).max(1), Relaxed);
+    }
+
+    /// Count from `base` again, when the source jumps back to it.
+    #[inline]
+    pub fn rewind(&self, base: Duration) {
+        self.samples.store(0, Relaxed);
+        self.base.store(base.as_nanos() as u64, Relaxed);
+    }
+
+    /// Fold the samples counted so far into `base` when the sample rate or
+    /// the channel count of the source changes.
+    #[inline]
+    fn retime(&self, rate: u64) {
+        let current = self.rate.load(Relaxed);
+        if current == rate {
+            return;
+        }
+        if current != 0 {
+            let played = self.samples.swap(0, Relaxed) as u128 * NANOS_PER_SEC / current as u128;
+            self.base.fetch_add(played as u64, Relaxed);
+        }
+        self.rate.store(rate, Relaxed);
+    }
+}
+
+/// Source that counts every sample pulled out of `inner` into a `Clock`.
+#[derive(Debug)]
+pub struct Tracked<S> {
+    inner: S,
+    clock: Arc<Clock>,
+    remaining: usize,
+    /// Samples left and total samples of the fade out, once it began.
+    fading: Option<(u64, u64)>,
+}
+
+impl<S> Tracked<S>
+where
+    S: Source,
+    S::Item: Sample,
+{
+    #[inline]
+    pub fn new(inner: S, clock: Arc<Clock>) -> Self {
+        Tracked {
+            inner,
+            clock,
+            remaining: 0,
+            fading: None,
+        }
+    }
+}
+
+impl<S> Iterator for Tracked<S>
+where
+    S: Source,
+    S::Item: Sample,
+{
+    type Item = S::Item;
+
+    #[inline]
+    fn next(&mut self) -> Option<S::Item> {
+        if self.remaining == 0 {
+            let rate = self.inner.sample_rate() as u64 * self.inner.channels() as u64;
+            self.clock.retime(rate);
+            self.remaining = self.inner.current_frame_len().unwrap_or(usize::MAX);
+        }
+        if self.clock.cancelled.load(Relaxed) {
+            return None;
+        }
+        if self.fading.is_none() {
+            let length = self.clock.fade_out.load(Relaxed);
+            if length != 0 {
+                let rate = self.clock.rate.load(Relaxed) as u128;
+                let total = (length as u128 * rate / NANOS_PER_SEC).max(1) as u64;
+                self.fading = Some((total, total));
+            }
+        }
+        if let Some((0, _)) = self.fading {
+            self.clock.ended.store(true, Relaxed);
+            return None;
+        }
+        match self.inner.next() {
+            Some(sample) => {
+                self.remaining = self.remaining.saturating_sub(1);
+                if self.clock.samples.fetch_add(1, Relaxed) == 0 {
+                    self.clock.started.store(true, Relaxed);
+                }
+                match self.fading.as_mut() {
+                    Some((left, total)) => {
+                        let gain = *left as f32 / *total as f32;
+                        *left -= 1;
+                        Some(sample.amplify(gain))
+                    }
+                    None => Some(sample),
+                }
+            }
+            None => {
+                self.clock.ended.store(true, Relaxed);
+                None
+            }
+        }
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<S> Source for Tracked<S>
+where
+    S: Source,
+    S::Item: Sample,
+{
+    #[inline]
+    fn current_frame_len(&self) -> Option<usize> {
+        self.inner.current_frame_len()
+    }
+
+    #[inline]
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    #[inline]
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    #[inline]
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use rodio::buffer::SamplesBuffer;
+
+    /// Two seconds of stereo at 10 Hz.
+    fn tracked(clock: &Arc<Clock>) -> Tracked<SamplesBuffer<f32>> {
+        Tracked::new(SamplesBuffer::new(2, 10, vec![0.0; 40]), clock.clone())
+    }
+
+    #[test]
+    fn test_position() {
+        let clock = Arc::new(Clock::new(Duration::from_secs(0), Some(Duration::from_secs(2))));
+        let mut source = tracked(&clock);
+        assert!(!clock.started());
+        assert_eq!(clock.elapsed(), Duration::from_secs(0));
+        assert_eq!(source.by_ref().take(10).count(), 10);
+        assert!(clock.started());
+        assert_eq!(clock.elapsed(), Duration::from_millis(500));
+        // Paused, the sink pulls nothing and the position stays where it is.
+        assert_eq!(clock.elapsed(), Duration::from_millis(500));
+        assert_eq!(source.by_ref().take(2).count(), 2);
+        assert_eq!(clock.elapsed(), Duration::from_millis(600));
+
+        // A seek starts over with a clock counting from the new position.
+        let clock = Arc::new(Clock::new(Duration::from_millis(1500), Some(Duration::from_secs(2))));
+        let mut source = tracked(&clock);
+        assert_eq!(clock.elapsed(), Duration::from_millis(1500));
+        assert_eq!(source.by_ref().take(4).count(), 4);
+        assert_eq!(clock.elapsed(), Duration::from_millis(1700));
+        // So does the A-B loop going around.
+        clock.rewind(Duration::from_secs(1));
+        assert_eq!(source.by_ref().take(2).count(), 2);
+        assert_eq!(clock.elapsed(), Duration::from_millis(1100));
+        assert!(!clock.ended());
+        assert_eq!(source.count(), 34);
+        assert!(clock.ended());
+    }
+}
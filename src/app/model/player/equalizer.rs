@@ -0,0 +1,341 @@
+use crate::config::Equalizer as EqualizerConfig;
+use crate::error::{Result, anyhow, NonexistentEqualizerPreset, InvalidEqualizerBands};
+use parking_lot::Mutex;
+use rodio::{Sample, Source};
+use std::f32::consts::PI;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+use std::time::Duration;
+
+/// Center frequencies of the bands, in Hz.
+pub const FREQUENCIES: [f32; 10] = [
+    31.0, 62.0, 125.0, 250.0, 500.0, 1000.0, 2000.0, 4000.0, 8000.0, 16000.0,
+];
+
+/// Gains of the bands are kept within ±`MAX_GAIN` dB.
+pub const MAX_GAIN: f32 = 12.0;
+
+/// Quality factor of a band about one octave wide.
+const Q: f32 = 1.41;
+
+/// How many samples are played before the gains are checked for updates.
+const REFRESH_PERIOD: usize = 1024;
+
+pub const BUILT_IN_PRESETS: [(&str, [f32; 10]); 8] = [
+    ("Flat", [0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0]),
+    ("Rock", [5.0, 4.0, 3.0, 1.0, -1.0, -1.0, 1.0, 3.0, 4.0, 5.0]),
+    ("Pop", [-1.0, 1.0, 3.0, 4.0, 3.0, 0.0, -1.0, -1.0, 1.0, 2.0]),
+    ("Jazz", [3.0, 2.0, 1.0, 2.0, -1.0, -1.0, 0.0, 1.0, 2.0, 3.0]),
+    ("Classical", [4.0, 3.0, 2.0, 1.0, -1.0, -1.0, 0.0, 2.0, 3.0, 4.0]),
+    ("Bass", [6.0, 5.0, 4.0, 2.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0]),
+    ("Treble", [0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 2.0, 4.0, 5.0, 6.0]),
+    ("Vocal", [-2.0, -3.0, -2.0, 1.0, 3.0, 4.0, 3.0, 1.0, 0.0, -1.0]),
+];
+
+/// Gains shared between the player and every source it feeds to the sink.
+#[derive(Debug, Default)]
+pub struct Bands {
+    gains: Mutex<[f32; 10]>,
+    /// Bumped on every change so that the sources know when to recompute their filters.
+    version: AtomicU64,
+}
+
+impl Bands {
+
+    #[inline]
+    pub fn gains(&self) -> [f32; 10] {
+        *self.gains.lock()
+    }
+
+    #[inline]
+    fn set(&self, gains: [f32; 10]) {
+        *self.gains.lock() = gains;
+        self.version.fetch_add(1, Relaxed);
+    }
+}
+
+/// The presets and the gains currently applied.
+#[derive(Debug, Default)]
+pub struct Equalizer {
+    presets: Vec<(String, [f32; 10])>,
+    /// Index of the active preset, `None` once the bands were edited by hand.
+    preset: Option<usize>,
+    pub bands: Arc<Bands>,
+}
+
+impl Equalizer {
+
+    /// Built-in presets followed by the ones defined in the configuration.
+    #[inline]
+    pub fn new(config: &EqualizerConfig) -> Result<Self> {
+        let mut presets = BUILT_IN_PRESETS
+            .iter()
+            .map(|(name, gains)| (name.to_string(), *gains))
+            .collect::<Vec<_>>();
+        if let Some(custom) = config.presets.as_ref() {
+            let mut custom = custom
+                .iter()
+                .map(|(name, gains)| Ok((name.clone(), to_gains(gains)?)))
+                .collect::<Result<Vec<_>>>()?;
+            custom.sort_by(|a, b| a.0.cmp(&b.0));
+            presets.extend(custom);
+        }
+
+        let mut equalizer = Equalizer {
+            presets,
+            ..Default::default()
+        };
+        match (config.preset.as_ref(), config.bands.as_ref()) {
+            (Some(name), _) => {
+                let idx = equalizer
+                    .presets
+                    .iter()
+                    .position(|(n, _)| n == name)
+                    .ok_or_else(|| anyhow!(NonexistentEqualizerPreset(name.clone())))?;
+                equalizer.select(idx);
+            }
+            (None, Some(bands)) => equalizer.bands.set(to_gains(bands)?),
+            (None, None) => equalizer.select(0),
+        }
+        Ok(equalizer)
+    }
+
+    /// Name of the active preset, `Custom` once the bands were edited by hand.
+    #[inline]
+    pub fn name(&self) -> &str {
+        self.preset.map(|i| self.presets[i].0.as_str()).unwrap_or("Custom")
+    }
+
+    #[inline]
+    pub fn gains(&self) -> [f32; 10] {
+        self.bands.gains()
+    }
+
+    #[inline]
+    pub fn next_preset(&mut self) {
+        let idx = self.preset.map(|i| (i + 1) % self.presets.len()).unwrap_or(0);
+        self.select(idx);
+    }
+
+    /// Raise or lower the gain of `band` by `delta` dB.
+    #[inline]
+    pub fn adjust(&mut self, band: usize, delta: f32) {
+        let mut gains = self.gains();
+        gains[band] = (gains[band] + delta).clamp(-MAX_GAIN, MAX_GAIN);
+        self.bands.set(gains);
+        self.preset = None;
+    }
+
+    #[inline]
+    fn select(&mut self, idx: usize) {
+        self.bands.set(self.presets[idx].1);
+        self.preset = Some(idx);
+    }
+}
+
+#[inline]
+fn to_gains(values: &[f32]) -> Result<[f32; 10]> {
+    if values.len() != 10 || values.iter().any(|g| g.abs() > MAX_GAIN) {
+        return Err(anyhow!(InvalidEqualizerBands));
+    }
+    let mut gains = [0.0; 10];
+    gains.copy_from_slice(values);
+    Ok(gains)
+}
+
+/// Peaking filter of the Audio EQ Cookbook, in direct form I.
+#[derive(Debug, Clone, Copy, Default)]
+struct Peaking {
+    b: [f32; 3],
+    a: [f32; 2],
+    x: [f32; 2],
+    y: [f32; 2],
+}
+
+impl Peaking {
+
+    #[inline]
+    fn new(frequency: f32, gain: f32, rate: u32) -> Self {
+        let a = 10f32.powf(gain / 40.0);
+        let w0 = 2.0 * PI * frequency / rate as f32;
+        let alpha = w0.sin() / (2.0 * Q);
+        let a0 = 1.0 + alpha / a;
+        Peaking {
+            b: [
+                (1.0 + alpha * a) / a0,
+                -2.0 * w0.cos() / a0,
+                (1.0 - alpha * a) / a0,
+            ],
+            a: [-2.0 * w0.cos() / a0, (1.0 - alpha / a) / a0],
+            ..Default::default()
+        }
+    }
+
+    #[inline]
+    fn process(&mut self, x: f32) -> f32 {
+        let y = self.b[0] * x + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[0] * self.y[0]
+            - self.a[1] * self.y[1];
+        self.x = [x, self.x[0]];
+        self.y = [y, self.y[0]];
+        y
+    }
+}
+
+/// Source running `inner` through the bands of the equalizer.
+#[derive(Debug)]
+pub struct Equalized<S> {
+    inner: S,
+    bands: Arc<Bands>,
+    version: u64,
+    rate: u32,
+    channels: u16,
+    /// Filters of each channel, bands that would have no effect are left out.
+    filters: Vec<Vec<Peaking>>,
+    /// Amplitude factor applied ahead of the filters, which lowers the signal
+    /// by the largest boost so that the boosted band doesn't clip.
+    preamp: f32,
+    channel: usize,
+    countdown: usize,
+}
+
+impl<S> Equalized<S>
+where
+    S: Source,
+    S::Item: Sample,
+{
+    #[inline]
+    pub fn new(inner: S, bands: Arc<Bands>) -> Self {
+        Equalized {
+            inner,
+            bands,
+            version: u64::MAX,
+            rate: 0,
+            channels: 0,
+            filters: Vec::new(),
+            preamp: 1.0,
+            channel: 0,
+            countdown: 0,
+        }
+    }
+
+    /// Rebuild the filters if the gains, the sample rate or the channel count changed.
+    /// The state of the filters is lost, which is inaudible as long as it's rare.
+    #[inline]
+    fn refresh(&mut self) {
+        let version = self.bands.version.load(Relaxed);
+        let rate = self.inner.sample_rate();
+        let channels = self.inner.channels();
+        if version == self.version && rate == self.rate && channels == self.channels {
+            return;
+        }
+        let gains = self.bands.gains();
+        let filters = FREQUENCIES
+            .iter()
+            .zip(gains.iter())
+            .filter(|(&f, &g)| g != 0.0 && f < rate as f32 / 2.0)
+            .map(|(&f, &g)| (Peaking::new(f, g, rate), g))
+            .collect::<Vec<_>>();
+        let boost = filters.iter().fold(0.0f32, |b, (_, g)| b.max(*g));
+        self.preamp = 10f32.powf(-boost / 20.0);
+        self.filters = vec![filters.into_iter().map(|(f, _)| f).collect(); channels as usize];
+        self.version = version;
+        self.rate = rate;
+        self.channels = channels;
+        self.channel = 0;
+    }
+}
+
+impl<S> Iterator for Equalized<S>
+where
+    S: Source,
+    S::Item: Sample,
+{
+    type Item = f32;
+
+    #[inline]
+    fn next(&mut self) -> Option<f32> {
+        if self.countdown == 0 && self.channel == 0 {
+            self.refresh();
+            self.countdown = REFRESH_PERIOD;
+        }
+        let mut sample = self.inner.next()?.to_f32() * self.preamp;
+        if let Some(filters) = self.filters.get_mut(self.channel) {
+            for filter in filters {
+                sample = filter.process(sample);
+            }
+        }
+        self.countdown = self.countdown.saturating_sub(1);
+        self.channel = (self.channel + 1) % self.channels.max(1) as usize;
+        Some(sample)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<S> Source for Equalized<S>
+where
+    S: Source,
+    S::Item: Sample,
+{
+    #[inline]
+    fn current_frame_len(&self) -> Option<usize> {
+        self.inner.current_frame_len()
+    }
+
+    #[inline]
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    #[inline]
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    #[inline]
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use rodio::buffer::SamplesBuffer;
+
+    fn peak(filter: &mut Peaking, frequency: f32) -> f32 {
+        (0..48000)
+            .map(|n| filter.process((2.0 * PI * frequency * n as f32 / 48000.0).sin()))
+            .skip(24000)
+            .fold(0.0, |p, y| p.max(y.abs()))
+    }
+
+    #[test]
+    fn test_peaking_gain() {
+        // +6 dB and -6 dB at the center frequency double and halve the amplitude.
+        assert!((peak(&mut Peaking::new(1000.0, 6.0, 48000), 1000.0) - 2.0).abs() < 0.01);
+        assert!((peak(&mut Peaking::new(1000.0, -6.0, 48000), 1000.0) - 0.5).abs() < 0.01);
+        // Far away from the center frequency the signal is left alone.
+        assert!((peak(&mut Peaking::new(8000.0, 6.0, 48000), 125.0) - 1.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_headroom() {
+        let sine = (0..48000).map(|n| (2.0 * PI * 1000.0 * n as f32 / 48000.0).sin()).collect::<Vec<_>>();
+        let bands = Arc::new(Bands::default());
+        let mut gains = [0.0; 10];
+        gains[5] = 6.0;
+        gains[9] = -6.0;
+        bands.set(gains);
+        // The boosted band comes out as loud as it went in, the rest is lowered.
+        let equalized = Equalized::new(SamplesBuffer::new(1, 48000, sine), bands);
+        let peak = equalized.skip(24000).fold(0.0f32, |p, y| p.max(y.abs()));
+        assert!((peak - 1.0).abs() < 0.01);
+    }
+}
+
@@ -0,0 +1,336 @@
+use crate::DEBUG;
+use crate::error::{Result, anyhow, NonexistentOutput, MissingOutputFile, OutputFileFull, NonexistentDevice, NoDevice};
+use rodio::{Sink, OutputStream, OutputStreamHandle};
+use rodio::cpal::{self, Device};
+use rodio::cpal::traits::{DeviceTrait, HostTrait};
+use rodio::dynamic_mixer::{self, DynamicMixerController};
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering::Relaxed};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use parking_lot::Mutex;
+use log::trace;
+
+pub const OUTPUTS: [&str; 3] = ["device", "null", "file"];
+
+/// Format the detached outputs render to.
+const CHANNELS: u16 = 2;
+const SAMPLE_RATE: u32 = 44100;
+
+/// Frames rendered at once by the detached outputs, 10 ms worth.
+const CHUNK: u32 = SAMPLE_RATE / 100;
+
+/// Bytes of a frame in the output file.
+const BLOCK_ALIGN: u16 = CHANNELS * 2;
+
+/// Most bytes of samples a WAV file can hold, its sizes are 32 bits.
+const MAX_DATA: u32 = (u32::MAX - 36) / BLOCK_ALIGN as u32 * BLOCK_ALIGN as u32;
+
+/// Where the sinks of the player end up.
+pub enum Output {
+    /// An audio device, the default one unless `pinned`.
+    Device {
+        _stream: OutputStream,
+        handle: OutputStreamHandle,
+        name: String,
+        pinned: bool,
+    },
+    /// The sinks are mixed by a thread of our own at the pace of a real device,
+    /// the samples are then dropped or written to a WAV file.
+    Detached {
+        controller: Arc<DynamicMixerController<f32>>,
+        stopped: Arc<AtomicBool>,
+        renderer: Option<JoinHandle<()>>,
+        /// Why the output file stopped being written, until it's taken.
+        failure: Arc<Mutex<Option<String>>>,
+    },
+}
+
+impl Output {
+
+    /// Open the output named `name`, one of `OUTPUTS`. The device output plays
+    /// to `device`, or the default device, the file output writes to `file`.
+    #[inline]
+    pub fn new(name: &str, device: Option<&str>, file: Option<&str>) -> Result<Self> {
+        match name {
+            "device" => Self::device(device),
+            "null" => Ok(Self::null()),
+            "file" => {
+                let file = file.ok_or_else(|| anyhow!(MissingOutputFile))?;
+                Ok(Self::detached(Some(Wav::create(file)?)))
+            }
+            _ => Err(anyhow!(NonexistentOutput(name.into()))),
+        }
+    }
+
+    #[inline]
+    fn device(name: Option<&str>) -> Result<Self> {
+        let host = cpal::default_host();
+        let device = match name {
+            Some(name) => host
+                .output_devices()?
+                .find(|d| d.name().map(|n| n == name).unwrap_or(false))
+                .ok_or_else(|| anyhow!(NonexistentDevice(name.into())))?,
+            None => host.default_output_device().ok_or_else(|| anyhow!(NoDevice))?,
+        };
+        let (_stream, handle) = OutputStream::try_from_device(&device)?;
+        if DEBUG.load(Relaxed) { trace!("Open the output device {:?}.", device.name()); }
+        Ok(Output::Device {
+            _stream,
+            handle,
+            name: device.name()?,
+            pinned: name.is_some(),
+        })
+    }
+
+    /// Play to nowhere, for when there is no device at all.
+    #[inline]
+    pub fn null() -> Self {
+        Self::detached(None)
+    }
+
+    #[inline]
+    fn detached(mut wav: Option<Wav>) -> Self {
+        let (controller, mut mixer) = dynamic_mixer::mixer::<f32>(CHANNELS, SAMPLE_RATE);
+        let stopped = Arc::new(AtomicBool::new(false));
+        let failure = Arc::new(Mutex::new(None));
+        let (stop, failed) = (stopped.clone(), failure.clone());
+        let renderer = thread::spawn(move || {
+            let start = Instant::now();
+            let mut rendered = 0u64;
+            let mut chunk = Vec::with_capacity((CHUNK * CHANNELS as u32) as usize);
+            while !stop.load(Relaxed) {
+                chunk.clear();
+                // The mixer runs dry when no sink is playing, that's silence.
+                chunk.extend((0..CHUNK * CHANNELS as u32).map(|_| mixer.next().unwrap_or(0.0)));
+                if let Err(e) = wav.as_mut().map_or(Ok(()), |w| w.write(&chunk)) {
+                    if DEBUG.load(Relaxed) { trace!("Unable to write the output file: {}", e); }
+                    *failed.lock() = Some(e.to_string());
+                    // What was written so far is kept, the mixing goes on.
+                    finish(wav.take());
+                }
+                rendered += CHUNK as u64;
+                let due = start + Duration::from_secs_f64(rendered as f64 / SAMPLE_RATE as f64);
+                if let Some(wait) = due.checked_duration_since(Instant::now()) {
+                    thread::sleep(wait);
+                }
+            }
+            finish(wav);
+        });
+
+        Output::Detached {
+            controller,
+            stopped,
+            renderer: Some(renderer),
+            failure,
+        }
+    }
+
+    /// Why the output file stopped being written, once.
+    #[inline]
+    pub fn take_failure(&self) -> Option<String> {
+        match self {
+            Output::Device { .. } => None,
+            Output::Detached { failure, .. } => failure.lock().take(),
+        }
+    }
+
+    /// Name of the device played to, `None` for the detached outputs.
+    #[inline]
+    pub fn device_name(&self) -> Option<&str> {
+        match self {
+            Output::Device { name, .. } => Some(name),
+            Output::Detached { .. } => None,
+        }
+    }
+
+    /// Name of the device played to and whether it was chosen, for `lost`
+    /// to check. `None` for the detached outputs, which can't go away.
+    #[inline]
+    pub fn watched(&self) -> Option<(String, bool)> {
+        match self {
+            Output::Device { name, pinned, .. } => Some((name.clone(), *pinned)),
+            Output::Detached { .. } => None,
+        }
+    }
+
+    /// A new sink playing to this output.
+    #[inline]
+    pub fn sink(&self) -> Result<Sink> {
+        match self {
+            Output::Device { handle, .. } => Ok(Sink::try_new(handle)?),
+            Output::Detached { controller, .. } => {
+                let (sink, queue) = Sink::new_idle();
+                controller.add(queue);
+                Ok(sink)
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Output {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Output::Device { name, .. } => write!(f, "Output::Device({})", name),
+            Output::Detached { .. } => write!(f, "Output::Detached"),
+        }
+    }
+}
+
+impl Drop for Output {
+    #[inline]
+    fn drop(&mut self) {
+        if let Output::Detached { stopped, renderer, .. } = self {
+            stopped.store(true, Relaxed);
+            if let Some(renderer) = renderer.take() {
+                let _ = renderer.join();
+            }
+        }
+    }
+}
+
+/// Names of the output devices plugged in right now.
+#[inline]
+pub fn devices() -> Vec<String> {
+    try_devices().unwrap_or_default()
+}
+
+/// Like `devices`, `None` if they can't be listed.
+#[inline]
+fn try_devices() -> Option<Vec<String>> {
+    let devices = cpal::default_host().output_devices().ok()?;
+    Some(devices.filter_map(|d| d.name().ok()).collect())
+}
+
+/// Whether the device `name` went away: unplugged if it was chosen, not the
+/// default anymore otherwise. `None` if the devices can't be listed right now.
+/// Slow on some hosts, better called off the UI thread.
+#[inline]
+pub fn lost(name: &str, pinned: bool) -> Option<bool> {
+    if pinned {
+        try_devices().map(|devices| !devices.iter().any(|d| d == name))
+    } else {
+        Some(default_device().as_deref() != Some(name))
+    }
+}
+
+/// Name of the default output device, if there is one.
+#[inline]
+fn default_device() -> Option<String> {
+    cpal::default_host()
+        .default_output_device()
+        .and_then(|d: Device| d.name().ok())
+}
+
+/// Fill in the header of the output file, if there is one.
+#[inline]
+fn finish(wav: Option<Wav>) {
+    if let Some(Err(e)) = wav.map(Wav::finish) {
+        if DEBUG.load(Relaxed) { trace!("Unable to finish the output file: {}", e); }
+    }
+}
+
+/// 16-bit PCM WAV writer, the sizes in the header are filled in by `finish`.
+struct Wav {
+    writer: BufWriter<File>,
+    len: u32,
+}
+
+impl Wav {
+
+    #[inline]
+    fn create(path: impl AsRef<Path>) -> Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(b"RIFF")?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        writer.write_all(b"WAVEfmt ")?;
+        writer.write_all(&16u32.to_le_bytes())?;
+        writer.write_all(&1u16.to_le_bytes())?;
+        writer.write_all(&CHANNELS.to_le_bytes())?;
+        writer.write_all(&SAMPLE_RATE.to_le_bytes())?;
+        writer.write_all(&(SAMPLE_RATE * BLOCK_ALIGN as u32).to_le_bytes())?;
+        writer.write_all(&BLOCK_ALIGN.to_le_bytes())?;
+        writer.write_all(&16u16.to_le_bytes())?;
+        writer.write_all(b"data")?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        Ok(Wav { writer, len: 0 })
+    }
+
+    /// Append `samples`, an error once the file can't hold them anymore.
+    #[inline]
+    fn write(&mut self, samples: &[f32]) -> Result<()> {
+        let len = u32::try_from(samples.len() * 2)
+            .ok()
+            .and_then(|bytes| self.len.checked_add(bytes))
+            .filter(|len| *len <= MAX_DATA)
+            .ok_or_else(|| anyhow!(OutputFileFull))?;
+        for &sample in samples {
+            let sample = (sample.max(-1.0).min(1.0) * i16::MAX as f32) as i16;
+            self.writer.write_all(&sample.to_le_bytes())?;
+        }
+        self.len = len;
+        Ok(())
+    }
+
+    #[inline]
+    fn finish(mut self) -> Result<()> {
+        self.writer.seek(SeekFrom::Start(4))?;
+        self.writer.write_all(&(36 + self.len).to_le_bytes())?;
+        self.writer.seek(SeekFrom::Start(40))?;
+        self.writer.write_all(&self.len.to_le_bytes())?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use rodio::Source;
+    use rodio::source::SineWave;
+    use std::fs;
+
+    #[test]
+    fn test_file_output() {
+        let path = std::env::temp_dir().join("ultra_test_file_output.wav");
+        let output = Output::new("file", None, path.to_str()).unwrap();
+        let sink = output.sink().unwrap();
+        sink.append(SineWave::new(440).take_duration(Duration::from_millis(100)));
+        thread::sleep(Duration::from_millis(300));
+        drop(sink);
+        drop(output);
+
+        let wav = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let len = u32::from_le_bytes([wav[40], wav[41], wav[42], wav[43]]) as usize;
+        assert_eq!(&wav[..4], b"RIFF");
+        assert_eq!(&wav[8..16], b"WAVEfmt ");
+        assert_eq!(wav.len(), 44 + len);
+        // Rendered in real time, so roughly 300 ms went through the output.
+        assert!(len >= SAMPLE_RATE as usize / 10 * 4);
+        // The sine was played, not only silence.
+        assert!(wav[44..].chunks(2).any(|s| i16::from_le_bytes([s[0], s[1]]).abs() > 1000));
+    }
+
+    #[test]
+    fn test_wav_limit() {
+        let path = std::env::temp_dir().join("ultra_test_wav_limit.wav");
+        let mut wav = Wav::create(&path).unwrap();
+        wav.len = MAX_DATA - BLOCK_ALIGN as u32;
+        wav.write(&[0.0, 0.0]).unwrap();
+        // A full file refuses more samples instead of wrapping its size around.
+        assert!(wav.write(&[0.0, 0.0]).is_err());
+        wav.finish().unwrap();
+
+        let header = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(u32::from_le_bytes([header[4], header[5], header[6], header[7]]), 36 + MAX_DATA);
+        assert_eq!(u32::from_le_bytes([header[40], header[41], header[42], header[43]]), MAX_DATA);
+    }
+}
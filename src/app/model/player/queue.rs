@@ -0,0 +1,216 @@
+use super::Mode;
+use super::super::library::song::Song;
+use rand::seq::SliceRandom;
+
+/// Songs waiting to be played, in the order they were shown on the board.
+#[derive(Debug, Default)]
+pub struct Queue {
+    songs: Vec<Song>,
+    cursor: Option<usize>,
+    /// Song picked by `peek`, `next` sticks to it when the current song ends by itself.
+    upcoming: Option<usize>,
+    /// Songs not yet played in `Mode::Random`, in shuffled order, the next one last.
+    bag: Vec<usize>,
+    /// Songs played in `Mode::Random`, the latest last, for `previous` to walk back.
+    trail: Vec<usize>,
+}
+
+impl Queue {
+
+    /// Replace the queue with `songs` and point the cursor at `cursor`.
+    #[inline]
+    pub fn load(&mut self, songs: Vec<Song>, cursor: usize) {
+        self.cursor = if cursor < songs.len() { Some(cursor) } else { None };
+        self.upcoming = None;
+        self.songs = songs;
+        self.reshuffle();
+    }
+
+    /// Follow the board after its contents changed, as long as the current
+    /// song is still there. Returns whether the order changed, songs are told
+    /// apart by `Song::id` since their flags and analysis change as they play.
+    #[inline]
+    pub fn update(&mut self, songs: Vec<Song>) -> bool {
+        if self.songs.len() == songs.len() && self.songs.iter().zip(&songs).all(|(a, b)| a.id() == b.id()) {
+            self.songs = songs;
+            return false;
+        }
+        match self.current().and_then(|c| songs.iter().position(|s| s.id() == c.id())) {
+            Some(cursor) => {
+                self.load(songs, cursor);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Start a new shuffle order, the songs played so far are forgotten.
+    #[inline]
+    pub fn reshuffle(&mut self) {
+        self.bag.clear();
+        self.trail.clear();
+    }
+
+    #[inline]
+    pub fn current(&self) -> Option<&Song> {
+        self.cursor.map(|i| &self.songs[i])
+    }
+
+    #[inline]
+    pub fn songs(&self) -> &[Song] {
+        &self.songs
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.songs.is_empty()
+    }
+
+    /// The song that will be played once the current one ends by itself.
+    #[inline]
+    pub fn peek(&mut self, mode: Mode) -> Option<&Song> {
+        if self.upcoming.is_none() {
+            self.upcoming = self.successor(mode, true);
+        }
+        self.upcoming.map(move |i| &self.songs[i])
+    }
+
+    /// Drop the song picked by `peek`, e.g. after the play mode changed.
+    #[inline]
+    pub fn forget(&mut self) {
+        self.upcoming = None;
+    }
+
+    /// Move to the song that should be played after the current one.
+    ///
+    /// `natural` tells whether the current song reached its end by itself,
+    /// only then `Mode::SingleCycle` keeps the cursor where it is.
+    /// `None` at the end of the list in `Mode::RepeatOff`, the cursor stays put then.
+    #[inline]
+    pub fn next(&mut self, mode: Mode, natural: bool) -> Option<&Song> {
+        let target = match self.upcoming.take() {
+            Some(upcoming) if natural => upcoming,
+            _ => self.successor(mode, natural)?,
+        };
+        if mode == Mode::Random {
+            self.bag.retain(|&i| i != target);
+            self.trail.extend(self.cursor);
+        }
+        self.cursor = Some(target);
+        self.current()
+    }
+
+    /// Move to the song before the current one, in `Mode::Random` that's the
+    /// song played before it.
+    #[inline]
+    pub fn previous(&mut self, mode: Mode) -> Option<&Song> {
+        let len = self.songs.len();
+        let current = self.cursor?;
+        let target = match mode {
+            Mode::Random => {
+                let target = self.trail.pop()?;
+                // Going forward again replays the same order.
+                self.bag.push(current);
+                target
+            }
+            Mode::RepeatOff if current == 0 => return None,
+            _ if current == 0 => len - 1,
+            _ => current - 1,
+        };
+        self.upcoming = None;
+        self.cursor = Some(target);
+        self.current()
+    }
+
+    #[inline]
+    fn successor(&mut self, mode: Mode, natural: bool) -> Option<usize> {
+        let len = self.songs.len();
+        let current = self.cursor?;
+        match mode {
+            Mode::SingleCycle if natural => Some(current),
+            Mode::RepeatAll | Mode::SingleCycle => Some((current + 1) % len),
+            Mode::RepeatOff => Some(current + 1).filter(|&i| i < len),
+            Mode::Random => {
+                if self.bag.is_empty() {
+                    self.refill(current);
+                }
+                Some(self.bag.last().copied().unwrap_or(current))
+            }
+        }
+    }
+
+    /// Shuffle every song but the current one into the bag.
+    #[inline]
+    fn refill(&mut self, current: usize) {
+        self.bag = (0..self.songs.len()).filter(|&i| i != current).collect();
+        self.bag.shuffle(&mut rand::thread_rng());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn songs(names: &[&str]) -> Vec<Song> {
+        names.iter().map(|n| Song::with_metadata(format!("/music/{}.flac", n), Default::default()).unwrap()).collect()
+    }
+
+    fn name(song: Option<&Song>) -> Option<String> {
+        song.map(|s| s.path().file_stem().unwrap().to_string_lossy().into_owned())
+    }
+
+    #[test]
+    fn test_shuffle() {
+        let mut queue = Queue::default();
+        queue.load(songs(&["a", "b", "c", "d", "e"]), 0);
+        // Every other song comes once before any of them repeats.
+        let mut played = (0..4).map(|_| name(queue.next(Mode::Random, true)).unwrap()).collect::<Vec<_>>();
+        played.sort();
+        assert_eq!(played, ["b", "c", "d", "e"]);
+        assert!(queue.next(Mode::Random, true).is_some());
+    }
+
+    #[test]
+    fn test_shuffle_back() {
+        let mut queue = Queue::default();
+        queue.load(songs(&["a", "b", "c", "d", "e"]), 0);
+        let played = (0..3).map(|_| name(queue.next(Mode::Random, false))).collect::<Vec<_>>();
+        // Back through the order they were played in, then forward along it again.
+        assert_eq!(name(queue.previous(Mode::Random)), played[1]);
+        assert_eq!(name(queue.previous(Mode::Random)), played[0]);
+        assert_eq!(name(queue.previous(Mode::Random)).as_deref(), Some("a"));
+        assert_eq!(name(queue.previous(Mode::Random)), None);
+        let again = (0..3).map(|_| name(queue.next(Mode::Random, false))).collect::<Vec<_>>();
+        assert_eq!(again, played);
+    }
+
+    #[test]
+    fn test_repeat_off() {
+        let mut queue = Queue::default();
+        queue.load(songs(&["a", "b"]), 0);
+        assert_eq!(name(queue.previous(Mode::RepeatOff)), None);
+        assert_eq!(name(queue.next(Mode::RepeatOff, true)).as_deref(), Some("b"));
+        assert_eq!(name(queue.next(Mode::RepeatOff, true)), None);
+        assert_eq!(name(queue.current()).as_deref(), Some("b"));
+        assert_eq!(name(queue.next(Mode::RepeatAll, true)).as_deref(), Some("a"));
+    }
+
+    #[test]
+    fn test_update() {
+        let mut queue = Queue::default();
+        queue.load(songs(&["a", "b", "c"]), 1);
+        assert!(!queue.update(songs(&["a", "b", "c"])));
+        // A song that turned out to be broken is still the same song.
+        let mut flagged = songs(&["a", "b", "c"]);
+        flagged[1].broken = true;
+        assert!(!queue.update(flagged));
+        assert!(queue.current().unwrap().broken);
+        assert!(queue.update(songs(&["c", "b", "a"])));
+        assert_eq!(queue.cursor, Some(1));
+        assert_eq!(name(queue.next(Mode::RepeatAll, false)).as_deref(), Some("a"));
+        // The current song is gone from the board, the queue stays as it was.
+        assert!(!queue.update(songs(&["b", "c"])));
+        assert_eq!(name(queue.current()).as_deref(), Some("a"));
+    }
+}
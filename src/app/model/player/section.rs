@@ -0,0 +1,338 @@
+use super::clock::Clock;
+use super::super::library::decode::Seekable;
+use super::super::library::song::Track;
+use crate::error::Result;
+use rodio::{Sample, Source};
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Source repeating the section of a song between two points forever, used by
+/// the A-B loop. The song is streamed and sought back to `a` every time it
+/// reaches `b`, so that a long section takes no more memory than a song.
+pub struct Section<S>
+where
+    S: Seekable,
+    S::Item: Sample,
+{
+    inner: S,
+    a: Duration,
+    b: Duration,
+    /// Length of what is left until `b`, the samples it makes are counted once the format is known.
+    length: Duration,
+    remaining: Option<u64>,
+    /// Moved back to the start of the section every time it repeats.
+    clock: Arc<Clock>,
+}
+
+impl<S> Section<S>
+where
+    S: Seekable,
+    S::Item: Sample,
+{
+
+    /// Loop the song between `a` and `b`, `inner` is the song already at
+    /// `from` which lies in between.
+    #[inline]
+    pub fn new(inner: S, a: Duration, b: Duration, from: Duration, clock: Arc<Clock>) -> Self {
+        Section {
+            inner,
+            a,
+            b,
+            length: b.checked_sub(from.max(a)).unwrap_or_default(),
+            remaining: None,
+            clock,
+        }
+    }
+
+    /// Samples left until `b`, in whole frames so that the channels stay in
+    /// place across repeats.
+    #[inline]
+    fn left(&mut self) -> &mut u64 {
+        let frames = (self.length.as_secs_f64() * self.inner.sample_rate() as f64).round() as u64;
+        let samples = frames * self.inner.channels() as u64;
+        self.remaining.get_or_insert(samples)
+    }
+
+    /// Go back to `a`, `false` if the song can't be sought anymore.
+    #[inline]
+    fn repeat(&mut self) -> bool {
+        if self.inner.seek(self.a).is_err() {
+            return false;
+        }
+        self.length = self.b - self.a;
+        self.remaining = None;
+        self.clock.rewind(self.a);
+        true
+    }
+}
+
+impl<S> fmt::Debug for Section<S>
+where
+    S: Seekable,
+    S::Item: Sample,
+{
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Section")
+            .field("a", &self.a)
+            .field("b", &self.b)
+            .field("remaining", &self.remaining)
+            .finish()
+    }
+}
+
+impl<S> Iterator for Section<S>
+where
+    S: Seekable,
+    S::Item: Sample,
+{
+    type Item = f32;
+
+    #[inline]
+    fn next(&mut self) -> Option<f32> {
+        if self.b <= self.a {
+            return None;
+        }
+        let mut repeated = false;
+        loop {
+            if *self.left() > 0 {
+                if let Some(sample) = self.inner.next() {
+                    *self.left() -= 1;
+                    return Some(sample.to_f32());
+                }
+            }
+            // A section the song can't fill plays nothing, instead of spinning.
+            if repeated || !self.repeat() {
+                return None;
+            }
+            repeated = true;
+        }
+    }
+}
+
+impl<S> Source for Section<S>
+where
+    S: Seekable,
+    S::Item: Sample,
+{
+    #[inline]
+    fn current_frame_len(&self) -> Option<usize> {
+        let len = self.inner.current_frame_len();
+        match self.remaining {
+            Some(remaining) if remaining > 0 => Some(len.map_or(remaining as usize, |l| l.min(remaining as usize))),
+            _ => len,
+        }
+    }
+
+    #[inline]
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    #[inline]
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    #[inline]
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// Source limited to one track of a single-file album, whole songs pass through.
+#[derive(Debug)]
+pub struct Slice<S>
+where
+    S: Seekable,
+    S::Item: Sample,
+{
+    inner: S,
+    /// Where the track starts in its file.
+    start: Duration,
+    length: Option<Duration>,
+    /// Position in the track the samples are counted from.
+    from: Duration,
+    /// Samples left until the end of the track, counted once the format is known.
+    remaining: Option<u64>,
+}
+
+impl<S> Slice<S>
+where
+    S: Seekable,
+    S::Item: Sample,
+{
+    /// The track of `inner`, the file is sought to its start.
+    #[inline]
+    pub fn new(mut inner: S, track: Option<Track>) -> Result<Self> {
+        let total = inner.total_duration();
+        let (start, length) = match track {
+            Some(track) => (
+                track.start,
+                track.end.map(|end| end.checked_sub(track.start).unwrap_or_default())
+                    .or_else(|| total.and_then(|t| t.checked_sub(track.start))),
+            ),
+            None => (Duration::from_secs(0), total),
+        };
+        if start > Duration::from_secs(0) {
+            inner.seek(start)?;
+        }
+        Ok(Slice {
+            inner,
+            start,
+            length,
+            from: Duration::from_secs(0),
+            remaining: None,
+        })
+    }
+}
+
+impl<S> Seekable for Slice<S>
+where
+    S: Seekable,
+    S::Item: Sample,
+{
+    /// Jump to `pos` in the track.
+    #[inline]
+    fn seek(&mut self, pos: Duration) -> Result<()> {
+        self.inner.seek(self.start + pos)?;
+        self.from = pos;
+        self.remaining = None;
+        Ok(())
+    }
+}
+
+impl<S> Iterator for Slice<S>
+where
+    S: Seekable,
+    S::Item: Sample,
+{
+    type Item = S::Item;
+
+    #[inline]
+    fn next(&mut self) -> Option<S::Item> {
+        if let Some(length) = self.length {
+            let length = length.checked_sub(self.from).unwrap_or_default();
+            let rate = self.inner.sample_rate() as f64 * self.inner.channels() as f64;
+            let remaining = self.remaining.get_or_insert((length.as_secs_f64() * rate).round() as u64);
+            if *remaining == 0 {
+                return None;
+            }
+            *remaining -= 1;
+        }
+        self.inner.next()
+    }
+}
+
+impl<S> Source for Slice<S>
+where
+    S: Seekable,
+    S::Item: Sample,
+{
+    #[inline]
+    fn current_frame_len(&self) -> Option<usize> {
+        let len = self.inner.current_frame_len();
+        match self.remaining {
+            Some(remaining) => Some(len.map_or(remaining as usize, |l| l.min(remaining as usize))),
+            None => len,
+        }
+    }
+
+    #[inline]
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    #[inline]
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    #[inline]
+    fn total_duration(&self) -> Option<Duration> {
+        self.length
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    /// Three seconds of a mono ramp at 10 Hz, counting the samples it makes.
+    #[derive(Debug)]
+    struct Ramp {
+        pos: usize,
+        decoded: usize,
+    }
+
+    impl Iterator for Ramp {
+        type Item = f32;
+
+        fn next(&mut self) -> Option<f32> {
+            if self.pos == 30 {
+                return None;
+            }
+            self.pos += 1;
+            self.decoded += 1;
+            Some((self.pos - 1) as f32)
+        }
+    }
+
+    impl Source for Ramp {
+        fn current_frame_len(&self) -> Option<usize> {
+            None
+        }
+
+        fn channels(&self) -> u16 {
+            1
+        }
+
+        fn sample_rate(&self) -> u32 {
+            10
+        }
+
+        fn total_duration(&self) -> Option<Duration> {
+            Some(Duration::from_secs(3))
+        }
+    }
+
+    impl Seekable for Ramp {
+        fn seek(&mut self, pos: Duration) -> Result<()> {
+            self.pos = ((pos.as_secs_f64() * 10.0) as usize).min(30);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_slice() {
+        let track = Track { number: 2, start: Duration::from_secs(1), end: Some(Duration::from_secs(2)) };
+        let mut slice = Slice::new(Ramp { pos: 0, decoded: 0 }, Some(track)).unwrap();
+        assert_eq!(slice.total_duration(), Some(Duration::from_secs(1)));
+        assert_eq!(slice.by_ref().collect::<Vec<_>>(), (10..20).map(|i| i as f32).collect::<Vec<_>>());
+        // Nothing before the track was decoded to get there.
+        assert_eq!(slice.inner.decoded, 10);
+
+        slice.seek(Duration::from_millis(500)).unwrap();
+        assert_eq!(slice.collect::<Vec<_>>(), (15..20).map(|i| i as f32).collect::<Vec<_>>());
+
+        let whole = Slice::new(Ramp { pos: 0, decoded: 0 }, None).unwrap();
+        assert_eq!(whole.count(), 30);
+    }
+
+    #[test]
+    fn test_section() {
+        let (a, b, from) = (Duration::from_secs(1), Duration::from_secs(2), Duration::from_millis(1500));
+        let clock = Arc::new(Clock::new(from, None));
+        let mut ramp = Ramp { pos: 0, decoded: 0 };
+        ramp.seek(from).unwrap();
+        let mut section = Section::new(ramp, a, b, from, clock.clone());
+        let played = section.by_ref().take(25).collect::<Vec<_>>();
+        let expected = (15..20).chain(10..20).chain(10..20).map(|i| i as f32).collect::<Vec<_>>();
+        assert_eq!(played, expected);
+        assert_eq!(clock.elapsed(), a);
+        // Every repeat is a seek, not decoding the song again from its start.
+        assert_eq!(section.inner.decoded, 25);
+    }
+}
@@ -0,0 +1,456 @@
+use crate::error::{Result, anyhow, NonexistentSpeedMode};
+use rodio::{Sample, Source};
+use std::f32::consts::PI;
+use std::iter;
+use std::mem;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering::Relaxed};
+use std::time::Duration;
+
+pub const SPEED_MODES: [&str; 2] = ["stretch", "resample"];
+
+pub const MIN_SPEED: f32 = 0.5;
+pub const MAX_SPEED: f32 = 3.0;
+pub const SPEED_STEP: f32 = 0.25;
+
+/// Length of the windows the stretch mode cuts the song into.
+const WINDOW_MS: usize = 40;
+/// How far a window may be moved to line up with the previous one.
+const TOLERANCE_MS: usize = 12;
+/// Only every `STRIDE`th frame is compared when lining windows up.
+const STRIDE: usize = 4;
+
+/// How the speed of a song is changed.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SpeedMode {
+    /// Keep the pitch, by overlapping windows of the song.
+    Stretch,
+    /// Play the samples faster or slower, like a tape.
+    Resample,
+}
+
+impl Default for SpeedMode {
+    #[inline]
+    fn default() -> Self {
+        SpeedMode::Stretch
+    }
+}
+
+impl FromStr for SpeedMode {
+    type Err = anyhow::Error;
+
+    #[inline]
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "stretch" => Ok(SpeedMode::Stretch),
+            "resample" => Ok(SpeedMode::Resample),
+            _ => Err(anyhow!(NonexistentSpeedMode(s.into()))),
+        }
+    }
+}
+
+/// Playback speed shared between the player and the sources it feeds to the sink.
+#[derive(Debug)]
+pub struct Speed(AtomicU32);
+
+impl Default for Speed {
+    #[inline]
+    fn default() -> Self {
+        Speed(AtomicU32::new(1f32.to_bits()))
+    }
+}
+
+impl Speed {
+
+    #[inline]
+    pub fn get(&self) -> f32 {
+        f32::from_bits(self.0.load(Relaxed))
+    }
+
+    #[inline]
+    pub fn set(&self, speed: f32) {
+        self.0.store(speed.max(MIN_SPEED).min(MAX_SPEED).to_bits(), Relaxed);
+    }
+}
+
+/// Source playing `inner` at the shared speed, which can change at any time.
+#[derive(Debug)]
+pub struct Varispeed<S> {
+    inner: S,
+    speed: Arc<Speed>,
+    channels: u16,
+    rate: u32,
+    stretcher: Stretcher,
+    output: Vec<f32>,
+    cursor: usize,
+}
+
+#[derive(Debug)]
+enum Stretcher {
+    Wsola(Wsola),
+    Resampler(Resampler),
+}
+
+impl<S> Varispeed<S>
+where
+    S: Source,
+    S::Item: Sample,
+{
+    #[inline]
+    pub fn new(inner: S, speed: Arc<Speed>, mode: SpeedMode) -> Self {
+        let channels = inner.channels();
+        let rate = inner.sample_rate();
+        let stretcher = match mode {
+            SpeedMode::Stretch => Stretcher::Wsola(Wsola::new(channels as usize, rate)),
+            SpeedMode::Resample => Stretcher::Resampler(Resampler::new(channels as usize)),
+        };
+        Varispeed {
+            inner,
+            speed,
+            channels,
+            rate,
+            stretcher,
+            output: Vec::new(),
+            cursor: 0,
+        }
+    }
+}
+
+impl<S> Iterator for Varispeed<S>
+where
+    S: Source,
+    S::Item: Sample,
+{
+    type Item = f32;
+
+    #[inline]
+    fn next(&mut self) -> Option<f32> {
+        loop {
+            if let Some(&sample) = self.output.get(self.cursor) {
+                self.cursor += 1;
+                return Some(sample);
+            }
+            self.output.clear();
+            self.cursor = 0;
+            let speed = self.speed.get();
+            let mut source = self.inner.by_ref().map(|s| s.to_f32());
+            let more = match &mut self.stretcher {
+                Stretcher::Wsola(w) => w.process(speed, &mut source, &mut self.output),
+                Stretcher::Resampler(r) => r.process(speed, &mut source, &mut self.output),
+            };
+            if !more {
+                return None;
+            }
+        }
+    }
+}
+
+impl<S> Source for Varispeed<S>
+where
+    S: Source,
+    S::Item: Sample,
+{
+    #[inline]
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    #[inline]
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    #[inline]
+    fn sample_rate(&self) -> u32 {
+        self.rate
+    }
+
+    #[inline]
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// Linear interpolation between consecutive frames, the pitch follows the speed.
+#[derive(Debug)]
+struct Resampler {
+    channels: usize,
+    /// The frames the output currently lies between.
+    a: Vec<f32>,
+    b: Vec<f32>,
+    /// Position of the output between `a` and `b`.
+    frac: f64,
+    primed: bool,
+}
+
+impl Resampler {
+
+    #[inline]
+    fn new(channels: usize) -> Self {
+        Resampler {
+            channels,
+            a: Vec::with_capacity(channels),
+            b: Vec::with_capacity(channels),
+            frac: 0.0,
+            primed: false,
+        }
+    }
+
+    /// Read a whole frame into `frame`, which is left empty at the end of the source.
+    #[inline]
+    fn read(channels: usize, source: &mut impl Iterator<Item = f32>, frame: &mut Vec<f32>) {
+        frame.clear();
+        frame.extend(source.by_ref().take(channels));
+        if frame.len() < channels {
+            frame.clear();
+        }
+    }
+
+    /// Output one frame, returns `false` once the source is exhausted.
+    #[inline]
+    fn process(&mut self, speed: f32, source: &mut impl Iterator<Item = f32>, out: &mut Vec<f32>) -> bool {
+        if !self.primed {
+            Self::read(self.channels, source, &mut self.a);
+            Self::read(self.channels, source, &mut self.b);
+            self.primed = true;
+        }
+        if self.a.is_empty() {
+            return false;
+        }
+        if self.frac == 0.0 {
+            out.extend_from_slice(&self.a);
+        } else if self.b.is_empty() {
+            return false;
+        } else {
+            let frac = self.frac as f32;
+            out.extend(self.a.iter().zip(&self.b).map(|(a, b)| a + (b - a) * frac));
+        }
+        self.frac += speed as f64;
+        while self.frac >= 1.0 {
+            self.frac -= 1.0;
+            mem::swap(&mut self.a, &mut self.b);
+            Self::read(self.channels, source, &mut self.b);
+        }
+        true
+    }
+}
+
+/// Time stretching by waveform similarity overlap-add: windows are taken from the
+/// song every `hop * speed` frames and laid every `hop` frames, each one moved a
+/// little so that it lines up with the one before. The pitch is kept.
+#[derive(Debug)]
+struct Wsola {
+    channels: usize,
+    /// Periodic Hann window, two of them overlapping by half sum to one.
+    window: Vec<f32>,
+    hop: usize,
+    tolerance: i64,
+    /// Interleaved input, starting at the frame `start`.
+    input: Vec<f32>,
+    start: i64,
+    /// Frame the source ended at, the input is padded with silence past it.
+    end: Option<i64>,
+    /// Where the next window should be taken from, regardless of lining up.
+    nominal: f64,
+    /// Where the previous window would naturally continue.
+    natural: i64,
+    /// Second half of the previous window, to be added to the next one.
+    overlap: Vec<f32>,
+    /// Output frames left to drop, the first window only covers the padding.
+    skip: usize,
+}
+
+impl Wsola {
+
+    #[inline]
+    fn new(channels: usize, rate: u32) -> Self {
+        let channels = channels.max(1);
+        let hop = (rate as usize * WINDOW_MS / 1000 / 2).max(1);
+        let len = hop * 2;
+        // Starting half a window early, in the padding, lets the first frames of
+        // the song be covered by two windows like any other.
+        Wsola {
+            channels,
+            window: (0..len)
+                .map(|n| 0.5 - 0.5 * (2.0 * PI * n as f32 / len as f32).cos())
+                .collect(),
+            hop,
+            tolerance: (rate as usize * TOLERANCE_MS / 1000) as i64,
+            input: vec![0.0; hop * channels],
+            start: -(hop as i64),
+            end: None,
+            nominal: -(hop as f64),
+            natural: -(hop as i64),
+            overlap: vec![0.0; hop * channels],
+            skip: hop,
+        }
+    }
+
+    /// Read the source until the input holds the frames before `until`.
+    #[inline]
+    fn fill(&mut self, until: i64, source: &mut impl Iterator<Item = f32>) {
+        let channels = self.channels;
+        loop {
+            let frames = self.start + (self.input.len() / channels) as i64;
+            if frames >= until {
+                return;
+            }
+            if self.end.is_some() {
+                self.input.extend(iter::repeat(0.0).take((until - frames) as usize * channels));
+                return;
+            }
+            let len = self.input.len();
+            self.input.extend(source.by_ref().take(channels));
+            let read = self.input.len() - len;
+            if read < channels {
+                self.end = Some(frames + (read > 0) as i64);
+                self.input.extend(iter::repeat(0.0).take(channels - read));
+            }
+        }
+    }
+
+    #[inline(always)]
+    fn frame(&self, frame: i64) -> &[f32] {
+        let idx = (frame - self.start) as usize * self.channels;
+        &self.input[idx..idx + self.channels]
+    }
+
+    /// How well the window at `candidate` continues the previous one.
+    #[inline]
+    fn similarity(&self, candidate: i64) -> f32 {
+        let (mut correlation, mut energy) = (0.0, 0.0);
+        for f in (0..self.hop as i64).step_by(STRIDE) {
+            let a = self.frame(candidate + f).iter().sum::<f32>();
+            let b = self.frame(self.natural + f).iter().sum::<f32>();
+            correlation += a * b;
+            energy += a * a;
+        }
+        if energy > 0.0 { correlation / energy.sqrt() } else { 0.0 }
+    }
+
+    /// Where to take the next window from, the nominal position moved by at most
+    /// the tolerance to line up with the previous window.
+    #[inline]
+    fn align(&self) -> i64 {
+        let center = self.nominal.round() as i64;
+        let lo = (center - self.tolerance).max(self.start);
+        let hi = (center + self.tolerance).max(lo);
+        let best = |from: i64, to: i64, step: usize| {
+            (from..=to)
+                .step_by(step)
+                .map(|p| (self.similarity(p), p))
+                .fold((f32::MIN, from), |best, c| if c.0 > best.0 { c } else { best })
+                .1
+        };
+        let coarse = best(lo, hi, STRIDE);
+        best((coarse - STRIDE as i64).max(lo), (coarse + STRIDE as i64).min(hi), 1)
+    }
+
+    /// Output the next `hop` frames, returns `false` once the source is exhausted.
+    #[inline]
+    fn process(&mut self, speed: f32, source: &mut impl Iterator<Item = f32>, out: &mut Vec<f32>) -> bool {
+        let len = self.hop as i64 * 2;
+        let unchanged = speed == 1.0;
+        let reach = if unchanged {
+            self.natural
+        } else {
+            self.natural.max(self.nominal.round() as i64 + self.tolerance)
+        };
+        self.fill(reach + len, source);
+
+        // At the normal speed the windows simply follow each other and the
+        // song comes out untouched.
+        let p = if unchanged { self.natural } else { self.align() };
+        let frames = match self.end {
+            Some(end) if p >= end => return false,
+            Some(end) => ((end - p) as usize).min(self.hop),
+            None => self.hop,
+        };
+
+        let channels = self.channels;
+        for f in 0..self.hop {
+            let head = self.window[f];
+            let tail = self.window[self.hop + f];
+            for c in 0..channels {
+                let i = f * channels + c;
+                let sample = self.overlap[i] + self.frame(p + f as i64)[c] * head;
+                self.overlap[i] = self.frame(p + (self.hop + f) as i64)[c] * tail;
+                if f >= self.skip && f < frames {
+                    out.push(sample);
+                }
+            }
+        }
+        self.skip = self.skip.saturating_sub(self.hop);
+
+        self.natural = p + self.hop as i64;
+        self.nominal = if unchanged {
+            self.natural as f64
+        } else {
+            self.nominal + self.hop as f64 * speed as f64
+        };
+
+        let keep = self.natural.min(self.nominal.floor() as i64 - self.tolerance);
+        if keep > self.start {
+            self.input.drain(..(keep - self.start) as usize * channels);
+            self.start = keep;
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn sine(frequency: f32, rate: u32, secs: f32) -> Vec<f32> {
+        (0..(rate as f32 * secs) as usize)
+            .map(|n| (2.0 * PI * frequency * n as f32 / rate as f32).sin())
+            .collect()
+    }
+
+    fn stretch(input: &[f32], speed: f32, rate: u32) -> Vec<f32> {
+        let mut wsola = Wsola::new(1, rate);
+        let mut source = input.iter().cloned();
+        let mut out = Vec::new();
+        while wsola.process(speed, &mut source, &mut out) {}
+        out
+    }
+
+    /// Frequency estimated from the zero crossings.
+    fn frequency(samples: &[f32], rate: u32) -> f32 {
+        let crossings = samples.windows(2).filter(|w| w[0] < 0.0 && w[1] >= 0.0).count();
+        crossings as f32 * rate as f32 / samples.len() as f32
+    }
+
+    #[test]
+    fn test_stretch_is_transparent_at_normal_speed() {
+        let input = sine(440.0, 8000, 0.5);
+        let out = stretch(&input, 1.0, 8000);
+        assert_eq!(out.len(), input.len());
+        assert!(input.iter().zip(&out).all(|(a, b)| (a - b).abs() < 1e-4));
+    }
+
+    #[test]
+    fn test_stretch_keeps_pitch() {
+        let input = sine(440.0, 8000, 2.0);
+        for &speed in &[0.5, 1.5, 2.0] {
+            let out = stretch(&input, speed, 8000);
+            let expected = input.len() as f32 / speed;
+            assert!((out.len() as f32 - expected).abs() < expected * 0.05);
+            assert!((frequency(&out, 8000) - 440.0).abs() < 10.0);
+        }
+    }
+
+    #[test]
+    fn test_resample_changes_pitch() {
+        let input = sine(440.0, 8000, 2.0);
+        let mut resampler = Resampler::new(1);
+        let mut source = input.iter().cloned();
+        let mut out = Vec::new();
+        while resampler.process(2.0, &mut source, &mut out) {}
+        assert!((out.len() as f32 - input.len() as f32 / 2.0).abs() < 2.0);
+        assert!((frequency(&out, 8000) - 880.0).abs() < 10.0);
+    }
+}
@@ -0,0 +1,288 @@
+use crate::DEBUG;
+use crate::error::Result;
+use parking_lot::Mutex;
+use std::fmt;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::sync::Arc;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::Duration;
+use log::trace;
+
+/// How long to wait for the station to answer or send more audio.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Size of the chunks read off the connection.
+const CHUNK: usize = 8 * 1024;
+
+/// Chunks read ahead of the decoder, to ride out a slow connection.
+const PREFETCH: usize = 64;
+
+/// Bytes kept behind the read position, so that the decoders can seek back
+/// while they probe the format.
+const WINDOW: usize = 1024 * 1024;
+
+/// Title of what the station is playing right now, as told by its ICY metadata.
+#[derive(Debug, Default)]
+pub struct NowPlaying(Mutex<Option<String>>);
+
+impl NowPlaying {
+
+    #[inline]
+    pub fn get(&self) -> Option<String> {
+        self.0.lock().clone()
+    }
+
+    #[inline]
+    fn set(&self, title: String) {
+        if DEBUG.load(Relaxed) { trace!("Now playing: {}", title); }
+        *self.0.lock() = Some(title).filter(|t| !t.is_empty());
+    }
+}
+
+/// An HTTP(S) audio stream, read ahead by a thread of its own.
+///
+/// Only the last `WINDOW` bytes can be seeked back to, and the end can't be
+/// seeked to at all, which is all the decoders need to tell the format.
+pub struct Stream {
+    chunks: Receiver<Vec<u8>>,
+    buffer: Vec<u8>,
+    /// Position of the first byte of `buffer` in the stream.
+    start: u64,
+    pos: u64,
+    ended: bool,
+}
+
+impl Stream {
+
+    /// Connect to `url`, asking for the ICY metadata which goes to `now_playing`.
+    #[inline]
+    pub fn open(url: &str, now_playing: Arc<NowPlaying>) -> Result<Self> {
+        let agent = ureq::AgentBuilder::new()
+            .timeout_connect(TIMEOUT)
+            .timeout_read(TIMEOUT)
+            .build();
+        let response = agent.get(url).set("Icy-MetaData", "1").call()?;
+        let metaint = response.header("icy-metaint").and_then(|m| m.trim().parse().ok()).filter(|&m| m > 0);
+        if DEBUG.load(Relaxed) { trace!("Connected to {}, metadata every {:?} bytes.", url, metaint); }
+        if let Some(name) = response.header("icy-name") {
+            if DEBUG.load(Relaxed) { trace!("The station calls itself {}.", name); }
+        }
+
+        let mut reader = Icy {
+            inner: response.into_reader(),
+            metaint,
+            left: metaint.unwrap_or(0),
+            now_playing,
+        };
+        let (tx, chunks) = mpsc::sync_channel(PREFETCH);
+        thread::spawn(move || loop {
+            let mut chunk = vec![0; CHUNK];
+            match reader.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(n) => {
+                    chunk.truncate(n);
+                    // The stream was dropped.
+                    if tx.send(chunk).is_err() {
+                        break;
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    if DEBUG.load(Relaxed) { trace!("The stream broke off: {}", e); }
+                    break;
+                }
+            }
+        });
+
+        Ok(Stream {
+            chunks,
+            buffer: Vec::with_capacity(WINDOW),
+            start: 0,
+            pos: 0,
+            ended: false,
+        })
+    }
+
+    /// Position right after the last byte received.
+    #[inline]
+    fn end(&self) -> u64 {
+        self.start + self.buffer.len() as u64
+    }
+}
+
+impl Read for Stream {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos >= self.end() && !self.ended {
+            match self.chunks.recv() {
+                Ok(chunk) => self.buffer.extend_from_slice(&chunk),
+                Err(_) => self.ended = true,
+            }
+        }
+        if self.pos >= self.end() {
+            return Ok(0);
+        }
+        let offset = (self.pos - self.start) as usize;
+        let n = buf.len().min(self.buffer.len() - offset);
+        buf[..n].copy_from_slice(&self.buffer[offset..offset + n]);
+        self.pos += n as u64;
+
+        // Let go of what's too far behind, a window at a time.
+        let behind = (self.pos - self.start) as usize;
+        if behind > 2 * WINDOW {
+            self.buffer.drain(..behind - WINDOW);
+            self.start += (behind - WINDOW) as u64;
+        }
+        Ok(n)
+    }
+}
+
+impl Seek for Stream {
+    #[inline]
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(p) => Some(p),
+            SeekFrom::Current(d) => (self.pos as i64).checked_add(d).filter(|p| *p >= 0).map(|p| p as u64),
+            SeekFrom::End(_) => None,
+        };
+        match target {
+            Some(target) if target >= self.start => {
+                self.pos = target;
+                Ok(target)
+            }
+            _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "unable to seek in a live stream")),
+        }
+    }
+}
+
+impl fmt::Debug for Stream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Stream({}..{} at {})", self.start, self.end(), self.pos)
+    }
+}
+
+/// Strips the ICY metadata, sent every `metaint` bytes of audio, out of a stream.
+struct Icy<R> {
+    inner: R,
+    metaint: Option<usize>,
+    /// Bytes of audio before the next metadata.
+    left: usize,
+    now_playing: Arc<NowPlaying>,
+}
+
+impl<R: Read> Read for Icy<R> {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let metaint = match self.metaint {
+            Some(metaint) => metaint,
+            None => return self.inner.read(buf),
+        };
+        if self.left == 0 {
+            // The metadata length comes in blocks of 16 bytes.
+            let mut len = [0u8];
+            if self.inner.read(&mut len)? == 0 {
+                return Ok(0);
+            }
+            let mut metadata = vec![0; len[0] as usize * 16];
+            self.inner.read_exact(&mut metadata)?;
+            if let Some(title) = stream_title(&String::from_utf8_lossy(&metadata)) {
+                self.now_playing.set(title);
+            }
+            self.left = metaint;
+        }
+        let len = buf.len().min(self.left);
+        let n = self.inner.read(&mut buf[..len])?;
+        self.left -= n;
+        Ok(n)
+    }
+}
+
+/// The `StreamTitle` of a metadata block like `StreamTitle='Artist - Title';StreamUrl='';`.
+#[inline]
+fn stream_title(metadata: &str) -> Option<String> {
+    const KEY: &str = "StreamTitle='";
+    let rest = &metadata[metadata.find(KEY)? + KEY.len()..];
+    let end = rest.find("';").or_else(|| rest.rfind('\'')).unwrap_or(rest.len());
+    Some(rest[..end].trim_end_matches('\0').trim().to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use rodio::Decoder;
+    use std::io::Write;
+    use std::net::TcpListener;
+
+    /// A mono 8 kHz WAV of `samples`.
+    fn wav(samples: &[i16]) -> Vec<u8> {
+        let len = samples.len() as u32 * 2;
+        let mut wav = vec![];
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&(36 + len).to_le_bytes());
+        wav.extend_from_slice(b"WAVEfmt ");
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&8000u32.to_le_bytes());
+        wav.extend_from_slice(&16000u32.to_le_bytes());
+        wav.extend_from_slice(&2u16.to_le_bytes());
+        wav.extend_from_slice(&16u16.to_le_bytes());
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&len.to_le_bytes());
+        samples.iter().for_each(|s| wav.extend_from_slice(&s.to_le_bytes()));
+        wav
+    }
+
+    #[test]
+    fn test_stream_title() {
+        assert_eq!(stream_title("StreamTitle='A - B';StreamUrl='';\0\0").as_deref(), Some("A - B"));
+        assert_eq!(stream_title("StreamTitle='It's';\0").as_deref(), Some("It's"));
+        assert_eq!(stream_title("StreamUrl='x';"), None);
+    }
+
+    #[test]
+    fn test_icy_stream() {
+        const METAINT: usize = 1000;
+        let samples = (0..4000).map(|i| (i * 7 % 2000) as i16).collect::<Vec<_>>();
+        let audio = wav(&samples);
+
+        // Serve the WAV the way an Icecast server does, the title is only
+        // sent once and then repeated as empty blocks.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (mut conn, _) = listener.accept().unwrap();
+            let mut request = vec![];
+            let mut byte = [0u8];
+            while !request.ends_with(b"\r\n\r\n") && conn.read(&mut byte).unwrap() == 1 {
+                request.push(byte[0]);
+            }
+            let request = String::from_utf8_lossy(&request).to_ascii_lowercase();
+            assert!(request.contains("icy-metadata: 1"));
+            write!(conn, "HTTP/1.0 200 OK\r\nContent-Type: audio/wav\r\nicy-metaint: {}\r\n\r\n", METAINT).unwrap();
+            for (i, block) in audio.chunks(METAINT).enumerate() {
+                conn.write_all(block).unwrap();
+                if block.len() == METAINT {
+                    if i == 0 {
+                        let mut meta = b"StreamTitle='Artist - Title';".to_vec();
+                        meta.resize(32, 0);
+                        conn.write_all(&[2]).unwrap();
+                        conn.write_all(&meta).unwrap();
+                    } else {
+                        conn.write_all(&[0]).unwrap();
+                    }
+                }
+            }
+        });
+
+        let now_playing = Arc::new(NowPlaying::default());
+        let stream = Stream::open(&format!("http://{}/stream", addr), now_playing.clone()).unwrap();
+        let decoded = Decoder::new(stream).unwrap().collect::<Vec<i16>>();
+        server.join().unwrap();
+        assert_eq!(decoded, samples);
+        assert_eq!(now_playing.get().as_deref(), Some("Artist - Title"));
+    }
+}
@@ -0,0 +1,49 @@
+use crate::utils::display_duration;
+use std::fmt;
+use std::time::Duration;
+
+/// Lengths the sleep timer cycles through, in minutes.
+pub const TIMER_PRESETS: [u64; 5] = [15, 30, 45, 60, 90];
+
+/// When the sleep timer stops the playback.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Timer {
+    /// Once this much more has been played.
+    After(Duration),
+    /// Once the current song ends.
+    EndOfSong,
+    /// Once the last song of the current album in the queue ends.
+    EndOfAlbum,
+}
+
+impl Timer {
+
+    /// The timer following `timer` in the cycle: the presets, the end of the
+    /// song, the end of the album, and off.
+    #[inline]
+    pub fn cycle(timer: Option<Timer>) -> Option<Timer> {
+        let preset = |i: usize| Timer::After(Duration::from_secs(TIMER_PRESETS[i] * 60));
+        match timer {
+            None => Some(preset(0)),
+            Some(Timer::After(left)) => {
+                // A running timer moves on to the next preset above what's left.
+                match TIMER_PRESETS.iter().position(|&m| Duration::from_secs(m * 60) >= left) {
+                    Some(i) if i + 1 < TIMER_PRESETS.len() => Some(preset(i + 1)),
+                    _ => Some(Timer::EndOfSong),
+                }
+            }
+            Some(Timer::EndOfSong) => Some(Timer::EndOfAlbum),
+            Some(Timer::EndOfAlbum) => None,
+        }
+    }
+}
+
+impl fmt::Display for Timer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Timer::After(left) => write!(f, "sleep in {}", display_duration(Some(left.as_secs().max(1)))),
+            Timer::EndOfSong => write!(f, "sleep after this song"),
+            Timer::EndOfAlbum => write!(f, "sleep after this album"),
+        }
+    }
+}
@@ -0,0 +1,191 @@
+use rodio::Source;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering::Relaxed};
+use std::time::Duration;
+
+/// Attenuation at the lowest volume level above 0, in dB.
+pub const VOLUME_RANGE: f32 = 60.0;
+
+/// From this level up the volume is the amplitude in percent, as it always
+/// was, so that 50 stays at about -6 dB. The levels below it are spread evenly
+/// in dB down to `VOLUME_RANGE`, so each step sounds about as loud as the next
+/// where the amplitude alone has too few of them.
+const KNEE: u64 = 50;
+
+/// Volume at startup when neither the config nor the last session set one.
+pub const DEFAULT_VOLUME: u64 = 100;
+
+pub const BALANCE_STEP: f32 = 0.1;
+
+/// Amplitude factor of the volume `level`, between 0 and 100.
+#[inline]
+pub fn amplitude(level: u64) -> f32 {
+    decibels(level).map_or(0.0, |db| 10f32.powf(db / 20.0))
+}
+
+/// Gain of the volume `level` in dB, `None` for silence.
+#[inline]
+pub fn decibels(level: u64) -> Option<f32> {
+    let linear = |level: u64| 20.0 * (level as f32 / 100.0).log10();
+    match level.min(100) {
+        0 => None,
+        level if level >= KNEE => Some(linear(level)),
+        level => {
+            let knee = linear(KNEE);
+            Some(knee - (KNEE - level) as f32 / (KNEE - 1) as f32 * (VOLUME_RANGE + knee))
+        }
+    }
+}
+
+/// Left/right balance and mono downmix, shared with the sources playing.
+#[derive(Debug, Default)]
+pub struct Balance {
+    /// From -1 (left only) to 1 (right only), stored as the bits of a f32.
+    pan: AtomicU32,
+    mono: AtomicBool,
+}
+
+impl Balance {
+
+    #[inline]
+    pub fn pan(&self) -> f32 {
+        f32::from_bits(self.pan.load(Relaxed))
+    }
+
+    #[inline]
+    pub fn set_pan(&self, pan: f32) {
+        // Rounded so that stepping back and forth lands on the center again.
+        let pan = (pan.clamp(-1.0, 1.0) * 100.0).round() / 100.0;
+        self.pan.store(pan.to_bits(), Relaxed);
+    }
+
+    #[inline]
+    pub fn mono(&self) -> bool {
+        self.mono.load(Relaxed)
+    }
+
+    #[inline]
+    pub fn set_mono(&self, mono: bool) {
+        self.mono.store(mono, Relaxed);
+    }
+
+    /// Apply the balance to a stereo frame.
+    #[inline]
+    fn apply(&self, left: f32, right: f32) -> (f32, f32) {
+        let (left, right) = if self.mono() {
+            let mid = (left + right) / 2.0;
+            (mid, mid)
+        } else {
+            (left, right)
+        };
+        let pan = self.pan();
+        (left * (1.0 - pan.max(0.0)), right * (1.0 + pan.min(0.0)))
+    }
+}
+
+/// Source with the balance applied, sources with other than 2 channels are left alone.
+#[derive(Debug)]
+pub struct Panned<S> {
+    inner: S,
+    balance: Arc<Balance>,
+    /// Right sample of the frame whose left sample was just returned.
+    pending: Option<f32>,
+}
+
+impl<S> Panned<S>
+where
+    S: Source<Item = f32>,
+{
+    #[inline]
+    pub fn new(inner: S, balance: Arc<Balance>) -> Self {
+        Panned {
+            inner,
+            balance,
+            pending: None,
+        }
+    }
+}
+
+impl<S> Iterator for Panned<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    #[inline]
+    fn next(&mut self) -> Option<f32> {
+        if let Some(right) = self.pending.take() {
+            return Some(right);
+        }
+        let left = self.inner.next()?;
+        if self.inner.channels() != 2 {
+            return Some(left);
+        }
+        let right = self.inner.next().unwrap_or(left);
+        let (left, right) = self.balance.apply(left, right);
+        self.pending = Some(right);
+        Some(left)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<S> Source for Panned<S>
+where
+    S: Source<Item = f32>,
+{
+    #[inline]
+    fn current_frame_len(&self) -> Option<usize> {
+        let pending = self.pending.is_some() as usize;
+        self.inner.current_frame_len().map(|len| len + pending)
+    }
+
+    #[inline]
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    #[inline]
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    #[inline]
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_volume_curve() {
+        assert_eq!(amplitude(0), 0.0);
+        assert_eq!(amplitude(100), 1.0);
+        // The upper half is the amplitude in percent, as before the curve.
+        assert!((amplitude(50) - 0.5).abs() < 0.0001);
+        assert!((amplitude(80) - 0.8).abs() < 0.0001);
+        assert!((decibels(1).unwrap() + VOLUME_RANGE).abs() < 0.001);
+        // Below it every step changes the volume by the same ratio.
+        let ratio = amplitude(42) / amplitude(40);
+        assert!((amplitude(12) / amplitude(10) - ratio).abs() < 0.001);
+        assert!(amplitude(49) < amplitude(50));
+    }
+
+    #[test]
+    fn test_balance() {
+        let balance = Balance::default();
+        assert_eq!(balance.apply(0.5, -0.5), (0.5, -0.5));
+        balance.set_pan(-0.25);
+        assert_eq!(balance.apply(0.5, 0.4), (0.5, 0.3));
+        balance.set_mono(true);
+        balance.set_pan(0.0);
+        assert_eq!(balance.apply(0.5, -0.5), (0.0, 0.0));
+    }
+}
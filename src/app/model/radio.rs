@@ -0,0 +1,121 @@
+use crate::DEBUG;
+use crate::error::Result;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::Ordering::Relaxed;
+use log::trace;
+
+/// An internet radio station, an HTTP(S) audio stream.
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct Station {
+    pub name: String,
+    pub url: String,
+}
+
+impl Station {
+
+    /// Whether `url` looks like something that can be tuned in to.
+    #[inline]
+    pub fn valid_url(url: &str) -> bool {
+        url.starts_with("http://") || url.starts_with("https://")
+    }
+}
+
+/// The stations of the config followed by the ones of the playlists in `files`.
+#[inline]
+pub fn load(stations: &[Station], files: &[String]) -> Result<Vec<Station>> {
+    let mut all = stations.to_vec();
+    for file in files {
+        let path = Path::new(file);
+        let text = String::from_utf8_lossy(&fs::read(path)?).into_owned();
+        let found = match path.extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase()) {
+            Some(ext) if ext == "pls" => parse_pls(&text),
+            _ => parse_m3u(&text),
+        };
+        if DEBUG.load(Relaxed) { trace!("Found {} stations in {:?}.", found.len(), path); }
+        all.extend(found);
+    }
+    Ok(all)
+}
+
+/// Stations of an M3U playlist, named by their `#EXTINF` line or else by their URL.
+#[inline]
+pub fn parse_m3u(text: &str) -> Vec<Station> {
+    let mut stations = vec![];
+    let mut name = None;
+    for line in text.trim_start_matches('\u{feff}').lines().map(str::trim) {
+        if let Some(info) = line.strip_prefix("#EXTINF:") {
+            name = info.split_once(',').map(|(_, n)| n.trim().to_owned()).filter(|n| !n.is_empty());
+        } else if Station::valid_url(line) {
+            stations.push(Station {
+                name: name.take().unwrap_or_else(|| line.to_owned()),
+                url: line.to_owned(),
+            });
+        }
+    }
+    stations
+}
+
+/// Stations of a PLS playlist, in the order of their numbers.
+#[inline]
+pub fn parse_pls(text: &str) -> Vec<Station> {
+    let mut entries: BTreeMap<u32, (Option<String>, Option<String>)> = BTreeMap::new();
+    for line in text.trim_start_matches('\u{feff}').lines() {
+        let (key, value) = match line.find('=') {
+            Some(i) => (line[..i].trim().to_ascii_lowercase(), line[i + 1..].trim()),
+            None => continue,
+        };
+        let (field, number) = key.split_at(key.find(|c: char| c.is_ascii_digit()).unwrap_or(key.len()));
+        let number = match number.parse() {
+            Ok(number) => number,
+            Err(_) => continue,
+        };
+        let entry = entries.entry(number).or_default();
+        match field {
+            "file" => entry.0 = Some(value.to_owned()),
+            "title" => entry.1 = Some(value.to_owned()).filter(|t| !t.is_empty()),
+            _ => {}
+        }
+    }
+    entries
+        .into_iter()
+        .filter_map(|(_, (url, name))| {
+            let url = url.filter(|u| Station::valid_url(u))?;
+            Some(Station { name: name.unwrap_or_else(|| url.clone()), url })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_playlists() {
+        let m3u = parse_m3u(concat!(
+            "#EXTM3U\n",
+            "#EXTINF:-1,Jazz FM\n",
+            "http://jazz.example.com/stream\n",
+            "/not/a/stream.mp3\n",
+            "https://news.example.com/live.ogg\n",
+        ));
+        assert_eq!(m3u, vec![
+            Station { name: "Jazz FM".to_owned(), url: "http://jazz.example.com/stream".to_owned() },
+            Station { name: "https://news.example.com/live.ogg".to_owned(), url: "https://news.example.com/live.ogg".to_owned() },
+        ]);
+
+        let pls = parse_pls(concat!(
+            "[playlist]\r\n",
+            "File2=http://two.example.com/\r\n",
+            "Title2=Two\r\n",
+            "File1=http://one.example.com/\r\n",
+            "Title1=One\r\n",
+            "NumberOfEntries=2\r\n",
+        ));
+        assert_eq!(pls.iter().map(|s| s.name.as_str()).collect::<Vec<_>>(), ["One", "Two"]);
+        assert_eq!(pls[1].url, "http://two.example.com/");
+    }
+}
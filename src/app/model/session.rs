@@ -0,0 +1,47 @@
+use super::{Model, Listing};
+use super::library::Flag;
+use super::library::song::Song;
+use super::player::Mode;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Everything needed to pick up where the last run left off, songs are
+/// identified by `Song::id`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Session {
+    pub song: Option<PathBuf>,
+    pub position: Duration,
+    pub queue: Vec<PathBuf>,
+    pub mode: Mode,
+    pub volume: u64,
+    pub flag: Flag,
+    pub selected: Option<usize>,
+    pub history: Vec<PathBuf>,
+}
+
+impl Session {
+
+    #[inline]
+    pub fn capture(model: &Model) -> Self {
+        let player = &model.player;
+        let paths = |songs: &[Song]| songs.iter().map(|s| s.id()).collect();
+        Session {
+            song: player.current.as_ref().map(|s| s.id()),
+            position: if player.current.is_some() { player.position() } else { Duration::default() },
+            queue: paths(player.queue.songs()),
+            mode: player.mode,
+            volume: player.volume(),
+            flag: model.flag,
+            selected: model.offset.filter(|_| model.listing == Listing::Songs),
+            history: paths(&player.history),
+        }
+    }
+
+    /// Look the saved songs up in `songs`, those that are gone are dropped.
+    #[inline]
+    pub fn find(paths: &[PathBuf], songs: &HashMap<PathBuf, Song>) -> Vec<Song> {
+        paths.iter().filter_map(|p| songs.get(p).cloned()).collect()
+    }
+}
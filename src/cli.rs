@@ -1,5 +1,9 @@
 use std::path::PathBuf;
 use crate::config::{Config, Theme};
+use crate::app::model::library::RESCAN_MODES;
+use crate::app::model::library::replaygain::REPLAYGAIN_MODES;
+use crate::app::model::player::output::OUTPUTS;
+use crate::app::model::player::speed::{SPEED_MODES, MIN_SPEED, MAX_SPEED};
 use clap::{self, App, Arg, ArgMatches};
 
 #[derive(Debug)]
@@ -73,6 +77,138 @@ impl CLI<'_> {
                         })
                         .takes_value(true),
                 )
+                .arg(
+                    Arg::with_name("balance")
+                        .value_name("BALANCE")
+                        .long("balance")
+                        .help("Set the left/right balance, from -1.0 (left only) to 1.0 (right only).")
+                        .validator(|v| match v.parse::<f32>() {
+                            Ok(b) if (-1.0..=1.0).contains(&b) => Ok(()),
+                            _ => Err("The balance must be between -1.0 and 1.0.".into()),
+                        })
+                        .allow_hyphen_values(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("mono")
+                        .value_name("BOOL")
+                        .long("mono")
+                        .help("Downmix stereo to mono or not.")
+                        .takes_value(true)
+                )
+                .arg(
+                    Arg::with_name("seek-step")
+                        .value_name("SECONDS")
+                        .short("s")
+                        .long("seek-step")
+                        .help("Set how many seconds to jump when seeking.")
+                        .validator(|v| {
+                            v.parse::<u64>()
+                                .map(|_| ())
+                                .map_err(|_| "The seek step must be a number of seconds.".into())
+                        })
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("crossfade")
+                        .value_name("SECONDS")
+                        .short("c")
+                        .long("crossfade")
+                        .help("Set how many seconds consecutive songs overlap. 0 disables crossfade.")
+                        .validator(|v| {
+                            v.parse::<u64>()
+                                .map(|_| ())
+                                .map_err(|_| "The crossfade must be a number of seconds.".into())
+                        })
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("replaygain")
+                        .value_name("MODE")
+                        .short("r")
+                        .long("replaygain")
+                        .help("Set which ReplayGain values to apply. Available values: 'off', 'track', 'album'.")
+                        .possible_values(&REPLAYGAIN_MODES)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("loudness-scan")
+                        .value_name("BOOL")
+                        .long("loudness-scan")
+                        .help("Measure the loudness of songs without ReplayGain tags in the background or not.")
+                        .takes_value(true)
+                )
+                .arg(
+                    Arg::with_name("rescan")
+                        .value_name("MODE")
+                        .long("rescan")
+                        .help("Set how the library is scanned again. Available values: 'incremental' only reads new and changed files, 'full' reads them all.")
+                        .possible_values(&RESCAN_MODES)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("speed")
+                        .value_name("FACTOR")
+                        .long("speed")
+                        .help("Set the playback speed at startup, between 0.5 and 3.0.")
+                        .validator(|v| {
+                            match v.parse::<f32>() {
+                                Ok(v) if (MIN_SPEED..=MAX_SPEED).contains(&v) => Ok(()),
+                                _ => Err("The speed must be between 0.5 and 3.0.".into()),
+                            }
+                        })
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("speed-mode")
+                        .value_name("MODE")
+                        .long("speed-mode")
+                        .help("Set how the speed is changed. Available values: 'stretch' keeps the pitch, 'resample' doesn't.")
+                        .possible_values(&SPEED_MODES)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .value_name("NAME")
+                        .short("o")
+                        .long("output")
+                        .help("Set where the audio goes. Available values: 'device', 'null', 'file'.")
+                        .possible_values(&OUTPUTS)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("device")
+                        .value_name("NAME")
+                        .long("device")
+                        .help("Set the audio device the 'device' output plays to.")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("output-file")
+                        .value_name("PATH")
+                        .long("output-file")
+                        .help("Set the WAV file the 'file' output writes to.")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("resume")
+                        .value_name("BOOL")
+                        .long("resume")
+                        .help("Cue the song of the last session at the saved position or not.")
+                        .takes_value(true)
+                )
+                .arg(
+                    Arg::with_name("resume-threshold")
+                        .value_name("MINUTES")
+                        .long("resume-threshold")
+                        .help("Remember where songs at least this long were left, and resume them from there.")
+                        .validator(|v| {
+                            v.parse::<u64>()
+                                .map(|_| ())
+                                .map_err(|_| "The resume threshold must be a number of minutes.".into())
+                        })
+                        .takes_value(true),
+                )
                 .arg(
                     Arg::with_name("debug")
                         .value_name("BOOL")
@@ -106,6 +242,27 @@ impl Into<Config> for CLI<'_> {
             lib_pos: args.value_of("INPUT").map(|l| l.to_owned()),
             db_pos: args.value_of("database").map(|d| d.to_owned()),
             volume: args.value_of("volume").map(|v| v.parse::<u64>().unwrap()),
+            balance: args.value_of("balance").map(|v| v.parse::<f32>().unwrap()),
+            mono: args.value_of("mono").map(|b| b.parse::<bool>().unwrap()),
+            seek_step: args.value_of("seek-step").map(|v| v.parse::<u64>().unwrap()),
+            crossfade: args.value_of("crossfade").map(|v| v.parse::<u64>().unwrap()),
+            crossfade_single_cycle: None,
+            replaygain: args.value_of("replaygain").map(|r| r.to_owned()),
+            loudness_scan: args.value_of("loudness-scan").map(|b| b.parse::<bool>().unwrap()),
+            rescan: args.value_of("rescan").map(|r| r.to_owned()),
+            speed: args.value_of("speed").map(|v| v.parse::<f32>().unwrap()),
+            speed_mode: args.value_of("speed-mode").map(|m| m.to_owned()),
+            sleep_fade: None,
+            equalizer: None,
+            output: args.value_of("output").map(|o| o.to_owned()),
+            device: args.value_of("device").map(|d| d.to_owned()),
+            output_file: args.value_of("output-file").map(|o| o.to_owned()),
+            restore_session: None,
+            resume: args.value_of("resume").map(|b| b.parse::<bool>().unwrap()),
+            resume_threshold: args.value_of("resume-threshold").map(|v| v.parse::<u64>().unwrap()),
+            resume_dirs: None,
+            stations: None,
+            station_files: None,
             debug: args.value_of("debug").map(|b| b.parse::<bool>().unwrap()),
         }
     }
@@ -1,9 +1,18 @@
 use crate::utils::{path_check, hex_to_rgb};
-use crate::error::{anyhow, Result, InvalidVolume, NonexistentPresetTheme,};
+use crate::error::{
+    anyhow, Result, InvalidVolume, InvalidBalance, NonexistentPresetTheme, InvalidReplayGainMode,
+    NonexistentOutput, MissingOutputFile, InvalidSpeed, NonexistentSpeedMode, InvalidStationUrl,
+    NonexistentStationFile, NonexistentRescanMode,
+};
+use crate::app::model::library::RESCAN_MODES;
+use crate::app::model::library::replaygain::REPLAYGAIN_MODES;
+use crate::app::model::player::{equalizer, output::OUTPUTS};
+use crate::app::model::player::speed::{SPEED_MODES, MIN_SPEED, MAX_SPEED};
+use crate::app::model::radio::Station;
 use dirs_next::{audio_dir, config_dir, data_dir};
 use serde::Deserialize;
 use std::io::Read;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::fs::{self, File};
 use std::collections::HashMap;
 use tui::style::Color;
@@ -17,10 +26,40 @@ pub struct Config {
     pub theme: Option<Theme>,
     pub lib_pos: Option<String>,
     pub db_pos: Option<String>,
+    /// `None` unless given, the volume of the last session is restored then.
     pub volume: Option<u64>,
+    pub balance: Option<f32>,
+    pub mono: Option<bool>,
+    pub seek_step: Option<u64>,
+    pub crossfade: Option<u64>,
+    pub crossfade_single_cycle: Option<bool>,
+    pub replaygain: Option<String>,
+    pub loudness_scan: Option<bool>,
+    pub rescan: Option<String>,
+    pub speed: Option<f32>,
+    pub speed_mode: Option<String>,
+    pub sleep_fade: Option<u64>,
+    pub equalizer: Option<Equalizer>,
+    pub output: Option<String>,
+    pub device: Option<String>,
+    pub output_file: Option<String>,
+    pub restore_session: Option<bool>,
+    pub resume: Option<bool>,
+    pub resume_threshold: Option<u64>,
+    pub resume_dirs: Option<Vec<String>>,
+    pub stations: Option<Vec<Station>>,
+    /// M3U or PLS playlists of radio stations.
+    pub station_files: Option<Vec<String>>,
     pub debug: Option<bool>,
 }
 
+#[derive(Debug, Clone, Default, Deserialize, PartialEq)]
+pub struct Equalizer {
+    pub preset: Option<String>,
+    pub bands: Option<Vec<f32>>,
+    pub presets: Option<HashMap<String, Vec<f32>>>,
+}
+
 #[derive(Debug, Clone, Deserialize, PartialEq)]
 pub struct Theme {
     pub preset: Option<String>,
@@ -108,7 +147,31 @@ impl Default for Config {
             theme: Some(Default::default()),
             lib_pos: Some(path_to_string(audio_dir().unwrap())),
             db_pos: Some(path_to_string(data_dir().unwrap().join("Ultra"))),
-            volume: Some(100),
+            volume: None,
+            balance: Some(0.0),
+            mono: Some(false),
+            seek_step: Some(5),
+            crossfade: Some(0),
+            crossfade_single_cycle: Some(false),
+            replaygain: Some("off".to_owned()),
+            loudness_scan: Some(false),
+            rescan: Some("incremental".to_owned()),
+            speed: Some(1.0),
+            speed_mode: Some("stretch".to_owned()),
+            sleep_fade: Some(10),
+            equalizer: Some(Equalizer {
+                preset: Some("Flat".to_owned()),
+                ..Default::default()
+            }),
+            output: Some("device".to_owned()),
+            device: None,
+            output_file: None,
+            restore_session: Some(true),
+            resume: Some(true),
+            resume_threshold: Some(20),
+            resume_dirs: Some(vec![]),
+            stations: Some(vec![]),
+            station_files: Some(vec![]),
             debug: Some(false)
         }
     }
@@ -170,6 +233,27 @@ impl Config {
             lib_pos,
             db_pos,
             volume,
+            balance,
+            mono,
+            seek_step,
+            crossfade,
+            crossfade_single_cycle,
+            replaygain,
+            loudness_scan,
+            rescan,
+            speed,
+            speed_mode,
+            sleep_fade,
+            equalizer,
+            output,
+            device,
+            output_file,
+            restore_session,
+            resume,
+            resume_threshold,
+            resume_dirs,
+            stations,
+            station_files,
             debug
         );
         Ok(cfg.check()?)
@@ -186,6 +270,27 @@ impl Config {
         lib_pos,
         db_pos,
         volume,
+        balance,
+        mono,
+        seek_step,
+        crossfade,
+        crossfade_single_cycle,
+        replaygain,
+        loudness_scan,
+        rescan,
+        speed,
+        speed_mode,
+        sleep_fade,
+        equalizer,
+        output,
+        device,
+        output_file,
+        restore_session,
+        resume,
+        resume_threshold,
+        resume_dirs,
+        stations,
+        station_files,
         debug
         ).check()
     }
@@ -199,10 +304,59 @@ impl Config {
             return Err(anyhow!(NonexistentPresetTheme(cfg_theme.into())));
         }
 
-        if !matches!(self.volume.unwrap(), 0..=100) {
+        if !matches!(self.volume, None | Some(0..=100)) {
             return Err(anyhow!(InvalidVolume));
         }
 
+        if !(-1.0..=1.0).contains(&self.balance.unwrap()) {
+            return Err(anyhow!(InvalidBalance));
+        }
+
+        let replaygain = self.replaygain.as_ref().unwrap().as_str();
+        if !REPLAYGAIN_MODES.contains(&replaygain) {
+            return Err(anyhow!(InvalidReplayGainMode(replaygain.into())));
+        }
+
+        let rescan = self.rescan.as_ref().unwrap().as_str();
+        if !RESCAN_MODES.contains(&rescan) {
+            return Err(anyhow!(NonexistentRescanMode(rescan.into())));
+        }
+
+        if !(MIN_SPEED..=MAX_SPEED).contains(&self.speed.unwrap()) {
+            return Err(anyhow!(InvalidSpeed));
+        }
+
+        let speed_mode = self.speed_mode.as_ref().unwrap().as_str();
+        if !SPEED_MODES.contains(&speed_mode) {
+            return Err(anyhow!(NonexistentSpeedMode(speed_mode.into())));
+        }
+
+        equalizer::Equalizer::new(self.equalizer.as_ref().unwrap())?;
+
+        let output = self.output.as_ref().unwrap().as_str();
+        if !OUTPUTS.contains(&output) {
+            return Err(anyhow!(NonexistentOutput(output.into())));
+        }
+        if output == "file" && self.output_file.is_none() {
+            return Err(anyhow!(MissingOutputFile));
+        }
+
+        for dir in self.resume_dirs.as_ref().unwrap() {
+            path_check(dir)?;
+        }
+
+        for station in self.stations.as_ref().unwrap() {
+            if !Station::valid_url(&station.url) {
+                return Err(anyhow!(InvalidStationUrl(station.url.clone())));
+            }
+        }
+        for file in self.station_files.as_ref().unwrap() {
+            let path = Path::new(file);
+            if !path.is_absolute() || !path.is_file() {
+                return Err(anyhow!(NonexistentStationFile(file.clone())));
+            }
+        }
+
         Ok(self)
     }
 }
@@ -13,6 +13,70 @@ pub struct InvalidUTF8Path;
 #[error("No preset theme named {0}.")]
 pub struct NonexistentPresetTheme(pub String);
 
+#[derive(Error, Debug)]
+#[error("No ReplayGain mode named {0}.")]
+pub struct InvalidReplayGainMode(pub String);
+
+#[derive(Error, Debug)]
+#[error("No equalizer preset named {0}.")]
+pub struct NonexistentEqualizerPreset(pub String);
+
+#[derive(Error, Debug)]
+#[error("Equalizer bands must be 10 gains between -12 and 12 dB.")]
+pub struct InvalidEqualizerBands;
+
+#[derive(Error, Debug)]
+#[error("No output named {0}.")]
+pub struct NonexistentOutput(pub String);
+
+#[derive(Error, Debug)]
+#[error("No output device named {0}.")]
+pub struct NonexistentDevice(pub String);
+
+#[derive(Error, Debug)]
+#[error("No output device available.")]
+pub struct NoDevice;
+
+#[derive(Error, Debug)]
+#[error("The file output needs a path to write to.")]
+pub struct MissingOutputFile;
+
+#[derive(Error, Debug)]
+#[error("The output file is full, a WAV file holds at most 4 GiB.")]
+pub struct OutputFileFull;
+
+#[derive(Error, Debug)]
+#[error("No speed mode named {0}.")]
+pub struct NonexistentSpeedMode(pub String);
+
+#[derive(Error, Debug)]
+#[error("No rescan mode named {0}.")]
+pub struct NonexistentRescanMode(pub String);
+
+#[derive(Error, Debug)]
+#[error("The speed must be between 0.5 and 3.0.")]
+pub struct InvalidSpeed;
+
+#[derive(Error, Debug)]
+#[error("The balance must be between -1.0 and 1.0.")]
+pub struct InvalidBalance;
+
+#[derive(Error, Debug)]
+#[error("The station URL {0} is not an HTTP(S) URL.")]
+pub struct InvalidStationUrl(pub String);
+
+#[derive(Error, Debug)]
+#[error("No station playlist at {0}, it must be an absolute path.")]
+pub struct NonexistentStationFile(pub String);
+
+#[derive(Error, Debug)]
+#[error("No reader supports {0}.")]
+pub struct UnsupportedFormat(pub String);
+
+#[derive(Error, Debug)]
+#[error("Playing {0} takes ffmpeg, which couldn't be found.")]
+pub struct MissingDecoder(pub String);
+
 #[derive(Error, Debug)]
 #[error("The volume value must be between 0 and 100.")]
 pub struct InvalidVolume;
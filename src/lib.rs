@@ -12,7 +12,12 @@ use std::sync::atomic::AtomicBool;
 use std::path::PathBuf;
 use lazy_static::lazy_static;
 
-pub const SUPPORT_FORMAT: [&str; 4] = ["flac", "mp3", "wav", "ogg"];
+pub const SUPPORT_FORMAT: [&str; 12] = [
+    "flac", "mp3", "wav", "ogg", "opus", "m4a", "aac", "alac", "aif", "aiff", "wv", "ape",
+];
+
+/// Extension of the CUE sheets splitting single-file albums into tracks.
+pub const CUE_SHEET: &str = "cue";
 
 static DEBUG: AtomicBool = AtomicBool::new(false);
 
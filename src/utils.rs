@@ -1,11 +1,13 @@
 use crate::error::{Result, anyhow, Unknown, InvalidLocation, InvalidColor};
-use crate::SUPPORT_FORMAT;
-use rodio::{Decoder, Source};
-use std::fs::{self, File};
+use crate::CUE_SHEET;
+use crate::app::model::library::decode::Decoded;
+use crate::app::model::library::format::Format;
+use rodio::Source;
+use std::fs;
 use std::panic::PanicInfo;
 use std::time::SystemTime;
 use std::path::{Path, PathBuf};
-use std::io::{self, Stdout, BufReader};
+use std::io::{self, Stdout};
 use crossterm::style::Print;
 use crossterm::ExecutableCommand;
 use crossterm::event::DisableMouseCapture;
@@ -37,16 +39,30 @@ pub fn get_last_modified_time(path: impl AsRef<Path>) -> SystemTime {
     fs::metadata(path).unwrap().modified().unwrap()
 }
 
+/// Inode of the file at `path`, only Unix has them.
+#[cfg(unix)]
+#[inline]
+pub fn get_inode(path: impl AsRef<Path>) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    fs::metadata(path).ok().map(|m| m.ino())
+}
+
+#[cfg(not(unix))]
+#[inline]
+pub fn get_inode(_path: impl AsRef<Path>) -> Option<u64> {
+    None
+}
+
 #[inline]
 pub fn get_duration(path: impl AsRef<Path>) -> Result<u64> {
-    Decoder::new(BufReader::new(File::open(&path)?))?
+    Decoded::new(&path)?
         .total_duration()
         .map(|t| t.as_secs())
         .ok_or(anyhow!(Unknown))
 }
 
 #[inline]
-pub fn get_snapshot(path: impl AsRef<Path>) -> Vec<PathBuf> {
+pub fn get_snapshot(path: impl AsRef<Path>, extensions: &[&str]) -> Vec<PathBuf> {
     walkdir::WalkDir::new(path)
         .into_iter()
         .filter_entry(|e| {
@@ -60,14 +76,13 @@ pub fn get_snapshot(path: impl AsRef<Path>) -> Vec<PathBuf> {
         .map(|e| e.path().to_path_buf())
         .filter(|e| e.is_file())
         .filter(|e| {
-            if let Some(ext) = e.extension() {
-                if let Some(val) = ext.to_str() {
-                    SUPPORT_FORMAT.contains(&val)
-                } else {
-                    false
-                }
-            } else {
-                false
+            match e.extension() {
+                Some(ext) => ext.to_str().map_or(false, |val| {
+                    let val = val.to_ascii_lowercase();
+                    extensions.contains(&val.as_str()) || val == CUE_SHEET
+                }),
+                // Files without an extension are told by their contents.
+                None => Format::new(e).map_or(false, |f| f != Format::Unsupported),
             }
         })
         .collect()